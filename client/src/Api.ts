@@ -4,6 +4,12 @@ import * as router from "./router"
 
 const BASE_URI = document.location.origin
 const AUTH_TOKEN_STORAGE_KEY = "AUTH_TOKEN_STORAGE_KEY"
+const REFRESH_TOKEN_STORAGE_KEY = "REFRESH_TOKEN_STORAGE_KEY"
+const TOKEN_EXPIRES_AT_STORAGE_KEY = "TOKEN_EXPIRES_AT_STORAGE_KEY"
+/** Access tokens are refreshed this long before they expire */
+const REFRESH_MARGIN_MS = 60 * 1000
+/** Message of the auth field when the access token is refused */
+const AUTH_ERROR = "WrongCredentials"
 const FEEDS_LIMIT = 15
 const SOURCES_LIMIT = 1000
 
@@ -11,20 +17,83 @@ export interface ApiError {
     errors: Array<{ message: string }>
 }
 
-function withToken(): Promise<string> {
-    return new Promise((resolve, reject) => {
-        const token = localStorage.getItem(AUTH_TOKEN_STORAGE_KEY) || sessionStorage.getItem(AUTH_TOKEN_STORAGE_KEY)
-        if (!token) {
-            router.replace("/login")
-            reject({
-                errors: [{ message: "Unauthorized" }]
-            })
-        } else {
-            resolve(token)
-        }
+interface AuthTokens {
+    accessToken: string
+    refreshToken: string
+    expiresIn: number
+}
+
+const AUTH_TOKENS_FIELDS = "accessToken refreshToken expiresIn"
+
+function storeTokens(tokens: AuthTokens) {
+    localStorage.setItem(AUTH_TOKEN_STORAGE_KEY, tokens.accessToken)
+    localStorage.setItem(REFRESH_TOKEN_STORAGE_KEY, tokens.refreshToken)
+    localStorage.setItem(TOKEN_EXPIRES_AT_STORAGE_KEY, String(Date.now() + tokens.expiresIn * 1000))
+}
+
+function clearTokens() {
+    localStorage.removeItem(AUTH_TOKEN_STORAGE_KEY)
+    localStorage.removeItem(REFRESH_TOKEN_STORAGE_KEY)
+    localStorage.removeItem(TOKEN_EXPIRES_AT_STORAGE_KEY)
+}
+
+function unauthorized(): Promise<never> {
+    clearTokens()
+    router.replace("/login")
+    return Promise.reject({
+        errors: [{ message: "Unauthorized" }]
     })
 }
 
+/** A refresh token works once, concurrent requests wait for the same refresh */
+let pendingRefresh: Promise<string> | undefined
+
+function refreshTokens(): Promise<string> {
+    const refreshToken = localStorage.getItem(REFRESH_TOKEN_STORAGE_KEY)
+    if (!refreshToken) {
+        return unauthorized()
+    }
+    if (!pendingRefresh) {
+        pendingRefresh = query(`mutation {
+            refreshToken(refreshToken: "${refreshToken}") { ${AUTH_TOKENS_FIELDS} }
+        }`).then(result => {
+            pendingRefresh = undefined
+            storeTokens(result.refreshToken)
+            return result.refreshToken.accessToken
+        }, () => {
+            pendingRefresh = undefined
+            return unauthorized()
+        })
+    }
+    return pendingRefresh
+}
+
+function withToken(): Promise<string> {
+    const token = localStorage.getItem(AUTH_TOKEN_STORAGE_KEY)
+    const expiresAt = Number(localStorage.getItem(TOKEN_EXPIRES_AT_STORAGE_KEY) || 0)
+    if (!token) {
+        return unauthorized()
+    } else if (expiresAt - Date.now() < REFRESH_MARGIN_MS) {
+        return refreshTokens()
+    } else {
+        return Promise.resolve(token)
+    }
+}
+
+function isAuthError(error: any): boolean {
+    return !!error && Array.isArray(error.errors) && error.errors.some((e: { message: string }) => e.message === AUTH_ERROR)
+}
+
+/** Query of the logged in user, retried once with new tokens when the access token is refused */
+function authQuery(build: (token: string) => string): Promise<any> {
+    return withToken()
+        .then(token => query(build(token)))
+        .catch(error => isAuthError(error)
+            ? refreshTokens().then(token => query(build(token)))
+            : Promise.reject(error)
+        )
+}
+
 function query(query: string): Promise<any> {
     return fetch(`${BASE_URI}/graphql`, fetchOptions(query))
         .then(response => response.json())
@@ -46,22 +115,22 @@ function fetchOptions(query: string): RequestInit {
 
 export function login(email: string, password: string): Promise<void> {
     return query(`query {
-        login(email: "${email}", password: "${password}")
+        login(email: "${email}", password: "${password}") { ${AUTH_TOKENS_FIELDS} }
     }`).then(result => {
-        localStorage.setItem(AUTH_TOKEN_STORAGE_KEY, result.login)
+        storeTokens(result.login)
     })
 }
 
 export function signup(login: string, email: string, password: string): Promise<void> {
     return query(`mutation {
-        signup(login: "${login}", email: "${email}", password: "${password}")
+        signup(login: "${login}", email: "${email}", password: "${password}") { ${AUTH_TOKENS_FIELDS} }
     }`).then(result => {
-        localStorage.setItem(AUTH_TOKEN_STORAGE_KEY, result.signup)
+        storeTokens(result.signup)
     })
 }
 
 export function loadUnfollowedSources(): Promise<Source[]> {
-    return withToken().then(token => query(`
+    return authQuery(token => `
         query {
             auth(token: "${token}") {
                 unfollowedSources {
@@ -76,12 +145,12 @@ export function loadUnfollowedSources(): Promise<Source[]> {
                 }
             }
         }
-    `))
+    `)
     .then(result => result.auth.unfollowedSources)
 }
 
 export function loadMySources(): Promise<Source[]> {
-    return withToken().then(token => query(`
+    return authQuery(token => `
         query {
             auth(token: "${token}") {
                 mySources(limit: ${SOURCES_LIMIT}) {
@@ -96,12 +165,12 @@ export function loadMySources(): Promise<Source[]> {
                 }
             }
         }
-    `))
+    `)
     .then(result => result.auth.mySources)
 }
 
 export function loadMySourcesStats(): Promise<SourceStat[]> {
-    return withToken().then(token => query(`
+    return authQuery(token => `
         query {
             auth(token: "${token}") {
                 sourcesStats {
@@ -110,12 +179,12 @@ export function loadMySourcesStats(): Promise<SourceStat[]> {
                 }
             }
         }
-    `))
+    `)
     .then(result => result.auth.sourcesStats)
 }
 
 export function loadUnreadedFeeds(): Promise<Feed[]> {
-    return withToken().then(token => query(`
+    return authQuery(token => `
         query {
             auth(token: "${token}") {
                 unreadedFeeds(limit: ${FEEDS_LIMIT}) {
@@ -136,12 +205,12 @@ export function loadUnreadedFeeds(): Promise<Feed[]> {
                 }
             }
         }
-    `))
+    `)
     .then(result => result.auth.unreadedFeeds)
 }
 
 export function loadUnreadedFeedsBySource(sourceUuid: string): Promise<Feed[]> {
-    return withToken().then(token => query(`
+    return authQuery(token => `
         query {
             auth(token: "${token}") {
                 unreadedFeedsBySource(sourceUuid: "${sourceUuid}", limit: ${FEEDS_LIMIT}) {
@@ -162,12 +231,12 @@ export function loadUnreadedFeedsBySource(sourceUuid: string): Promise<Feed[]> {
                 }
             }
         }
-    `))
+    `)
     .then(result => result.auth.unreadedFeedsBySource)
 }
 
 export function feedsByReaction(reaction: Reaction): Promise<FeedSimple[]> {
-    return withToken().then(token => query(`
+    return authQuery(token => `
         query {
             auth(token: "${token}") {
                 feedsByReaction(reaction: "${reaction}", limit: 10000) {
@@ -182,7 +251,7 @@ export function feedsByReaction(reaction: Reaction): Promise<FeedSimple[]> {
                 }
             }
         }
-    `))
+    `)
     .then(result => result.auth.feedsByReaction)
 }
 
@@ -192,7 +261,7 @@ function log<T>(t: T): T {
 }
 
 export function fallowSource(source: Source): Promise<Source> {
-    return withToken().then(token => query(`
+    return authQuery(token => `
         mutation {
             auth(token: "${token}") {
                 fallowSource(sourceUuid: "${source.uuid}") {
@@ -207,7 +276,7 @@ export function fallowSource(source: Source): Promise<Source> {
                 }
             }
         }
-    `))
+    `)
     .then(result => result.auth.fallowSource)
 }
 
@@ -215,30 +284,43 @@ export function addSource(xmlUrl: string): Promise<Source> {
     return query(`
         mutation {
             addRssSource(xmlUrl: "${xmlUrl}") {
-                uuid
-                sourceType
-                rssSource {
+                source {
+                    uuid
+                    sourceType
+                    rssSource {
+                        title
+                        xmlUrl
+                        htmlUrl
+                    }
+                    error
+                    created
+                    updated
+                }
+                candidates {
+                    url
                     title
-                    xmlUrl
-                    htmlUrl
+                    mimeType
                 }
-                error
-                created
-                updated
             }
         }
     `)
-    .then(result => result.addRssSource)
+    .then(result => {
+        if (result.addRssSource.source) {
+            return result.addRssSource.source
+        } else {
+            throw { candidates: result.addRssSource.candidates }
+        }
+    })
 }
 
 export function feedReaction(feed: Feed, reaction: Reaction): Promise<Feed> {
-    return withToken().then(token => query(`
+    return authQuery(token => `
         mutation {
             auth(token: "${token}") {
                 feedReaction(feedUuid: "${feed.uuid}", reaction: "${reaction}")
             }
         }
-    `))
+    `)
     .then(() => feed)
 }
 
@@ -251,6 +333,6 @@ export function success(result: any) {
 }
 
 export function logout() {
-    localStorage.removeItem(AUTH_TOKEN_STORAGE_KEY)
+    clearTokens()
     router.replace("/login")
 }
@@ -17,3 +17,9 @@ export interface SourceStat {
     uuid: string
     count: number
 }
+
+export interface FeedCandidate {
+    url: string
+    title?: string
+    mimeType?: string
+}
@@ -8,6 +8,7 @@ import { SourcesState } from "./SourcesReducer"
 import { Source } from "./Source"
 import SourcesList from "./components/SourcesList"
 import AddSourceForm from "./components/AddSourceForm"
+import FeedCandidates from "./components/FeedCandidates"
 import HeaderContainer from "../app/HeaderContainer"
 
 interface DispatchProps {
@@ -28,7 +29,7 @@ class SourcesContainer extends React.PureComponent<Props> {
         this.props.onLoadMySourcesStats()
     }
     render() {
-        const { newSourceUrl, addSourceOnChange, addSourceOnSubmit } = this.props
+        const { newSourceUrl, candidates, addSourceOnChange, addSourceOnSubmit } = this.props
         return (
             <div className={styles.sourcesContainer}>
                 <HeaderContainer />
@@ -38,6 +39,9 @@ class SourcesContainer extends React.PureComponent<Props> {
                     onChange={addSourceOnChange}
                     onSubmit={addSourceOnSubmit}
                 />
+                {candidates.length > 0 &&
+                    <FeedCandidates candidates={candidates} onSelect={addSourceOnSubmit} />
+                }
                 <h3>Sources</h3>
                 {this.renderSourcesList()}
                 <h3>My Sources</h3>
@@ -1,4 +1,4 @@
-import { Source, SourceStat } from "./Source"
+import { Source, SourceStat, FeedCandidate } from "./Source"
 import { SourceAction } from "./SourcesActions"
 
 export interface SourcesState {
@@ -10,6 +10,7 @@ export interface SourcesState {
     error?: any
     newSourceUrl: string
     addSourceLoading: boolean
+    candidates: FeedCandidate[]
 }
 
 const initState: SourcesState = {
@@ -21,20 +22,26 @@ const initState: SourcesState = {
     error: undefined,
     newSourceUrl: "",
     addSourceLoading: false,
+    candidates: [],
 }
 
 const SourcesReducer = (state: SourcesState = initState, action: SourceAction) => {
     switch (action.type) {
         case "ADD_SOURCE_ON_CHANGE": return { ...state, [action.field]: action.value }
 
-        case "ADD_SOURCE": return { ...state, addSourceLoading: true }
+        case "ADD_SOURCE": return { ...state, addSourceLoading: true, candidates: [] }
         case "ADD_SOURCE_SUCCESS": return {
             ...state,
             addSourceLoading: false,
             newSourceUrl: "",
             sources: [...state.sources, action.source]
         }
-        case "ADD_SOURCE_ERROR": return { ...state, addSourceLoading: false, error: action.error }
+        case "ADD_SOURCE_ERROR": return {
+            ...state,
+            addSourceLoading: false,
+            error: action.error,
+            candidates: (action.error && action.error.candidates) || []
+        }
 
         case "LOAD_UNFOLLOWED_SOURCES": return { ...state, loading: true }
         case "LOAD_UNFOLLOWED_SOURCES_SUCCESS": return { ...state, sources: action.sources, loading: false }
@@ -0,0 +1,30 @@
+import * as React from "react"
+import { FeedCandidate } from "../../sources/Source"
+
+interface Props {
+    candidates: FeedCandidate[]
+    onSelect(sourceUrl: string): void
+}
+
+export default class FeedCandidates extends React.PureComponent<Props> {
+    render() {
+        const { candidates } = this.props
+        return (
+            <div>
+                <label>Several feeds were found, pick one</label>
+                <ul>
+                    {candidates.map(candidate =>
+                        <li key={candidate.url}>
+                            {candidate.title || candidate.url}
+                            <button onClick={this.onSelectHandler(candidate.url)}>Add</button>
+                        </li>
+                    )}
+                </ul>
+            </div>
+        )
+    }
+
+    onSelectHandler = (sourceUrl: string) => () => {
+        this.props.onSelect(sourceUrl)
+    }
+}
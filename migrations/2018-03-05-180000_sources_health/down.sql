@@ -0,0 +1,7 @@
+ALTER TABLE sources
+    DROP COLUMN last_success,
+    DROP COLUMN last_failure,
+    DROP COLUMN failure_count,
+    DROP COLUMN last_status,
+    DROP COLUMN next_fetch_at,
+    DROP COLUMN disabled;
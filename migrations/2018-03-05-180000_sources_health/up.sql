@@ -0,0 +1,7 @@
+ALTER TABLE sources
+    ADD COLUMN IF NOT EXISTS last_success TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS last_failure TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS failure_count INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS last_status INTEGER,
+    ADD COLUMN IF NOT EXISTS next_fetch_at TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS disabled BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1 @@
+ALTER TABLE sources ADD COLUMN IF NOT EXISTS fetch_interval INTEGER;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS websub_subscriptions (
+    uuid UUID PRIMARY KEY,
+    source_uuid UUID UNIQUE NOT NULL REFERENCES sources(uuid),
+    hub_url TEXT NOT NULL,
+    topic_url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    verified BOOLEAN NOT NULL DEFAULT false,
+    lease_seconds INTEGER,
+    expires_at TIMESTAMP,
+    created TIMESTAMP,
+    updated TIMESTAMP
+);
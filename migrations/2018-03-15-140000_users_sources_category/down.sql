@@ -0,0 +1 @@
+ALTER TABLE users_sources DROP COLUMN category;
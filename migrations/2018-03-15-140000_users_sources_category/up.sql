@@ -0,0 +1 @@
+ALTER TABLE users_sources ADD COLUMN IF NOT EXISTS category TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE extraction_jobs;
+DROP TYPE "extractionstatus";
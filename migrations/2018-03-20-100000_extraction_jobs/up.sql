@@ -0,0 +1,25 @@
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'extractionstatus') THEN
+        CREATE TYPE ExtractionStatus AS ENUM (
+            'Pending',
+            'Done',
+            'Failed'
+        );
+    END IF;
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS extraction_jobs (
+    uuid UUID PRIMARY KEY,
+    feed_uuid UUID UNIQUE NOT NULL REFERENCES feeds(uuid) ON DELETE CASCADE,
+    status ExtractionStatus NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMP NOT NULL,
+    locked_until TIMESTAMP,
+    created TIMESTAMP,
+    updated TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS extraction_jobs_pending_idx ON extraction_jobs (next_attempt_at) WHERE status = 'Pending';
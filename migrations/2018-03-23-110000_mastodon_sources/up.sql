@@ -0,0 +1,19 @@
+-- ALTER TYPE ... ADD VALUE can not run inside the migration transaction, the enum is swapped instead
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM pg_enum
+        JOIN pg_type ON pg_type.oid = pg_enum.enumtypid
+        WHERE pg_type.typname = 'sourcetype' AND pg_enum.enumlabel = 'Mastodon'
+    ) THEN
+        ALTER TYPE SourceType RENAME TO sourcetype_old;
+        CREATE TYPE SourceType AS ENUM (
+            'Rss', 'Twitter', 'Mastodon'
+        );
+        ALTER TABLE sources ALTER COLUMN source_type TYPE SourceType USING source_type::text::SourceType;
+        DROP TYPE sourcetype_old;
+    END IF;
+END
+$$;
+
+ALTER TABLE feeds ADD COLUMN IF NOT EXISTS mastodon JSONB;
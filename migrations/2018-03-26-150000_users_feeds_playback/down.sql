@@ -0,0 +1,2 @@
+ALTER TABLE users_feeds DROP COLUMN playback_updated;
+ALTER TABLE users_feeds DROP COLUMN playback_position;
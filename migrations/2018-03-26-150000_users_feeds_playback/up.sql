@@ -0,0 +1,2 @@
+ALTER TABLE users_feeds ADD COLUMN IF NOT EXISTS playback_position INTEGER;
+ALTER TABLE users_feeds ADD COLUMN IF NOT EXISTS playback_updated TIMESTAMP;
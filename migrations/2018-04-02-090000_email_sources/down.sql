@@ -0,0 +1,16 @@
+DELETE FROM users_feeds WHERE feed_uuid IN (
+    SELECT feeds.uuid FROM feeds JOIN sources ON sources.uuid = feeds.source_uuid WHERE sources.source_type = 'Email'
+);
+DELETE FROM extraction_jobs WHERE feed_uuid IN (
+    SELECT feeds.uuid FROM feeds JOIN sources ON sources.uuid = feeds.source_uuid WHERE sources.source_type = 'Email'
+);
+DELETE FROM feeds WHERE source_uuid IN (SELECT uuid FROM sources WHERE source_type = 'Email');
+DELETE FROM users_sources WHERE source_uuid IN (SELECT uuid FROM sources WHERE source_type = 'Email');
+DELETE FROM sources WHERE source_type = 'Email';
+
+ALTER TYPE SourceType RENAME TO sourcetype_old;
+CREATE TYPE SourceType AS ENUM (
+    'Rss', 'Twitter', 'Mastodon', 'WebPage'
+);
+ALTER TABLE sources ALTER COLUMN source_type TYPE SourceType USING source_type::text::SourceType;
+DROP TYPE sourcetype_old;
@@ -0,0 +1,16 @@
+DELETE FROM users_feeds WHERE feed_uuid IN (
+    SELECT feeds.uuid FROM feeds JOIN sources ON sources.uuid = feeds.source_uuid WHERE sources.source_type = 'Webhook'
+);
+DELETE FROM extraction_jobs WHERE feed_uuid IN (
+    SELECT feeds.uuid FROM feeds JOIN sources ON sources.uuid = feeds.source_uuid WHERE sources.source_type = 'Webhook'
+);
+DELETE FROM feeds WHERE source_uuid IN (SELECT uuid FROM sources WHERE source_type = 'Webhook');
+DELETE FROM users_sources WHERE source_uuid IN (SELECT uuid FROM sources WHERE source_type = 'Webhook');
+DELETE FROM sources WHERE source_type = 'Webhook';
+
+ALTER TYPE SourceType RENAME TO sourcetype_old;
+CREATE TYPE SourceType AS ENUM (
+    'Rss', 'Twitter', 'Mastodon', 'WebPage', 'Email'
+);
+ALTER TABLE sources ALTER COLUMN source_type TYPE SourceType USING source_type::text::SourceType;
+DROP TYPE sourcetype_old;
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS feeds_search_vector_idx;
+DROP TRIGGER IF EXISTS feeds_search_vector ON feeds;
+DROP FUNCTION IF EXISTS feeds_search_vector_update();
+ALTER TABLE feeds DROP COLUMN IF EXISTS search_vector;
+ALTER TABLE feeds DROP COLUMN IF EXISTS search_config;
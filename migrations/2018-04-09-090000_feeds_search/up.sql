@@ -0,0 +1,24 @@
+-- Text search configuration of the feed language, kept as text so feeds.* rows stay readable by the driver
+ALTER TABLE feeds ADD COLUMN IF NOT EXISTS search_config TEXT NOT NULL DEFAULT 'simple';
+ALTER TABLE feeds ADD COLUMN IF NOT EXISTS search_vector TSVECTOR;
+
+CREATE OR REPLACE FUNCTION feeds_search_vector_update() RETURNS trigger AS $$
+DECLARE
+    config REGCONFIG := NEW.search_config::regconfig;
+BEGIN
+    NEW.search_vector :=
+        setweight(to_tsvector(config, coalesce(NEW.rss->>'title', NEW.readable->>'title', '')), 'A') ||
+        setweight(to_tsvector(config, coalesce(NEW.rss->>'summary', NEW.readable->>'excerpt', '')), 'B') ||
+        setweight(to_tsvector(config, concat_ws(' ', NEW.rss->>'content', NEW.twitter->>'text', NEW.mastodon->>'content')), 'C') ||
+        setweight(to_tsvector(config, coalesce(NEW.readable->>'content', '')), 'D');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER feeds_search_vector BEFORE INSERT OR UPDATE OF rss, readable, twitter, mastodon, search_config
+    ON feeds FOR EACH ROW EXECUTE PROCEDURE feeds_search_vector_update();
+
+-- Existing feeds are indexed without stemming, their language was never detected
+UPDATE feeds SET search_config = 'simple';
+
+CREATE INDEX IF NOT EXISTS feeds_search_vector_idx ON feeds USING GIN (search_vector);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS smart_feeds (
+    uuid UUID PRIMARY KEY,
+    user_uuid UUID NOT NULL REFERENCES users(uuid),
+    name TEXT NOT NULL,
+    query TEXT,
+    source_uuids UUID[] NOT NULL DEFAULT '{}',
+    reactions Reaction[] NOT NULL DEFAULT '{}',
+    window_days INTEGER,
+    created TIMESTAMP NOT NULL,
+    updated TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS smart_feeds_user_uuid_idx ON smart_feeds (user_uuid);
@@ -0,0 +1,5 @@
+ALTER TABLE users_feeds DROP COLUMN IF EXISTS tags;
+DROP TABLE IF EXISTS filter_rules;
+DROP TYPE IF EXISTS FilterAction;
+DROP TYPE IF EXISTS FilterMatch;
+DROP TYPE IF EXISTS FilterField;
@@ -0,0 +1,47 @@
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'filterfield') THEN
+        CREATE TYPE FilterField AS ENUM (
+            'Title',
+            'Content',
+            'Author',
+            'Keywords',
+            'Any'
+        );
+    END IF;
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'filtermatch') THEN
+        CREATE TYPE FilterMatch AS ENUM (
+            'Substring',
+            'Regex'
+        );
+    END IF;
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'filteraction') THEN
+        CREATE TYPE FilterAction AS ENUM (
+            'Skip',
+            'SetReaction',
+            'Tag'
+        );
+    END IF;
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS filter_rules (
+    uuid UUID PRIMARY KEY,
+    user_uuid UUID NOT NULL REFERENCES users(uuid),
+    name TEXT NOT NULL,
+    field FilterField NOT NULL,
+    match_type FilterMatch NOT NULL,
+    pattern TEXT NOT NULL,
+    source_uuid UUID REFERENCES sources(uuid),
+    action FilterAction NOT NULL,
+    reaction Reaction,
+    tag TEXT,
+    position INTEGER NOT NULL DEFAULT 0,
+    enabled BOOLEAN NOT NULL DEFAULT true,
+    created TIMESTAMP NOT NULL,
+    updated TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS filter_rules_user_uuid_idx ON filter_rules (user_uuid);
+
+ALTER TABLE users_feeds ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
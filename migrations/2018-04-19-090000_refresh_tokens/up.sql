@@ -0,0 +1,14 @@
+-- Refresh tokens are rotated on every use, the tokens of one login share a family revoked at once
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    uuid UUID PRIMARY KEY,
+    user_uuid UUID NOT NULL REFERENCES users(uuid),
+    family_uuid UUID NOT NULL,
+    token_hash TEXT UNIQUE NOT NULL,
+    created TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_uuid_idx ON refresh_tokens (family_uuid);
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_uuid_idx ON refresh_tokens (user_uuid);
@@ -0,0 +1,19 @@
+-- A session is a login, its uuid is the family of its refresh tokens
+CREATE TABLE IF NOT EXISTS sessions (
+    uuid UUID PRIMARY KEY,
+    user_uuid UUID NOT NULL REFERENCES users(uuid),
+    device_label TEXT,
+    user_agent TEXT,
+    ip TEXT,
+    created TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_uuid_idx ON sessions (user_uuid);
+
+INSERT INTO sessions (uuid, user_uuid, created, last_seen)
+SELECT family_uuid, user_uuid, MIN(created), MAX(created) FROM refresh_tokens
+GROUP BY family_uuid, user_uuid
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE refresh_tokens ADD CONSTRAINT refresh_tokens_family_uuid_fkey FOREIGN KEY (family_uuid) REFERENCES sessions(uuid);
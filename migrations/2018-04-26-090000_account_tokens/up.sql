@@ -0,0 +1,26 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified BOOLEAN NOT NULL DEFAULT false;
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'accounttokenpurpose') THEN
+        CREATE TYPE AccountTokenPurpose AS ENUM (
+            'EmailVerification',
+            'PasswordReset'
+        );
+    END IF;
+END
+$$;
+
+-- One-time tokens sent by email, only their hash is kept
+CREATE TABLE IF NOT EXISTS account_tokens (
+    uuid UUID PRIMARY KEY,
+    user_uuid UUID NOT NULL REFERENCES users(uuid),
+    purpose AccountTokenPurpose NOT NULL,
+    token_hash TEXT UNIQUE NOT NULL,
+    email TEXT NOT NULL,
+    created TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS account_tokens_user_uuid_idx ON account_tokens (user_uuid, purpose);
@@ -0,0 +1,19 @@
+DROP TABLE IF EXISTS data_exports;
+DROP TYPE IF EXISTS ExportStatus;
+
+ALTER TABLE users_sources DROP CONSTRAINT IF EXISTS users_sources_user_uuid_fkey;
+ALTER TABLE users_sources ADD CONSTRAINT users_sources_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid);
+ALTER TABLE users_feeds DROP CONSTRAINT IF EXISTS users_feeds_user_uuid_fkey;
+ALTER TABLE users_feeds ADD CONSTRAINT users_feeds_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid);
+ALTER TABLE smart_feeds DROP CONSTRAINT IF EXISTS smart_feeds_user_uuid_fkey;
+ALTER TABLE smart_feeds ADD CONSTRAINT smart_feeds_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid);
+ALTER TABLE filter_rules DROP CONSTRAINT IF EXISTS filter_rules_user_uuid_fkey;
+ALTER TABLE filter_rules ADD CONSTRAINT filter_rules_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid);
+ALTER TABLE refresh_tokens DROP CONSTRAINT IF EXISTS refresh_tokens_user_uuid_fkey;
+ALTER TABLE refresh_tokens ADD CONSTRAINT refresh_tokens_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid);
+ALTER TABLE sessions DROP CONSTRAINT IF EXISTS sessions_user_uuid_fkey;
+ALTER TABLE sessions ADD CONSTRAINT sessions_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid);
+ALTER TABLE account_tokens DROP CONSTRAINT IF EXISTS account_tokens_user_uuid_fkey;
+ALTER TABLE account_tokens ADD CONSTRAINT account_tokens_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid);
+ALTER TABLE refresh_tokens DROP CONSTRAINT IF EXISTS refresh_tokens_family_uuid_fkey;
+ALTER TABLE refresh_tokens ADD CONSTRAINT refresh_tokens_family_uuid_fkey FOREIGN KEY (family_uuid) REFERENCES sessions(uuid);
@@ -0,0 +1,44 @@
+-- Deleting a user deletes everything that belongs to it
+ALTER TABLE users_sources DROP CONSTRAINT IF EXISTS users_sources_user_uuid_fkey;
+ALTER TABLE users_sources ADD CONSTRAINT users_sources_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid) ON DELETE CASCADE;
+ALTER TABLE users_feeds DROP CONSTRAINT IF EXISTS users_feeds_user_uuid_fkey;
+ALTER TABLE users_feeds ADD CONSTRAINT users_feeds_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid) ON DELETE CASCADE;
+ALTER TABLE smart_feeds DROP CONSTRAINT IF EXISTS smart_feeds_user_uuid_fkey;
+ALTER TABLE smart_feeds ADD CONSTRAINT smart_feeds_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid) ON DELETE CASCADE;
+ALTER TABLE filter_rules DROP CONSTRAINT IF EXISTS filter_rules_user_uuid_fkey;
+ALTER TABLE filter_rules ADD CONSTRAINT filter_rules_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid) ON DELETE CASCADE;
+ALTER TABLE refresh_tokens DROP CONSTRAINT IF EXISTS refresh_tokens_user_uuid_fkey;
+ALTER TABLE refresh_tokens ADD CONSTRAINT refresh_tokens_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid) ON DELETE CASCADE;
+ALTER TABLE sessions DROP CONSTRAINT IF EXISTS sessions_user_uuid_fkey;
+ALTER TABLE sessions ADD CONSTRAINT sessions_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid) ON DELETE CASCADE;
+ALTER TABLE account_tokens DROP CONSTRAINT IF EXISTS account_tokens_user_uuid_fkey;
+ALTER TABLE account_tokens ADD CONSTRAINT account_tokens_user_uuid_fkey FOREIGN KEY (user_uuid) REFERENCES users(uuid) ON DELETE CASCADE;
+ALTER TABLE refresh_tokens DROP CONSTRAINT IF EXISTS refresh_tokens_family_uuid_fkey;
+ALTER TABLE refresh_tokens ADD CONSTRAINT refresh_tokens_family_uuid_fkey FOREIGN KEY (family_uuid) REFERENCES sessions(uuid) ON DELETE CASCADE;
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'exportstatus') THEN
+        CREATE TYPE ExportStatus AS ENUM (
+            'Pending',
+            'Done',
+            'Failed'
+        );
+    END IF;
+END
+$$;
+
+-- Archives of the data of a user, built by the export job and kept until they expire
+CREATE TABLE IF NOT EXISTS data_exports (
+    uuid UUID PRIMARY KEY,
+    user_uuid UUID NOT NULL REFERENCES users(uuid) ON DELETE CASCADE,
+    status ExportStatus NOT NULL,
+    file_name TEXT,
+    error TEXT,
+    created TIMESTAMP NOT NULL,
+    completed_at TIMESTAMP,
+    expires_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS data_exports_user_uuid_idx ON data_exports (user_uuid);
+CREATE INDEX IF NOT EXISTS data_exports_pending_idx ON data_exports (created) WHERE status = 'Pending';
@@ -0,0 +1,236 @@
+use uuid::Uuid;
+use chrono::prelude::*;
+use chrono::{Duration, NaiveDateTime};
+use postgres::rows::Row;
+use postgres::types::ToSql;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use validator::Validate;
+
+use errors::*;
+use config::Config;
+use pg::{Insertable, PgDatabase};
+use mailer::{self, OutgoingMail};
+use refresh_tokens::{hash_token, revoke_all_families};
+use user::{User, hash_password};
+
+const EMAIL_VERIFICATION_HOURS: i64 = 48;
+const PASSWORD_RESET_HOURS: i64 = 1;
+/// No new mail while the previous token of the same purpose is this recent and unused, the requests need no authentication
+const REISSUE_MINUTES: i64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql)]
+#[postgres(name = "accounttokenpurpose")]
+pub enum AccountTokenPurpose {
+    EmailVerification,
+    PasswordReset,
+}
+
+impl AccountTokenPurpose {
+    fn lifetime(&self) -> Duration {
+        match *self {
+            AccountTokenPurpose::EmailVerification => Duration::hours(EMAIL_VERIFICATION_HOURS),
+            AccountTokenPurpose::PasswordReset => Duration::hours(PASSWORD_RESET_HOURS),
+        }
+    }
+}
+
+/// One-time token sent by email, only its hash is kept
+#[derive(Debug)]
+pub struct AccountToken {
+    pub uuid: Uuid,
+    pub user_uuid: Uuid,
+    pub purpose: AccountTokenPurpose,
+    pub token_hash: String,
+    /// Address the token was sent to, the one verified by an email verification
+    pub email: String,
+    pub created: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+impl AccountToken {
+    fn new(user_uuid: Uuid, purpose: AccountTokenPurpose, email: &str, token: &str) -> Self {
+        let now = Utc::now().naive_utc();
+        AccountToken {
+            uuid: Uuid::new_v4(),
+            user_uuid,
+            purpose,
+            token_hash: hash_token(token),
+            email: email.to_owned(),
+            created: now,
+            expires_at: now + purpose.lifetime(),
+            used_at: None,
+        }
+    }
+}
+
+impl<'a> From<Row<'a>> for AccountToken {
+    fn from(row: Row) -> Self {
+        AccountToken {
+            uuid: row.get("uuid"),
+            user_uuid: row.get("user_uuid"),
+            purpose: row.get("purpose"),
+            token_hash: row.get("token_hash"),
+            email: row.get("email"),
+            created: row.get("created"),
+            expires_at: row.get("expires_at"),
+            used_at: row.get("used_at"),
+        }
+    }
+}
+
+impl Insertable for AccountToken {
+    fn insert_query(&self) -> String {
+        r#"
+            INSERT INTO account_tokens (uuid, user_uuid, purpose, token_hash, email, created, expires_at, used_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8);
+        "#.to_owned()
+    }
+
+    fn insert_params(&self) -> Box<[&ToSql]> {
+        Box::new([
+            &self.uuid,
+            &self.user_uuid,
+            &self.purpose,
+            &self.token_hash,
+            &self.email,
+            &self.created,
+            &self.expires_at,
+            &self.used_at,
+        ])
+    }
+}
+
+/// New token of the user, the previous ones of the same purpose stop working
+fn issue_token(pg: &PgDatabase, user_uuid: &Uuid, purpose: AccountTokenPurpose, email: &str) -> Result<String> {
+    let query = "DELETE FROM account_tokens WHERE user_uuid = $1::uuid AND purpose = $2 AND used_at IS NULL;";
+    pg.update(query, &[user_uuid, &purpose])?;
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    pg.insert(&AccountToken::new(*user_uuid, purpose, email, &token))?;
+    Ok(token)
+}
+
+/// Whether an unused token of the purpose was mailed to `email` in the last REISSUE_MINUTES
+fn recently_issued(pg: &PgDatabase, user_uuid: &Uuid, purpose: AccountTokenPurpose, email: &str) -> Result<bool> {
+    let query = r#"
+        SELECT COUNT(*) AS exist FROM account_tokens
+        WHERE user_uuid = $1::uuid AND purpose = $2 AND lower(email) = lower($3) AND used_at IS NULL AND created > $4;
+    "#;
+    let since = Utc::now().naive_utc() - Duration::minutes(REISSUE_MINUTES);
+    pg.exist(query, &[user_uuid, &purpose, &email, &since])
+}
+
+/// Unused and unexpired token, it is still usable after this
+fn find_valid_token(pg: &PgDatabase, purpose: AccountTokenPurpose, token: &str) -> Result<AccountToken> {
+    let query = r#"
+        SELECT * FROM account_tokens
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > $3;
+    "#;
+    let found = pg.find_one(query, &[&hash_token(token), &purpose, &Utc::now().naive_utc()])?;
+    Ok(found.ok_or(ErrorKind::InvalidToken)?)
+}
+
+/// Marks the token used, fails when a concurrent request used it first
+fn consume_token(pg: &PgDatabase, account_token: &AccountToken) -> Result<()> {
+    let query = "UPDATE account_tokens SET used_at = $1 WHERE uuid = $2::uuid AND used_at IS NULL;";
+    if pg.update(query, &[&Utc::now().naive_utc(), &account_token.uuid])? == 0 {
+        return Err(ErrorKind::InvalidToken.into());
+    }
+    Ok(())
+}
+
+fn link(config: &Config, path: &str, token: &str) -> String {
+    match config.app_url {
+        Some(ref app_url) => format!("{}/{}?token={}", app_url, path, token),
+        None => format!("Token: {}", token),
+    }
+}
+
+/// Mails a verification link to the address, it becomes the email of the user once followed
+pub fn send_email_verification(pg: &PgDatabase, config: &Config, user: &User, email: &str) -> Result<()> {
+    if recently_issued(pg, &user.uuid, AccountTokenPurpose::EmailVerification, email)? {
+        return Ok(());
+    }
+    let token = issue_token(pg, &user.uuid, AccountTokenPurpose::EmailVerification, email)?;
+    mailer::send_in_background(OutgoingMail {
+        to: email.to_owned(),
+        subject: "Confirm your email address".to_owned(),
+        text: format!(
+            "Hello {},\n\nConfirm this address for your Mindstream account by following this link, it is valid {} hours:\n\n{}\n\nIgnore this mail if you did not ask for it.\n",
+            user.login, EMAIL_VERIFICATION_HOURS, link(config, "verify-email", &token)
+        ),
+    });
+    Ok(())
+}
+
+fn find_user(pg: &PgDatabase, user_uuid: &Uuid) -> Result<User> {
+    let query = "SELECT * FROM users WHERE uuid = $1::uuid;";
+    Ok(pg.find_one(query, &[user_uuid])?.ok_or(ErrorKind::NotFound)?)
+}
+
+pub fn request_email_verification_resolver(pool: Pool<PostgresConnectionManager>, config: &Config, user: &User) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pool)?;
+    if user.email_verified {
+        bail!("email already verified");
+    }
+    send_email_verification(&pg, config, user, &user.email)?;
+    Ok(true)
+}
+
+pub fn verify_email_resolver(pool: Pool<PostgresConnectionManager>, token: &str) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let account_token = find_valid_token(&pg, AccountTokenPurpose::EmailVerification, token)?;
+    let query = "SELECT COUNT(*) AS exist FROM users WHERE lower(email) = lower($1) AND uuid <> $2::uuid;";
+    if pg.exist(query, &[&account_token.email, &account_token.user_uuid])? {
+        return Err(ErrorKind::AlreadyExist.into());
+    }
+    let user = find_user(&pg, &account_token.user_uuid)?;
+    consume_token(&pg, &account_token)?;
+    let query = "UPDATE users SET email = $1, email_verified = TRUE, updated = $2 WHERE uuid = $3::uuid;";
+    pg.update(query, &[&account_token.email, &Utc::now().naive_utc(), &user.uuid])?;
+    if user.email != account_token.email {
+        // The email is the login credential, the sessions opened with the previous one are closed
+        revoke_all_families(&pg, &user.uuid)?;
+    }
+    Ok(true)
+}
+
+/// Always succeeds, whether the address belongs to a user is not disclosed
+pub fn request_password_reset_resolver(pool: Pool<PostgresConnectionManager>, config: &Config, email: &str) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let query = "SELECT * FROM users WHERE lower(email) = lower($1);";
+    let user: Option<User> = pg.find_one(query, &[&email.trim()])?;
+    if let Some(user) = user {
+        if recently_issued(&pg, &user.uuid, AccountTokenPurpose::PasswordReset, &user.email)? {
+            return Ok(true);
+        }
+        let token = issue_token(&pg, &user.uuid, AccountTokenPurpose::PasswordReset, &user.email)?;
+        mailer::send_in_background(OutgoingMail {
+            to: user.email.clone(),
+            subject: "Reset your password".to_owned(),
+            text: format!(
+                "Hello {},\n\nChoose a new password for your Mindstream account by following this link, it is valid {} hour:\n\n{}\n\nIgnore this mail if you did not ask for it, your password is unchanged.\n",
+                user.login, PASSWORD_RESET_HOURS, link(config, "reset-password", &token)
+            ),
+        });
+    }
+    Ok(true)
+}
+
+/// Sets the new password and logs out every session, the mailbox owner is in control again
+pub fn reset_password_resolver(pool: Pool<PostgresConnectionManager>, token: &str, password: String) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let account_token = find_valid_token(&pg, AccountTokenPurpose::PasswordReset, token)?;
+    let mut user = find_user(&pg, &account_token.user_uuid)?;
+    user.password = password;
+    let _ = user.validate()?;
+    consume_token(&pg, &account_token)?;
+    let query = r#"
+        UPDATE users SET password = $1, email_verified = email_verified OR email = $2, updated = $3
+        WHERE uuid = $4::uuid;
+    "#;
+    pg.update(query, &[&hash_password(&user.password)?, &account_token.email, &Utc::now().naive_utc(), &user.uuid])?;
+    revoke_all_families(&pg, &user.uuid)?;
+    Ok(true)
+}
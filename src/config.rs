@@ -1,27 +1,127 @@
 use std::env;
+use std::net::IpAddr;
 use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Config {
     pub secret_key: String,
     pub database_url: String,
-    pub mercury_api_key: String,
+    /// Optional, the built-in readability extractor is used without it
+    pub mercury_api_key: Option<String>,
     pub rss_job_interval: Duration,
+    pub rss_job_workers: usize,
+    pub rss_job_host_concurrency: usize,
+    pub extraction_workers: usize,
+    /// Public base url of the server, WebSub push subscriptions are disabled without it
+    pub websub_callback_url: Option<String>,
+    /// Bearer token of the Twitter API, twitter sources are not fetched without it
+    pub twitter_bearer_token: Option<String>,
+    /// Base url of the Twitter API, can point to a local mock
+    pub twitter_api_url: String,
+    /// Domain of the newsletter inbound addresses, email sources are disabled without it
+    pub inbound_email_domain: Option<String>,
+    /// Address the built-in SMTP listener binds to, like 0.0.0.0:2525
+    pub smtp_listen_addr: Option<String>,
+    /// Maildir whose new messages are delivered like SMTP ones, for local testing or an external MTA
+    pub inbound_maildir: Option<String>,
+    /// Sender of the account mails
+    pub mail_from: String,
+    /// SMTP relay the account mails are handed to, like localhost:25
+    pub smtp_relay_addr: Option<String>,
+    /// Directory the account mails are written to when there is no relay
+    pub mail_outbox_dir: Option<String>,
+    /// smtp, file or log, deduced from the relay or outbox when not set, the server does not start without a transport
+    pub mail_transport: Option<String>,
+    /// Public url of the web client, the links of the account mails point to it
+    pub app_url: Option<String>,
+    /// Public base url of the server, webhook sources can not be created without it
+    pub public_url: Option<String>,
+    /// Directory the data export archives are written to
+    pub export_dir: String,
+    /// Logins allowed to change how the readable content of a source is extracted, it applies to every follower
+    pub admin_logins: Vec<String>,
+    /// Extraction services an external extractor may call, any other endpoint is refused
+    pub extractor_endpoints: Vec<String>,
+    /// Reverse proxies whose X-Forwarded-For and X-Real-IP headers are believed, they are ignored otherwise
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
-impl Config {
-    pub fn new(secret_key: String, database_url: String, mercury_api_key: String, rss_job_interval: Duration) -> Self {
-        Config { secret_key, database_url, mercury_api_key, rss_job_interval }
-    }
+const DEFAULT_RSS_JOB_WORKERS: usize = 8;
+const DEFAULT_RSS_JOB_HOST_CONCURRENCY: usize = 2;
+const DEFAULT_EXTRACTION_WORKERS: usize = 4;
+const DEFAULT_TWITTER_API_URL: &str = "https://api.twitter.com";
+const DEFAULT_MAIL_FROM: &str = "mindstream@localhost";
+const DEFAULT_EXPORT_DIR: &str = "./exports";
 
+/// Comma separated values, empty when the variable is not set
+fn list_var(name: &str) -> Vec<String> {
+    env::var(name).ok()
+        .map(|value| value.split(',').map(|item| item.trim().to_owned()).filter(|item| !item.is_empty()).collect())
+        .unwrap_or_else(Vec::new)
+}
+
+impl Config {
     pub fn from_env() -> Self {
         let secret_key = env::var("SECRET_KEY").expect("SECRET_KEY must be set");
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let mercury_api_key = env::var("MERCURY_API_KEY").expect("MERCURY_API_KEY must be set");
+        let mercury_api_key = env::var("MERCURY_API_KEY").ok().and_then(|api_key| if api_key.is_empty() { None } else { Some(api_key) });
         let rss_job_interval = env::var("RSS_JOB_INTERVAL").expect("RSS_JOB_INTERVAL must be set");
         let rss_job_interval = rss_job_interval.parse::<u64>().expect("RSS_JOB_INTERVAL must be an integer");
         let rss_job_interval = Duration::from_secs(rss_job_interval);
-        Config::new(secret_key, database_url, mercury_api_key, rss_job_interval)
+        let rss_job_workers = env::var("RSS_JOB_WORKERS").ok()
+            .map(|workers| workers.parse::<usize>().expect("RSS_JOB_WORKERS must be an integer"))
+            .unwrap_or(DEFAULT_RSS_JOB_WORKERS);
+        let rss_job_host_concurrency = env::var("RSS_JOB_HOST_CONCURRENCY").ok()
+            .map(|concurrency| concurrency.parse::<usize>().expect("RSS_JOB_HOST_CONCURRENCY must be an integer"))
+            .unwrap_or(DEFAULT_RSS_JOB_HOST_CONCURRENCY);
+        let extraction_workers = env::var("EXTRACTION_WORKERS").ok()
+            .map(|workers| workers.parse::<usize>().expect("EXTRACTION_WORKERS must be an integer"))
+            .unwrap_or(DEFAULT_EXTRACTION_WORKERS);
+        let websub_callback_url = env::var("WEBSUB_CALLBACK_URL").ok();
+        let twitter_bearer_token = env::var("TWITTER_BEARER_TOKEN").ok().and_then(|token| if token.is_empty() { None } else { Some(token) });
+        let twitter_api_url = env::var("TWITTER_API_URL").unwrap_or_else(|_| DEFAULT_TWITTER_API_URL.to_owned());
+        let inbound_email_domain = env::var("INBOUND_EMAIL_DOMAIN").ok().map(|domain| domain.to_lowercase());
+        let smtp_listen_addr = env::var("SMTP_LISTEN_ADDR").ok();
+        let inbound_maildir = env::var("INBOUND_MAILDIR").ok();
+        let mail_from = env::var("MAIL_FROM").unwrap_or_else(|_| DEFAULT_MAIL_FROM.to_owned());
+        let smtp_relay_addr = env::var("SMTP_RELAY_ADDR").ok().and_then(|addr| if addr.is_empty() { None } else { Some(addr) });
+        let mail_outbox_dir = env::var("MAIL_OUTBOX_DIR").ok().and_then(|dir| if dir.is_empty() { None } else { Some(dir) });
+        let mail_transport = env::var("MAIL_TRANSPORT").ok().and_then(|transport| if transport.is_empty() { None } else { Some(transport.to_lowercase()) });
+        let app_url = env::var("APP_URL").ok().map(|url| url.trim_right_matches('/').to_owned());
+        let public_url = env::var("PUBLIC_URL").ok()
+            .map(|url| url.trim_right_matches('/').to_owned())
+            .and_then(|url| if url.is_empty() { None } else { Some(url) });
+        let export_dir = env::var("EXPORT_DIR").unwrap_or_else(|_| DEFAULT_EXPORT_DIR.to_owned());
+        let admin_logins = list_var("ADMIN_LOGINS");
+        let extractor_endpoints = list_var("EXTRACTOR_ENDPOINTS");
+        let trusted_proxies = list_var("TRUSTED_PROXIES").iter()
+            .map(|proxy| proxy.parse::<IpAddr>().expect("TRUSTED_PROXIES must be ip addresses"))
+            .collect();
+        Config {
+            secret_key,
+            database_url,
+            mercury_api_key,
+            rss_job_interval,
+            rss_job_workers,
+            rss_job_host_concurrency,
+            extraction_workers,
+            websub_callback_url,
+            twitter_bearer_token,
+            twitter_api_url,
+            inbound_email_domain,
+            smtp_listen_addr,
+            inbound_maildir,
+            mail_from,
+            smtp_relay_addr,
+            mail_outbox_dir,
+            mail_transport,
+            app_url,
+            public_url,
+            export_dir,
+            admin_logins,
+            extractor_endpoints,
+            trusted_proxies,
+        }
     }
 }
 
@@ -0,0 +1,332 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration as StdDuration, Instant};
+use uuid::Uuid;
+use chrono::prelude::*;
+use chrono::{Duration, NaiveDateTime};
+use postgres::rows::Row;
+use postgres::types::ToSql;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use serde_json::{self, Value};
+use zip::ZipWriter;
+use zip::write::FileOptions;
+
+use errors::*;
+use pg::{Insertable, PgDatabase};
+use opml::export_opml;
+use user::User;
+
+/// Archives can be downloaded for this long, their file is deleted after
+const EXPORT_DAYS: i64 = 7;
+
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, ToSql, FromSql)]
+#[postgres(name = "exportstatus")]
+pub enum ExportStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// Archive of the data of a user, JSON documents and the OPML of the subscriptions
+#[derive(Debug)]
+pub struct DataExport {
+    pub uuid: Uuid,
+    pub user_uuid: Uuid,
+    pub status: ExportStatus,
+    /// Name of the archive in the export directory, once built
+    pub file_name: Option<String>,
+    pub error: Option<String>,
+    pub created: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl DataExport {
+    pub fn new(user: &User) -> Self {
+        DataExport {
+            uuid: Uuid::new_v4(),
+            user_uuid: user.uuid,
+            status: ExportStatus::Pending,
+            file_name: None,
+            error: None,
+            created: Utc::now().naive_utc(),
+            completed_at: None,
+            expires_at: None,
+        }
+    }
+}
+
+impl<'a> From<Row<'a>> for DataExport {
+    fn from(row: Row) -> Self {
+        DataExport {
+            uuid: row.get("uuid"),
+            user_uuid: row.get("user_uuid"),
+            status: row.get("status"),
+            file_name: row.get("file_name"),
+            error: row.get("error"),
+            created: row.get("created"),
+            completed_at: row.get("completed_at"),
+            expires_at: row.get("expires_at"),
+        }
+    }
+}
+
+impl Insertable for DataExport {
+    fn insert_query(&self) -> String {
+        r#"
+            INSERT INTO data_exports (uuid, user_uuid, status, file_name, error, created, completed_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8);
+        "#.to_owned()
+    }
+
+    fn insert_params(&self) -> Box<[&ToSql]> {
+        Box::new([
+            &self.uuid,
+            &self.user_uuid,
+            &self.status,
+            &self.file_name,
+            &self.error,
+            &self.created,
+            &self.completed_at,
+            &self.expires_at,
+        ])
+    }
+}
+
+graphql_object!(DataExport: () as "DataExport" |&self| {
+    description: "Archive of the profile, subscriptions, reactions and annotations of the user"
+
+    field uuid() -> Uuid {
+        self.uuid
+    }
+
+    field status() -> ExportStatus {
+        self.status
+    }
+
+    field download_url() -> Option<String> as "Path of the archive, downloaded with the auth token" {
+        match self.status {
+            ExportStatus::Done => Some(format!("/exports/{}", self.uuid.hyphenated())),
+            _ => None,
+        }
+    }
+
+    field error() -> &Option<String> {
+        &self.error
+    }
+
+    field created() -> String {
+        format!("{}", self.created)
+    }
+
+    field expires_at() -> Option<String> {
+        self.expires_at.map(|expires_at| format!("{}", expires_at))
+    }
+});
+
+/// Rows of the query as a JSON array, Postgres builds the documents
+fn json_rows(pg: &PgDatabase, query: &str, user: &User) -> Result<Value> {
+    let query = format!("SELECT COALESCE(json_agg(export), '[]'::json) AS export FROM ({}) export;", query);
+    let rows: Vec<JsonRows> = pg.find(&query, &[&user.uuid])?;
+    Ok(rows.into_iter().next().map(|rows| rows.0).unwrap_or(Value::Array(Vec::new())))
+}
+
+struct JsonRows(Value);
+
+impl<'a> From<Row<'a>> for JsonRows {
+    fn from(row: Row) -> Self {
+        JsonRows(row.get("export"))
+    }
+}
+
+/// Documents of the archive, by file name
+fn export_documents(pg: &PgDatabase, user: &User) -> Result<Vec<(&'static str, Vec<u8>)>> {
+    let profile = json!({
+        "uuid": user.uuid.hyphenated().to_string(),
+        "login": user.login,
+        "email": user.email,
+        "email_verified": user.email_verified,
+        "created": user.created.map(|created| format!("{}", created)),
+        "updated": user.updated.map(|updated| format!("{}", updated)),
+    });
+    // The webhook token is a credential, an archive passed around must not let anyone post items
+    let subscriptions = json_rows(pg, r#"
+        SELECT sources.uuid, sources.source_type, sources."data" - 'token_hash' AS "data", users_sources.category
+        FROM users_sources JOIN sources ON sources.uuid = users_sources.source_uuid
+        WHERE users_sources.user_uuid = $1::uuid
+    "#, user)?;
+    let reactions = json_rows(pg, r#"
+        SELECT feeds.uuid AS feed_uuid, feeds.url, feeds.rss->>'title' AS title, feeds.source_uuid,
+            users_feeds.reaction, users_feeds.created, users_feeds.updated
+        FROM users_feeds JOIN feeds ON feeds.uuid = users_feeds.feed_uuid
+        WHERE users_feeds.user_uuid = $1::uuid
+        ORDER BY users_feeds.created
+    "#, user)?;
+    let annotations = json_rows(pg, r#"
+        SELECT users_feeds.feed_uuid, feeds.url, users_feeds.tags, users_feeds.playback_position, users_feeds.playback_updated
+        FROM users_feeds JOIN feeds ON feeds.uuid = users_feeds.feed_uuid
+        WHERE users_feeds.user_uuid = $1::uuid
+        AND (cardinality(users_feeds.tags) > 0 OR users_feeds.playback_position IS NOT NULL)
+        ORDER BY users_feeds.created
+    "#, user)?;
+    let smart_feeds = json_rows(pg, r#"
+        SELECT uuid, name, query, source_uuids, reactions, window_days, created, updated
+        FROM smart_feeds WHERE user_uuid = $1::uuid
+    "#, user)?;
+    let filter_rules = json_rows(pg, r#"
+        SELECT uuid, name, field, match_type, pattern, source_uuid, action, reaction, tag, position, enabled
+        FROM filter_rules WHERE user_uuid = $1::uuid
+    "#, user)?;
+    let sessions = json_rows(pg, r#"
+        SELECT uuid, device_label, user_agent, ip, created, last_seen
+        FROM sessions WHERE user_uuid = $1::uuid
+    "#, user)?;
+    Ok(vec![
+        ("profile.json", serde_json::to_vec_pretty(&profile)?),
+        ("subscriptions.json", serde_json::to_vec_pretty(&subscriptions)?),
+        ("subscriptions.opml", export_opml(pg, user)?.into_bytes()),
+        ("reactions.json", serde_json::to_vec_pretty(&reactions)?),
+        ("annotations.json", serde_json::to_vec_pretty(&annotations)?),
+        ("smart_feeds.json", serde_json::to_vec_pretty(&smart_feeds)?),
+        ("filter_rules.json", serde_json::to_vec_pretty(&filter_rules)?),
+        ("sessions.json", serde_json::to_vec_pretty(&sessions)?),
+    ])
+}
+
+/// Zip archive written under a temporary name, renamed once complete
+fn write_archive(dir: &Path, file_name: &str, documents: Vec<(&'static str, Vec<u8>)>) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let partial = dir.join(format!("{}.partial", file_name));
+    {
+        let mut zip = ZipWriter::new(fs::File::create(&partial)?);
+        for (name, content) in documents {
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(&content)?;
+        }
+        zip.finish()?;
+    }
+    fs::rename(&partial, dir.join(file_name))?;
+    Ok(())
+}
+
+fn build_export(pg: &PgDatabase, dir: &Path, export: &DataExport) -> Result<String> {
+    let query = "SELECT * FROM users WHERE uuid = $1::uuid;";
+    let user: User = pg.find_one(query, &[&export.user_uuid])?.ok_or(ErrorKind::NotFound)?;
+    let file_name = format!("mindstream-{}.zip", export.uuid.simple());
+    write_archive(dir, &file_name, export_documents(pg, &user)?)?;
+    Ok(file_name)
+}
+
+/// Deletes the expired archives and their rows
+fn remove_expired(pg: &PgDatabase, dir: &Path) -> Result<()> {
+    let query = "DELETE FROM data_exports WHERE expires_at < $1 RETURNING *;";
+    let expired: Vec<DataExport> = pg.find(query, &[&Utc::now().naive_utc()])?;
+    for export in expired {
+        remove_file(dir, &export);
+    }
+    Ok(())
+}
+
+fn remove_file(dir: &Path, export: &DataExport) {
+    if let Some(ref file_name) = export.file_name {
+        if let Err(error) = fs::remove_file(dir.join(file_name)) {
+            println!("data export {:?} remove error {}", export.uuid, error);
+        }
+    }
+}
+
+fn process_exports(dir: &Path, pool: &Pool<PostgresConnectionManager>) -> Result<()> {
+    let pg = PgDatabase::from_pool(pool.clone())?;
+    remove_expired(&pg, dir)?;
+    let query = "SELECT * FROM data_exports WHERE status = 'Pending' ORDER BY created;";
+    let pending: Vec<DataExport> = pg.find(query, &[])?;
+    for export in pending {
+        let now = Utc::now().naive_utc();
+        match build_export(&pg, dir, &export) {
+            Ok(file_name) => {
+                let query = r#"
+                    UPDATE data_exports SET status = 'Done', file_name = $1, completed_at = $2, expires_at = $3
+                    WHERE uuid = $4::uuid;
+                "#;
+                let updated = pg.update(query, &[&file_name, &now, &(now + Duration::days(EXPORT_DAYS)), &export.uuid])?;
+                // The row went away with its user while the archive was built, nothing would ever delete the file
+                if updated == 0 {
+                    if let Err(error) = fs::remove_file(dir.join(&file_name)) {
+                        println!("data export {:?} remove error {}", export.uuid, error);
+                    }
+                }
+            },
+            Err(error) => {
+                println!("data export {:?} error {}", export.uuid, error);
+                let query = r#"
+                    UPDATE data_exports SET status = 'Failed', error = $1, completed_at = $2, expires_at = $3
+                    WHERE uuid = $4::uuid;
+                "#;
+                pg.update(query, &[&error.to_string(), &now, &(now + Duration::days(EXPORT_DAYS)), &export.uuid])?;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Builds the requested archives into the export directory
+pub fn run_export_job(dir: String, interval: StdDuration, pool: Pool<PostgresConnectionManager>) {
+    thread::spawn(move || {
+        loop {
+            let started = Instant::now();
+            if let Err(err) = process_exports(Path::new(&dir), &pool) {
+                println!("process_exports error {:?}", err);
+            }
+            let elapsed = started.elapsed();
+            if elapsed < interval {
+                thread::sleep(interval - elapsed);
+            }
+        }
+    });
+}
+
+/// Archive of the user ready for download, its path in the export directory
+pub fn find_export_file(pg: &PgDatabase, dir: &str, uuid: &str, user: &User) -> Result<PathBuf> {
+    let uuid = Uuid::parse_str(uuid)?;
+    let query = r#"
+        SELECT * FROM data_exports
+        WHERE uuid = $1::uuid AND user_uuid = $2::uuid AND status = 'Done' AND expires_at > $3;
+    "#;
+    let export: DataExport = pg.find_one(query, &[&uuid, &user.uuid, &Utc::now().naive_utc()])?.ok_or(ErrorKind::NotFound)?;
+    let file_name = export.file_name.ok_or(ErrorKind::NotFound)?;
+    Ok(Path::new(dir).join(file_name))
+}
+
+/// Exports of the user, read before deleting it since their rows go with the user
+pub fn find_user_exports(pg: &PgDatabase, user: &User) -> Result<Vec<DataExport>> {
+    let query = "SELECT * FROM data_exports WHERE user_uuid = $1::uuid;";
+    Ok(pg.find(query, &[&user.uuid])?)
+}
+
+/// Deletes the archives of exports whose rows are gone
+pub fn remove_export_files(dir: &str, exports: &[DataExport]) {
+    for export in exports {
+        remove_file(Path::new(dir), export);
+    }
+}
+
+/// A pending export is reused, the next archive would hold the same data
+pub fn request_data_export_resolver(pool: Pool<PostgresConnectionManager>, user: &User) -> Result<DataExport> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let query = "SELECT * FROM data_exports WHERE user_uuid = $1::uuid AND status = 'Pending';";
+    if let Some(pending) = pg.find_one(query, &[&user.uuid])? {
+        return Ok(pending);
+    }
+    let export = DataExport::new(user);
+    pg.insert(&export)?;
+    Ok(export)
+}
+
+pub fn data_exports_resolver(pool: Pool<PostgresConnectionManager>, user: &User) -> Result<Vec<DataExport>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let query = "SELECT * FROM data_exports WHERE user_uuid = $1::uuid ORDER BY created DESC;";
+    Ok(pg.find(query, &[&user.uuid])?)
+}
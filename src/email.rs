@@ -0,0 +1,242 @@
+use chrono::prelude::*;
+use chrono::DateTime;
+use mailparse::{self, ParsedMail, MailHeaderMap};
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use scraper::Html;
+use url::Url;
+use uuid::Uuid;
+
+use errors::*;
+use config::CONFIG;
+use pg::PgDatabase;
+use feeds::{is_feed_exist, insert_feed, item_fingerprint, Feed, Rss};
+use source::Source;
+use source_option::EmailSource;
+use sources::find_email_source;
+use users_sources::{find_users_by_source, UserSource};
+use readability::{clean_html, escape_text, text_of};
+use rss::insert_subscribers_feeds;
+use filter_rules::{SubscriberRules, find_enabled_rules};
+use user::User;
+
+/// Newsletter parsed from a mail, in the `Rss` shape of feed entries
+#[derive(Debug)]
+pub struct EmailItem {
+    pub url: String,
+    pub rss: Rss,
+}
+
+fn header(mail: &ParsedMail, name: &str) -> Option<String> {
+    mail.headers.get_first_value(name).ok()
+        .and_then(|value| value)
+        .map(|value| value.trim().to_owned())
+        .and_then(|value| if value.is_empty() { None } else { Some(value) })
+}
+
+fn is_attachment(part: &ParsedMail) -> bool {
+    header(part, "Content-Disposition")
+        .map(|disposition| disposition.to_lowercase().starts_with("attachment"))
+        .unwrap_or(false)
+}
+
+/// First inline part of `mimetype`, multipart/alternative puts the richest one last so the walk is depth first
+fn find_part<'a>(mail: &'a ParsedMail<'a>, mimetype: &str) -> Option<&'a ParsedMail<'a>> {
+    if is_attachment(mail) {
+        return None;
+    }
+    if mail.subparts.is_empty() {
+        return if mail.ctype.mimetype.eq_ignore_ascii_case(mimetype) { Some(mail) } else { None };
+    }
+    mail.subparts.iter().filter_map(|part| find_part(part, mimetype)).next()
+}
+
+/// Html of the mail with scripts, styles and tracking attributes removed
+fn sanitize_html(html: &str) -> Result<String> {
+    let base_url = Url::parse("about:blank")?;
+    let document = Html::parse_document(html);
+    let mut content = String::new();
+    clean_html(document.root_element(), &base_url, &[], &mut content);
+    Ok(content)
+}
+
+/// Plain text mails become one paragraph per block of lines
+fn text_to_html(text: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| paragraph.trim())
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| format!("<p>{}</p>", escape_text(paragraph).replace('\n', "<br>")))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+fn mail_content(mail: &ParsedMail) -> Result<Option<String>> {
+    if let Some(part) = find_part(mail, "text/html") {
+        return Ok(Some(sanitize_html(&part.get_body()?)?));
+    }
+    if let Some(part) = find_part(mail, "text/plain") {
+        return Ok(Some(text_to_html(&part.get_body()?.replace("\r\n", "\n"))));
+    }
+    Ok(None)
+}
+
+/// `Name <address>` of the From header, or only the address
+fn sender_name(from: &str) -> String {
+    match from.find('<') {
+        Some(position) if position > 0 => from[..position].trim().trim_matches('"').to_owned(),
+        _ => from.trim_matches(|c| c == '<' || c == '>').to_owned(),
+    }
+}
+
+/// Parses a raw RFC 5322 message, the feed url is its Message-ID
+pub fn parse_email(raw: &[u8]) -> Result<EmailItem> {
+    let mail = mailparse::parse_mail(raw)?;
+    let title = header(&mail, "Subject");
+    let content = mail_content(&mail)?;
+    let published = header(&mail, "Date")
+        .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+        .map(|date| date.naive_utc())
+        .unwrap_or_else(|| Utc::now().naive_utc());
+    let message_id = header(&mail, "Message-ID").map(|id| id.trim_matches(|c| c == '<' || c == '>').to_owned());
+    let fingerprint = item_fingerprint(message_id.as_ref().map(|id| id.as_str()).unwrap_or(""), &title, &content);
+    let url = format!("mid:{}", message_id.unwrap_or_else(|| fingerprint.clone()));
+    let rss = Rss {
+        id: url.clone(),
+        title,
+        summary: content.as_ref().map(|content| text_of(content)),
+        content,
+        author: header(&mail, "From").map(|from| sender_name(&from)),
+        published: published.to_string(),
+        updated: None,
+        alternate: None,
+        keywords: Vec::new(),
+        enclosure: None,
+        fingerprint,
+        enclosures: Vec::new(),
+        podcast: None,
+    };
+    Ok(EmailItem { url, rss })
+}
+
+/// Whether `address` is on INBOUND_EMAIL_DOMAIN
+pub fn is_inbound_address(address: &str) -> bool {
+    match (CONFIG.inbound_email_domain.as_ref(), address.rsplitn(2, '@').next()) {
+        (Some(domain), Some(address_domain)) => address.contains('@') && address_domain.eq_ignore_ascii_case(domain),
+        _ => false,
+    }
+}
+
+/// Email source of an inbound address, none for addresses nobody owns
+pub fn find_inbound_source(pg: &PgDatabase, address: &str) -> Result<Option<Source>> {
+    if !is_inbound_address(address) {
+        return Ok(None);
+    }
+    find_email_source(pg, address)
+}
+
+/// Stores the mail as a feed of the source and fans it out to its subscribers
+pub fn deliver(pg: &PgDatabase, source: &Source, raw: &[u8]) -> Result<()> {
+    let item = parse_email(raw)?;
+    if is_feed_exist(pg, &item.url, source)? {
+        return Ok(());
+    }
+    let feed = Feed::new(&item.url, Some(item.rss), None, None, None, source.uuid);
+    insert_feed(pg, &feed)?;
+    let subscribers = find_users_by_source(pg, source)?;
+    let filter_rules = find_enabled_rules(pg, &subscribers)?;
+    insert_subscribers_feeds(&subscribers, &SubscriberRules::new(&filter_rules), &feed, pg)
+}
+
+/// `login.random@domain`, the random part keeps addresses unguessable
+fn new_inbound_address(user: &User, domain: &str) -> String {
+    let login: String = user.login.to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let random = Uuid::new_v4().simple().to_string();
+    format!("{}.{}@{}", login.trim_matches('-'), &random[..8], domain)
+}
+
+pub fn create_inbound_address_resolver(pool: Pool<PostgresConnectionManager>, title: Option<String>, user: &User) -> Result<Source> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let domain = CONFIG.inbound_email_domain.clone().ok_or("inbound email is not configured")?;
+    let address = new_inbound_address(user, &domain);
+    let title = title.unwrap_or_else(|| format!("Newsletters {}", address));
+    let source = Source::new_email(EmailSource::new(&title, &address))?;
+    pg.insert(&source)?;
+    let user_source = UserSource::new(user.uuid.clone(), source.uuid.clone(), None);
+    pg.insert(&user_source)?;
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sender_names() {
+        assert_eq!(sender_name("\"Weekly News\" <news@example.com>"), "Weekly News");
+        assert_eq!(sender_name("<news@example.com>"), "news@example.com");
+        assert_eq!(sender_name("news@example.com"), "news@example.com");
+    }
+
+    #[test]
+    fn plain_text_paragraphs_are_escaped() {
+        assert_eq!(text_to_html("Hello <you>\nline\n\n\nBye & see"), "<p>Hello &lt;you&gt;<br>line</p><p>Bye &amp; see</p>");
+    }
+
+    #[test]
+    fn html_part_of_an_alternative_mail() {
+        let raw = concat!(
+            "From: \"Weekly News\" <news@example.com>\r\n",
+            "Subject: Issue 12\r\n",
+            "Date: Sun, 01 Apr 2018 10:00:00 +0200\r\n",
+            "Message-ID: <issue-12@example.com>\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/alternative; boundary=\"sep\"\r\n",
+            "\r\n",
+            "--sep\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "Plain version\r\n",
+            "--sep\r\n",
+            "Content-Type: text/html; charset=utf-8\r\n",
+            "\r\n",
+            "<html><body><p>Rich version</p><script>alert(1)</script></body></html>\r\n",
+            "--sep--\r\n",
+        );
+        let item = parse_email(raw.as_bytes()).unwrap();
+        assert_eq!(item.url, "mid:issue-12@example.com");
+        assert_eq!(item.rss.id, item.url);
+        assert_eq!(item.rss.title, Some("Issue 12".to_owned()));
+        assert_eq!(item.rss.author, Some("Weekly News".to_owned()));
+        assert_eq!(item.rss.published, NaiveDate::from_ymd(2018, 4, 1).and_hms(8, 0, 0).to_string());
+        let content = item.rss.content.unwrap();
+        assert!(content.contains("Rich version"));
+        assert!(!content.contains("Plain version"));
+        assert!(!content.contains("alert"));
+    }
+
+    #[test]
+    fn plain_mail_without_message_id_and_attachments() {
+        let raw = concat!(
+            "From: news@example.com\r\n",
+            "Subject: No id\r\n",
+            "Content-Type: multipart/mixed; boundary=\"sep\"\r\n",
+            "\r\n",
+            "--sep\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "Body text\r\n",
+            "--sep\r\n",
+            "Content-Type: text/html\r\n",
+            "Content-Disposition: attachment; filename=\"page.html\"\r\n",
+            "\r\n",
+            "<p>Attached page</p>\r\n",
+            "--sep--\r\n",
+        );
+        let item = parse_email(raw.as_bytes()).unwrap();
+        assert_eq!(item.url, format!("mid:{}", item.rss.fingerprint));
+        assert_eq!(item.rss.content, Some("<p>Body text</p>".to_owned()));
+        assert_eq!(item.rss.author, Some("news@example.com".to_owned()));
+    }
+}
@@ -4,6 +4,16 @@ error_chain! {
         NotFound
         NotInserted
         WrongCredentials
+        InvalidFeed
+        InvalidToken
+        InvalidSelector(selector: String) {
+            description("invalid css selector")
+            display("invalid css selector: {}", selector)
+        }
+        HttpStatus(status: u16, retry_after: Option<i64>) {
+            description("unexpected http status")
+            display("unexpected http status: {}", status)
+        }
     }
 
     foreign_links {
@@ -19,5 +29,7 @@ error_chain! {
         Strum(::strum::ParseError);
         Bcrypt(::bcrypt::BcryptError);
         Url(::url::ParseError);
+        Mail(::mailparse::MailParseError);
+        Zip(::zip::result::ZipError);
     }
 }
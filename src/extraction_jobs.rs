@@ -0,0 +1,232 @@
+use std::cmp;
+use std::thread;
+use std::time::{Duration as StdDuration, Instant};
+use uuid::Uuid;
+use chrono::NaiveDateTime;
+use chrono::prelude::*;
+use chrono::Duration;
+use postgres::rows::Row;
+use postgres::types::ToSql;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use reqwest::Client;
+use serde_json;
+
+use errors::*;
+use pg::{Insertable, PgDatabase};
+use feeds::{Feed, Rss};
+use source::Source;
+use sources::find_source_by_uuid;
+use source_option::{SourceOption, ExtractorOption};
+use extractors::extractor;
+use readability::sanitize_readable;
+use worker_pool::{run_pool, host_of};
+
+/// A job is failed for good after this many attempts
+const MAX_ATTEMPTS: i32 = 8;
+const RETRY_BASE_SECONDS: i64 = 60;
+const RETRY_MAX_SECONDS: i64 = 24 * 60 * 60;
+/// A claimed job is handed to another worker if it was not finished in this time
+const LOCK_SECONDS: i64 = 10 * 60;
+const BATCH_SIZE: i64 = 200;
+
+#[derive(Debug, PartialEq, ToSql, FromSql)]
+#[postgres(name = "extractionstatus")]
+pub enum ExtractionStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// Readable content extraction of a feed, processed apart from the ingestion so items show up right away
+#[derive(Debug)]
+pub struct ExtractionJob {
+    pub uuid: Uuid,
+    pub feed_uuid: Uuid,
+    pub status: ExtractionStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+impl ExtractionJob {
+    pub fn new(feed_uuid: Uuid) -> Self {
+        ExtractionJob {
+            uuid: Uuid::new_v4(),
+            feed_uuid,
+            status: ExtractionStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: Utc::now().naive_utc(),
+            locked_until: None,
+            created: Utc::now().naive_utc(),
+            updated: Utc::now().naive_utc(),
+        }
+    }
+}
+
+impl<'a> From<Row<'a>> for ExtractionJob {
+    fn from(row: Row) -> Self {
+        ExtractionJob {
+            uuid: row.get("uuid"),
+            feed_uuid: row.get("feed_uuid"),
+            status: row.get("status"),
+            attempts: row.get("attempts"),
+            last_error: row.get("last_error"),
+            next_attempt_at: row.get("next_attempt_at"),
+            locked_until: row.get("locked_until"),
+            created: row.get("created"),
+            updated: row.get("updated"),
+        }
+    }
+}
+
+impl Insertable for ExtractionJob {
+    fn insert_query(&self) -> String {
+        r#"
+            INSERT INTO extraction_jobs (uuid, feed_uuid, status, attempts, last_error, next_attempt_at, locked_until, created, updated)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+        "#.to_owned()
+    }
+
+    fn insert_params(&self) -> Box<[&ToSql]> {
+        Box::new([
+            &self.uuid,
+            &self.feed_uuid,
+            &self.status,
+            &self.attempts,
+            &self.last_error,
+            &self.next_attempt_at,
+            &self.locked_until,
+            &self.created,
+            &self.updated,
+        ])
+    }
+}
+
+pub fn enqueue_extraction(pg: &PgDatabase, feed: &Feed) -> Result<u64> {
+    pg.insert(&ExtractionJob::new(feed.uuid))
+}
+
+fn retry_delay(attempts: i32) -> Duration {
+    let exponent = cmp::min(cmp::max(attempts - 1, 0), 20) as u32;
+    Duration::seconds(cmp::min(RETRY_BASE_SECONDS * 2i64.pow(exponent), RETRY_MAX_SECONDS))
+}
+
+/// Locks a batch of due jobs for this run, `SKIP LOCKED` keeps concurrent servers from taking the same ones
+fn claim_jobs(pg: &PgDatabase, now: &NaiveDateTime) -> Result<Vec<ExtractionJob>> {
+    let locked_until = *now + Duration::seconds(LOCK_SECONDS);
+    let query = r#"
+        UPDATE extraction_jobs SET locked_until = $1
+        WHERE uuid IN (
+            SELECT uuid FROM extraction_jobs
+            WHERE status = 'Pending'
+                AND next_attempt_at <= $2
+                AND (locked_until IS NULL OR locked_until < $2)
+            ORDER BY next_attempt_at
+            LIMIT $3
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *;
+    "#;
+    pg.find(query, &[&locked_until, now, &BATCH_SIZE])
+}
+
+fn find_feed_by_uuid(pg: &PgDatabase, uuid: &Uuid) -> Result<Option<Feed>> {
+    pg.find_one("SELECT * FROM feeds WHERE uuid = $1::uuid;", &[uuid])
+}
+
+fn source_extractor(source: &Source) -> Result<ExtractorOption> {
+    match source.options()? {
+        SourceOption::Rss(rss_source) => Ok(rss_source.extractor),
+        _ => Ok(ExtractorOption::Readability),
+    }
+}
+
+fn extract_feed(client: &Client, pg: &PgDatabase, job: &ExtractionJob) -> Result<()> {
+    let feed = find_feed_by_uuid(pg, &job.feed_uuid)?.ok_or(ErrorKind::NotFound)?;
+    let source = find_source_by_uuid(pg, &feed.source_uuid)?.ok_or(ErrorKind::NotFound)?;
+    let rss = feed.rss.clone().and_then(|rss| serde_json::from_value::<Rss>(rss).ok());
+    if let Some(rss) = rss {
+        let extractor = extractor(&source_extractor(&source)?)?;
+        if let Some(readable) = extractor.extract(client, &feed.url, &rss)? {
+            let readable = serde_json::to_value(sanitize_readable(readable, &feed.url))?;
+            let query = "UPDATE feeds SET readable = $1, updated = $2 WHERE uuid = $3::uuid;";
+            pg.update(query, &[&readable, &Utc::now().naive_utc(), &feed.uuid])?;
+        }
+    }
+    Ok(())
+}
+
+fn record_success(pg: &PgDatabase, job: &ExtractionJob) -> Result<u64> {
+    let query = r#"
+        UPDATE extraction_jobs
+        SET status = 'Done', attempts = $1, last_error = NULL, locked_until = NULL, updated = $2
+        WHERE uuid = $3::uuid;
+    "#;
+    pg.update(query, &[&(job.attempts + 1), &Utc::now().naive_utc(), &job.uuid])
+}
+
+fn record_failure(pg: &PgDatabase, job: &ExtractionJob, error: &Error) -> Result<u64> {
+    let now = Utc::now().naive_utc();
+    let attempts = job.attempts + 1;
+    let status = if attempts >= MAX_ATTEMPTS { ExtractionStatus::Failed } else { ExtractionStatus::Pending };
+    let next_attempt_at = now + retry_delay(attempts);
+    let query = r#"
+        UPDATE extraction_jobs
+        SET status = $1, attempts = $2, last_error = $3, next_attempt_at = $4, locked_until = NULL, updated = $5
+        WHERE uuid = $6::uuid;
+    "#;
+    pg.update(query, &[&status, &attempts, &error.to_string(), &next_attempt_at, &now, &job.uuid])
+}
+
+fn process_job(client: &Client, pool: &Pool<PostgresConnectionManager>, job: &ExtractionJob) -> Result<()> {
+    let pg = PgDatabase::from_pool(pool.clone())?;
+    match extract_feed(client, &pg, job) {
+        Ok(_) => record_success(&pg, job)?,
+        Err(error) => {
+            println!("extraction {:?} error {}", job.feed_uuid, error);
+            record_failure(&pg, job, &error)?
+        },
+    };
+    Ok(())
+}
+
+fn process_jobs(client: &Client, pool: &Pool<PostgresConnectionManager>, workers: usize, max_per_host: usize) -> Result<()> {
+    let jobs = {
+        let pg = PgDatabase::from_pool(pool.clone())?;
+        let jobs = claim_jobs(&pg, &Utc::now().naive_utc())?;
+        let mut hosted_jobs = Vec::new();
+        for job in jobs {
+            let host = find_feed_by_uuid(&pg, &job.feed_uuid)?.map(|feed| host_of(&feed.url)).unwrap_or_else(String::new);
+            hosted_jobs.push((host, job));
+        }
+        hosted_jobs
+    };
+    let client = client.clone();
+    let pool = pool.clone();
+    run_pool(jobs, workers, max_per_host, move |job: ExtractionJob| {
+        if let Err(err) = process_job(&client, &pool, &job) {
+            println!("process_job {:?} error {:?}", job.uuid, err);
+        }
+    });
+    Ok(())
+}
+
+pub fn run_extraction_job(interval: StdDuration, workers: usize, max_per_host: usize, client: Client, pool: Pool<PostgresConnectionManager>) {
+    thread::spawn(move || {
+        loop {
+            let started = Instant::now();
+            if let Err(err) = process_jobs(&client, &pool, workers, max_per_host) {
+                println!("process_jobs error {:?}", err);
+            }
+            let elapsed = started.elapsed();
+            if elapsed < interval {
+                thread::sleep(interval - elapsed);
+            }
+        }
+    });
+}
@@ -0,0 +1,203 @@
+use reqwest::Client;
+use scraper::{Html, Selector};
+use url::Url;
+
+use errors::*;
+use feeds::Rss;
+use readability::{self, ReadableData, clean_html, first_text, text_of, truncate_words};
+use source_option::{ExtractorOption, ExtractorKind, ExtractorInput};
+use config::CONFIG;
+
+const EXCERPT_LENGTH: usize = 200;
+
+/// Produces the readable version of a feed item
+pub trait Extractor {
+    fn extract(&self, client: &Client, url: &str, rss: &Rss) -> Result<Option<ReadableData>>;
+}
+
+/// Leaves items without readable content
+pub struct NoExtractor;
+
+impl Extractor for NoExtractor {
+    fn extract(&self, _client: &Client, _url: &str, _rss: &Rss) -> Result<Option<ReadableData>> {
+        Ok(None)
+    }
+}
+
+/// Uses the content shipped in the feed, saves a round trip for full content feeds
+pub struct RssContentExtractor;
+
+impl Extractor for RssContentExtractor {
+    fn extract(&self, _client: &Client, url: &str, rss: &Rss) -> Result<Option<ReadableData>> {
+        let content = match rss.content.clone().or_else(|| rss.summary.clone()) {
+            Some(content) => content,
+            None => return Ok(None),
+        };
+        let text = text_of(&content);
+        Ok(Some(ReadableData {
+            url: url.to_owned(),
+            domain: Url::parse(url).ok().and_then(|url| url.host_str().map(|host| host.to_owned())),
+            title: rss.title.clone(),
+            date_published: Some(rss.published.clone()),
+            lead_image_url: None,
+            dek: None,
+            excerpt: rss.summary.as_ref().map(|summary| truncate_words(&text_of(summary), EXCERPT_LENGTH))
+                .or_else(|| Some(truncate_words(&text, EXCERPT_LENGTH))),
+            word_count: Some(text.split_whitespace().count() as i32),
+            direction: None,
+            total_pages: Some(1),
+            rendered_pages: Some(1),
+            next_page_url: None,
+            content: Some(content),
+        }))
+    }
+}
+
+/// Built-in readability engine, or Mercury when a key is configured
+pub struct ReadabilityExtractor;
+
+impl Extractor for ReadabilityExtractor {
+    fn extract(&self, client: &Client, url: &str, _rss: &Rss) -> Result<Option<ReadableData>> {
+        readability::fetch_readable(client, url)
+    }
+}
+
+/// Takes the elements matching user supplied CSS selectors
+pub struct SelectorsExtractor {
+    content: Selector,
+    title: Option<Selector>,
+    remove: Vec<Selector>,
+}
+
+impl Extractor for SelectorsExtractor {
+    fn extract(&self, client: &Client, url: &str, rss: &Rss) -> Result<Option<ReadableData>> {
+        let base_url = Url::parse(url)?;
+        let html = readability::fetch_html(client, url)?;
+        let document = Html::parse_document(&html);
+        let mut content = String::new();
+        for element in document.select(&self.content) {
+            clean_html(element, &base_url, &self.remove, &mut content);
+        }
+        if content.trim().is_empty() {
+            return Ok(None);
+        }
+        let text = text_of(&content);
+        Ok(Some(ReadableData {
+            url: url.to_owned(),
+            domain: base_url.host_str().map(|host| host.to_owned()),
+            title: self.title.as_ref().and_then(|title| first_text(&document, title)).or_else(|| rss.title.clone()),
+            date_published: Some(rss.published.clone()),
+            lead_image_url: None,
+            dek: None,
+            excerpt: Some(truncate_words(&text, EXCERPT_LENGTH)),
+            word_count: Some(text.split_whitespace().count() as i32),
+            direction: None,
+            total_pages: Some(1),
+            rendered_pages: Some(1),
+            next_page_url: None,
+            content: Some(content),
+        }))
+    }
+}
+
+/// Mercury compatible service, called with the article as `?url=` and answering `ReadableData` json,
+/// the answer is sanitized before being stored like any other extractor output
+pub struct ExternalExtractor {
+    endpoint: Url,
+}
+
+impl Extractor for ExternalExtractor {
+    fn extract(&self, client: &Client, url: &str, _rss: &Rss) -> Result<Option<ReadableData>> {
+        let mut endpoint = self.endpoint.clone();
+        endpoint.query_pairs_mut().append_pair("url", url);
+        let mut response = client.get(endpoint).send()?;
+        if !response.status().is_success() {
+            bail!(ErrorKind::HttpStatus(response.status().as_u16(), None));
+        }
+        Ok(response.json()?)
+    }
+}
+
+pub fn parse_selector(selector: &str) -> Result<Selector> {
+    Selector::parse(selector).map_err(|_| ErrorKind::InvalidSelector(selector.to_owned()).into())
+}
+
+/// Only the services listed in EXTRACTOR_ENDPOINTS can be called, a user supplied url would reach the internal network
+pub fn allowed_endpoint(endpoint: &str, allowed: &[String]) -> Result<Url> {
+    let url = Url::parse(endpoint)?;
+    let is_http = url.scheme() == "http" || url.scheme() == "https";
+    let is_allowed = allowed.iter().filter_map(|allowed| Url::parse(allowed).ok()).any(|allowed| allowed == url);
+    if !is_http || !is_allowed {
+        bail!("extractor endpoint {} is not allowed", endpoint);
+    }
+    Ok(url)
+}
+
+pub fn extractor(option: &ExtractorOption) -> Result<Box<Extractor>> {
+    Ok(match *option {
+        ExtractorOption::None => Box::new(NoExtractor),
+        ExtractorOption::RssContent => Box::new(RssContentExtractor),
+        ExtractorOption::Readability => Box::new(ReadabilityExtractor),
+        ExtractorOption::Selectors { ref content, ref title, ref remove } => {
+            let mut removed = Vec::new();
+            for selector in remove {
+                removed.push(parse_selector(selector)?);
+            }
+            Box::new(SelectorsExtractor {
+                content: parse_selector(content)?,
+                title: match *title {
+                    Some(ref title) => Some(parse_selector(title)?),
+                    None => None,
+                },
+                remove: removed,
+            })
+        },
+        ExtractorOption::External { ref endpoint } => Box::new(ExternalExtractor { endpoint: allowed_endpoint(endpoint, &CONFIG.extractor_endpoints)? }),
+    })
+}
+
+/// Builds the option out of the GraphQL input, checking that the selectors and endpoint are usable
+pub fn extractor_option(input: ExtractorInput) -> Result<ExtractorOption> {
+    let option = match input.kind {
+        ExtractorKind::None => ExtractorOption::None,
+        ExtractorKind::RssContent => ExtractorOption::RssContent,
+        ExtractorKind::Readability => ExtractorOption::Readability,
+        ExtractorKind::Selectors => {
+            let content = input.content_selector.ok_or_else(|| Error::from("content_selector is required"))?;
+            ExtractorOption::Selectors {
+                content,
+                title: input.title_selector,
+                remove: input.remove_selectors.unwrap_or_else(Vec::new),
+            }
+        },
+        ExtractorKind::External => {
+            let endpoint = input.endpoint.ok_or_else(|| Error::from("endpoint is required"))?;
+            ExtractorOption::External { endpoint }
+        },
+    };
+    extractor(&option)?;
+    Ok(option)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed() -> Vec<String> {
+        vec!["https://mercury.example.com/parser".to_owned()]
+    }
+
+    #[test]
+    fn listed_endpoint_is_allowed() {
+        assert!(allowed_endpoint("https://mercury.example.com/parser", &allowed()).is_ok());
+    }
+
+    #[test]
+    fn other_endpoints_are_refused() {
+        assert!(allowed_endpoint("http://169.254.169.254/latest/meta-data", &allowed()).is_err());
+        assert!(allowed_endpoint("http://localhost:5432/", &allowed()).is_err());
+        assert!(allowed_endpoint("https://mercury.example.com/other", &allowed()).is_err());
+        assert!(allowed_endpoint("file:///etc/passwd", &["file:///etc/passwd".to_owned()]).is_err());
+        assert!(allowed_endpoint("https://mercury.example.com/parser", &[]).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use std::thread;
+use std::time::Duration;
+use reqwest::Client;
+use scraper::{Html, Selector};
+use url::Url;
+
+use errors::*;
+use rss::{fetch_feeds_channel_conditional, FeedsChannel};
+
+const FEED_MIME_TYPES: [&str; 4] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+    "application/json",
+];
+
+/// Discovery runs inside a GraphQL request, a slow site must not hold it
+const DISCOVERY_TIMEOUT_SECONDS: u64 = 10;
+/// Probed in addition to the feeds the page advertises
+const COMMON_FEED_PATHS: [&str; 7] = ["/feed", "/rss", "/rss.xml", "/atom.xml", "/feed.xml", "/index.xml", "/feed.json"];
+
+#[derive(GraphQLObject, Debug, Clone, PartialEq)]
+pub struct FeedCandidate {
+    pub url: String,
+    pub title: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+/// Feeds advertised by `<link rel="alternate">` in the page head
+pub fn find_link_candidates(html: &str, page_url: &Url) -> Vec<FeedCandidate> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("link[rel][href]").expect("valid link selector");
+    let mut candidates: Vec<FeedCandidate> = Vec::new();
+    for element in document.select(&selector) {
+        let element = element.value();
+        let is_alternate = element.attr("rel")
+            .map(|rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("alternate")))
+            .unwrap_or(false);
+        let mime_type = element.attr("type").map(|mime_type| mime_type.trim().to_lowercase());
+        let is_feed = mime_type.as_ref().map(|mime_type| FEED_MIME_TYPES.contains(&mime_type.as_str())).unwrap_or(false);
+        if is_alternate && is_feed {
+            if let Some(url) = element.attr("href").and_then(|href| page_url.join(href).ok()) {
+                let candidate = FeedCandidate {
+                    url: url.into_string(),
+                    title: element.attr("title").map(|title| title.trim().to_owned()),
+                    mime_type,
+                };
+                if !candidates.iter().any(|existing| existing.url == candidate.url) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+    }
+    candidates
+}
+
+/// Client with a bounded timeout, shared by the page fetch and the probes
+pub fn discovery_client() -> Result<Client> {
+    Ok(Client::builder().timeout(Duration::from_secs(DISCOVERY_TIMEOUT_SECONDS)).build()?)
+}
+
+/// Tries the usual feed locations of the site in parallel, keeping the ones that parse as a feed
+pub fn probe_common_paths(client: &Client, page_url: &Url) -> Vec<FeedCandidate> {
+    let probes: Vec<_> = COMMON_FEED_PATHS.iter()
+        .filter_map(|path| page_url.join(path).ok())
+        .map(|url| {
+            let client = client.clone();
+            thread::spawn(move || match fetch_feeds_channel_conditional(&client, url.as_str(), &None, &None) {
+                Ok(FeedsChannel::Modified { feed, .. }) => Some(FeedCandidate {
+                    url: url.into_string(),
+                    title: feed.title,
+                    mime_type: None,
+                }),
+                _ => None,
+            })
+        })
+        .collect();
+    probes.into_iter().filter_map(|probe| probe.join().ok().and_then(|candidate| candidate)).collect()
+}
+
+/// Advertised feeds first, then the common paths answering with a feed
+pub fn discover_feeds(client: &Client, html: &str, page_url: &Url) -> Vec<FeedCandidate> {
+    let mut candidates = find_link_candidates(html, page_url);
+    for candidate in probe_common_paths(client, page_url) {
+        if !candidates.iter().any(|existing| existing.url == candidate.url) {
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
@@ -0,0 +1,99 @@
+use quick_xml::reader::Reader;
+use quick_xml::events::{Event, BytesStart};
+
+/// Channel level metadata that feed_rs does not expose
+#[derive(Debug, Default, Clone)]
+pub struct FeedMeta {
+    /// `<ttl>` in minutes
+    pub ttl: Option<i64>,
+    /// `sy:updatePeriod`, hourly, daily, weekly, monthly or yearly
+    pub update_period: Option<String>,
+    /// `sy:updateFrequency`, number of updates per period
+    pub update_frequency: Option<i64>,
+    /// WebSub hub advertised with `<link rel="hub">`
+    pub hub: Option<String>,
+    /// Canonical feed url advertised with `<link rel="self">`
+    pub self_url: Option<String>,
+}
+
+fn local_name(name: &[u8]) -> &[u8] {
+    match name.iter().rposition(|byte| *byte == b':') {
+        Some(index) => &name[index + 1..],
+        None => name,
+    }
+}
+
+fn is_inside_entry(path: &Vec<Vec<u8>>) -> bool {
+    path.iter().any(|name| name.as_slice() == b"item" || name.as_slice() == b"entry")
+}
+
+fn link_rel_href(element: &BytesStart, reader: &Reader<&[u8]>) -> Option<(String, String)> {
+    let mut rel = None;
+    let mut href = None;
+    for attribute in element.attributes() {
+        if let Ok(attribute) = attribute {
+            let value = attribute.unescape_and_decode_value(reader).ok();
+            match attribute.key {
+                b"rel" => rel = value,
+                b"href" => href = value,
+                _ => (),
+            }
+        }
+    }
+    match (rel, href) {
+        (Some(rel), Some(href)) => Some((rel, href)),
+        _ => None,
+    }
+}
+
+fn read_link(meta: &mut FeedMeta, element: &BytesStart, reader: &Reader<&[u8]>) {
+    if local_name(element.name()) == b"link" {
+        match link_rel_href(element, reader) {
+            Some((ref rel, ref href)) if rel == "hub" && meta.hub.is_none() => meta.hub = Some(href.to_owned()),
+            Some((ref rel, ref href)) if rel == "self" && meta.self_url.is_none() => meta.self_url = Some(href.to_owned()),
+            _ => (),
+        }
+    }
+}
+
+pub fn parse_feed_meta(document: &str) -> FeedMeta {
+    let mut meta = FeedMeta::default();
+    let mut reader = Reader::from_str(document);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref element)) => {
+                if !is_inside_entry(&path) {
+                    read_link(&mut meta, element, &reader);
+                }
+                path.push(local_name(element.name()).to_vec());
+            },
+            Ok(Event::Empty(ref element)) => {
+                if !is_inside_entry(&path) {
+                    read_link(&mut meta, element, &reader);
+                }
+            },
+            Ok(Event::End(_)) => {
+                path.pop();
+            },
+            Ok(Event::Text(ref text)) => {
+                if !is_inside_entry(&path) {
+                    let value = text.unescape_and_decode(&reader).unwrap_or_default();
+                    let value = value.trim();
+                    match path.last().map(|name| name.as_slice()) {
+                        Some(b"ttl") => meta.ttl = value.parse().ok(),
+                        Some(b"updatePeriod") => meta.update_period = Some(value.to_owned()),
+                        Some(b"updateFrequency") => meta.update_frequency = value.parse().ok(),
+                        _ => (),
+                    }
+                }
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    meta
+}
@@ -8,12 +8,18 @@ use r2d2_postgres::PostgresConnectionManager;
 use serde_json;
 use serde_json::Value;
 use feed_rs::entry::Entry;
+use crypto::digest::Digest;
+use crypto::md5::Md5;
 
 use errors::*;
 use graphql::query::Query;
 use source::Source;
-use mercury::ReadableData;
+use readability::ReadableData;
+use twitter::Tweet;
+use mastodon::Toot;
+use podcast::{Enclosure, PodcastEpisode};
 use pg::{Insertable, PgDatabase};
+use search::search_config;
 
 #[derive(GraphQLObject, Serialize, Deserialize, Debug, Clone)]
 pub struct Rss {
@@ -26,8 +32,13 @@ pub struct Rss {
     pub updated:     Option<String>,
     pub alternate:   Option<String>,
     pub keywords:    Vec<String>,
+    /// Url of the first enclosure
     pub enclosure:   Option<String>,
     pub fingerprint: String,
+    #[serde(default)]
+    pub enclosures:  Vec<Enclosure>,
+    #[serde(default)]
+    pub podcast:     Option<PodcastEpisode>,
 }
 
 impl From<Entry> for Rss {
@@ -42,12 +53,23 @@ impl From<Entry> for Rss {
             updated: entry.updated.map(|updated| updated.to_string()),
             alternate: entry.alternate.iter().map(|link| link.href.clone()).collect::<Vec<String>>().pop(),
             keywords: entry.keywords,
-            enclosure: entry.alternate.iter().map(|link| link.href.clone()).collect::<Vec<String>>().pop(),
+            enclosure: None,
             fingerprint: entry.fingerprint,
+            enclosures: Vec::new(),
+            podcast: None,
         }
     }
 }
 
+/// Fingerprint of items whose format does not provide one
+pub fn item_fingerprint(id: &str, title: &Option<String>, content: &Option<String>) -> String {
+    let mut md5 = Md5::new();
+    md5.input_str(id);
+    md5.input_str(title.as_ref().map(|title| title.as_str()).unwrap_or(""));
+    md5.input_str(content.as_ref().map(|content| content.as_str()).unwrap_or(""));
+    md5.result_str()
+}
+
 #[derive(Debug, Clone)]
 pub struct Feed {
     pub uuid: Uuid,
@@ -55,22 +77,28 @@ pub struct Feed {
     pub rss: Option<Value>,
     pub readable: Option<Value>,
     pub twitter: Option<Value>,
+    pub mastodon: Option<Value>,
     pub created: NaiveDateTime,
     pub updated: NaiveDateTime,
     pub source_uuid: Uuid,
+    /// Postgres text search configuration of the feed language
+    pub search_config: String,
 }
 
 impl Feed {
-    pub fn new(url: &str, rss: Option<Rss>, readable: Option<ReadableData>, twitter: Option<Value>, source_uuid: Uuid) -> Self {
+    pub fn new(url: &str, rss: Option<Rss>, readable: Option<ReadableData>, twitter: Option<Value>, mastodon: Option<Value>, source_uuid: Uuid) -> Self {
+        let search_config = search_config(&rss, &mastodon).to_owned();
         Feed {
             uuid: Uuid::new_v4(),
             url: url.to_string(),
             rss: serde_json::to_value(rss).ok(),
             readable: serde_json::to_value(readable).ok(),
             twitter,
+            mastodon,
             created: Utc::now().naive_utc(),
             updated: Utc::now().naive_utc(),
             source_uuid,
+            search_config,
         }
     }
 }
@@ -95,8 +123,12 @@ graphql_object!(Feed: Query as "Feed" |&self| {
         
     }
     
-    field twitter() -> Option<String> as "twitter" {
-        None
+    field twitter() -> Option<Tweet> as "twitter" {
+        self.twitter.clone().and_then(|t| serde_json::from_value::<Tweet>(t).ok())
+    }
+
+    field mastodon() -> Option<Toot> as "mastodon" {
+        self.mastodon.clone().and_then(|m| serde_json::from_value::<Toot>(m).ok())
     }
 
     field created() -> String as "created" {
@@ -120,9 +152,11 @@ impl<'a> From<Row<'a>> for Feed {
             rss: row.get("rss"),
             readable: row.get("readable"),
             twitter: row.get("twitter"),
+            mastodon: row.get("mastodon"),
             created: row.get("created"),
             updated: row.get("updated"),
             source_uuid: row.get("source_uuid"),
+            search_config: row.get("search_config"),
         }
     }
 }
@@ -130,8 +164,8 @@ impl<'a> From<Row<'a>> for Feed {
 impl Insertable for Feed {
     fn insert_query(&self) -> String {
         r#"
-            INSERT INTO feeds (uuid, url, rss, readable, twitter, created, updated, source_uuid)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8);
+            INSERT INTO feeds (uuid, url, rss, readable, twitter, mastodon, created, updated, source_uuid, search_config)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);
         "#.to_owned()
     }
 
@@ -142,9 +176,11 @@ impl Insertable for Feed {
             &self.rss,
             &self.readable,
             &self.twitter,
+            &self.mastodon,
             &self.created,
             &self.updated,
             &self.source_uuid,
+            &self.search_config,
         ])
     }
 }
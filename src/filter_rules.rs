@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+use chrono::prelude::*;
+use chrono::NaiveDateTime;
+use postgres::rows::Row;
+use postgres::types::ToSql;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use regex::{Regex, RegexBuilder};
+use serde_json;
+
+use errors::*;
+use pg::{Insertable, PgDatabase};
+use feeds::{Feed, Rss};
+use twitter::Tweet;
+use mastodon::Toot;
+use readability::text_of;
+use users_feeds::Reaction;
+use users_sources::user_source_exist;
+use user::User;
+
+/// Compiled regexes bigger than this are refused, patterns come from users
+const REGEX_SIZE_LIMIT: usize = 1024 * 1024;
+/// Recent feeds of the user a dry run looks at
+const PREVIEW_SCAN: i64 = 1000;
+/// Reactions a rule can give, the others only make sense once the item was seen
+const RULE_REACTIONS: [Reaction; 4] = [Reaction::Archived, Reaction::ReadLater, Reaction::Liked, Reaction::Readed];
+
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, ToSql, FromSql)]
+#[postgres(name = "filterfield")]
+pub enum FilterField {
+    Title,
+    Content,
+    Author,
+    Keywords,
+    Any,
+}
+
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, ToSql, FromSql)]
+#[postgres(name = "filtermatch")]
+pub enum FilterMatch {
+    /// Case insensitive
+    Substring,
+    Regex,
+}
+
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq, ToSql, FromSql)]
+#[postgres(name = "filteraction")]
+pub enum FilterAction {
+    /// The item is not added to the stream of the user
+    Skip,
+    SetReaction,
+    Tag,
+}
+
+/// Rule run on every new item fanned out to its user, in `position` order
+#[derive(Debug)]
+pub struct FilterRule {
+    pub uuid: Uuid,
+    pub user_uuid: Uuid,
+    pub name: String,
+    pub field: FilterField,
+    pub match_type: FilterMatch,
+    pub pattern: String,
+    /// Only items of this source, of every source when not set
+    pub source_uuid: Option<Uuid>,
+    pub action: FilterAction,
+    pub reaction: Option<Reaction>,
+    pub tag: Option<String>,
+    pub position: i32,
+    pub enabled: bool,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+pub struct FilterRuleInput {
+    pub name: String,
+    pub field: FilterField,
+    pub match_type: FilterMatch,
+    pub pattern: String,
+    pub source_uuid: Option<String>,
+    pub action: FilterAction,
+    /// Archived, ReadLater, Liked or Readed, for the SetReaction action
+    pub reaction: Option<String>,
+    /// For the Tag action
+    pub tag: Option<String>,
+    pub position: Option<i32>,
+    pub enabled: Option<bool>,
+}
+
+impl FilterRule {
+    pub fn new(user: &User, input: FilterRuleInput) -> Result<Self> {
+        let mut rule = FilterRule {
+            uuid: Uuid::new_v4(),
+            user_uuid: user.uuid,
+            name: String::new(),
+            field: FilterField::Any,
+            match_type: FilterMatch::Substring,
+            pattern: String::new(),
+            source_uuid: None,
+            action: FilterAction::Skip,
+            reaction: None,
+            tag: None,
+            position: 0,
+            enabled: true,
+            created: Utc::now().naive_utc(),
+            updated: Utc::now().naive_utc(),
+        };
+        rule.apply(input)?;
+        Ok(rule)
+    }
+
+    fn apply(&mut self, input: FilterRuleInput) -> Result<()> {
+        if input.name.trim().is_empty() || input.pattern.is_empty() {
+            bail!("filter rule name and pattern can not be empty");
+        }
+        let reaction = match input.reaction {
+            Some(ref reaction) => Some(Reaction::from_str(reaction)?),
+            None => None,
+        };
+        let tag = input.tag.map(|tag| tag.trim().to_owned()).and_then(|tag| if tag.is_empty() { None } else { Some(tag) });
+        match input.action {
+            FilterAction::SetReaction if !reaction.map(|reaction| RULE_REACTIONS.contains(&reaction)).unwrap_or(false) => {
+                bail!("a SetReaction rule needs one of the Archived, ReadLater, Liked or Readed reactions")
+            },
+            FilterAction::Tag if tag.is_none() => bail!("a Tag rule needs a tag"),
+            _ => (),
+        }
+        self.name = input.name.trim().to_owned();
+        self.field = input.field;
+        self.match_type = input.match_type;
+        self.pattern = input.pattern;
+        self.source_uuid = match input.source_uuid {
+            Some(ref source_uuid) => Some(Uuid::parse_str(source_uuid)?),
+            None => None,
+        };
+        self.action = input.action;
+        self.reaction = if self.action == FilterAction::SetReaction { reaction } else { None };
+        self.tag = if self.action == FilterAction::Tag { tag } else { None };
+        self.position = input.position.unwrap_or(self.position);
+        self.enabled = input.enabled.unwrap_or(self.enabled);
+        self.updated = Utc::now().naive_utc();
+        CompiledRule::new(self)?;
+        Ok(())
+    }
+}
+
+impl<'a> From<Row<'a>> for FilterRule {
+    fn from(row: Row) -> Self {
+        FilterRule {
+            uuid: row.get("uuid"),
+            user_uuid: row.get("user_uuid"),
+            name: row.get("name"),
+            field: row.get("field"),
+            match_type: row.get("match_type"),
+            pattern: row.get("pattern"),
+            source_uuid: row.get("source_uuid"),
+            action: row.get("action"),
+            reaction: row.get("reaction"),
+            tag: row.get("tag"),
+            position: row.get("position"),
+            enabled: row.get("enabled"),
+            created: row.get("created"),
+            updated: row.get("updated"),
+        }
+    }
+}
+
+impl Insertable for FilterRule {
+    fn insert_query(&self) -> String {
+        r#"
+            INSERT INTO filter_rules (uuid, user_uuid, name, field, match_type, pattern, source_uuid, action, reaction, tag, position, enabled, created, updated)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14);
+        "#.to_owned()
+    }
+
+    fn insert_params(&self) -> Box<[&ToSql]> {
+        Box::new([
+            &self.uuid,
+            &self.user_uuid,
+            &self.name,
+            &self.field,
+            &self.match_type,
+            &self.pattern,
+            &self.source_uuid,
+            &self.action,
+            &self.reaction,
+            &self.tag,
+            &self.position,
+            &self.enabled,
+            &self.created,
+            &self.updated,
+        ])
+    }
+}
+
+graphql_object!(FilterRule: () as "FilterRule" |&self| {
+    description: "Rule run on the new items of the user, in position order"
+
+    field uuid() -> Uuid {
+        self.uuid
+    }
+
+    field name() -> &String {
+        &self.name
+    }
+
+    field field() -> FilterField {
+        self.field
+    }
+
+    field match_type() -> FilterMatch {
+        self.match_type
+    }
+
+    field pattern() -> &String {
+        &self.pattern
+    }
+
+    field source_uuid() -> Option<String> as "Only items of this source" {
+        self.source_uuid.map(|uuid| uuid.hyphenated().to_string())
+    }
+
+    field action() -> FilterAction {
+        self.action
+    }
+
+    field reaction() -> Option<String> {
+        self.reaction.map(|reaction| reaction.to_string())
+    }
+
+    field tag() -> &Option<String> {
+        &self.tag
+    }
+
+    field position() -> i32 {
+        self.position
+    }
+
+    field enabled() -> bool {
+        self.enabled
+    }
+});
+
+/// Text of an item the rules match on
+#[derive(Debug, Default)]
+pub struct FilterItem {
+    pub title: String,
+    pub content: String,
+    pub author: String,
+    pub keywords: Vec<String>,
+    pub source_uuid: Option<Uuid>,
+}
+
+impl<'a> From<&'a Feed> for FilterItem {
+    fn from(feed: &Feed) -> Self {
+        let mut item = FilterItem { source_uuid: Some(feed.source_uuid), ..FilterItem::default() };
+        let mut contents = Vec::new();
+        if let Some(rss) = feed.rss.clone().and_then(|rss| serde_json::from_value::<Rss>(rss).ok()) {
+            item.title = rss.title.unwrap_or_default();
+            item.author = rss.author.unwrap_or_default();
+            item.keywords = rss.keywords;
+            contents.extend(rss.summary);
+            contents.extend(rss.content);
+        }
+        if let Some(tweet) = feed.twitter.clone().and_then(|tweet| serde_json::from_value::<Tweet>(tweet).ok()) {
+            item.author = tweet.author_name.unwrap_or(tweet.author_username);
+            contents.push(tweet.text);
+        }
+        if let Some(toot) = feed.mastodon.clone().and_then(|toot| serde_json::from_value::<Toot>(toot).ok()) {
+            item.author = format!("{} {}", toot.account.display_name, toot.account.acct);
+            contents.extend(toot.spoiler_text);
+            contents.push(toot.content);
+        }
+        if let Some(content) = feed.readable.as_ref().and_then(|readable| readable.get("content")).and_then(|content| content.as_str()) {
+            contents.push(content.to_owned());
+        }
+        item.content = contents.iter().map(|content| text_of(content)).collect::<Vec<String>>().join(" ");
+        item
+    }
+}
+
+/// Rule with its pattern ready to run
+pub struct CompiledRule<'a> {
+    pub rule: &'a FilterRule,
+    regex: Regex,
+}
+
+impl<'a> CompiledRule<'a> {
+    pub fn new(rule: &'a FilterRule) -> Result<Self> {
+        let pattern = match rule.match_type {
+            FilterMatch::Substring => ::regex::escape(&rule.pattern),
+            FilterMatch::Regex => rule.pattern.clone(),
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|error| format!("invalid pattern {}", error))?;
+        Ok(CompiledRule { rule, regex })
+    }
+
+    pub fn matches(&self, item: &FilterItem) -> bool {
+        if self.rule.source_uuid.is_some() && self.rule.source_uuid != item.source_uuid {
+            return false;
+        }
+        match self.rule.field {
+            FilterField::Title => self.regex.is_match(&item.title),
+            FilterField::Content => self.regex.is_match(&item.content),
+            FilterField::Author => self.regex.is_match(&item.author),
+            FilterField::Keywords => item.keywords.iter().any(|keyword| self.regex.is_match(keyword)),
+            FilterField::Any => {
+                self.regex.is_match(&item.title) || self.regex.is_match(&item.content) || self.regex.is_match(&item.author)
+                    || item.keywords.iter().any(|keyword| self.regex.is_match(keyword))
+            },
+        }
+    }
+}
+
+/// What the rules of a user decided for an item
+#[derive(Debug, PartialEq)]
+pub struct Verdict {
+    pub skip: bool,
+    pub reaction: Reaction,
+    pub tags: Vec<String>,
+}
+
+/// A skip rule wins over the others, the first matching reaction is kept and every matching tag is applied
+pub fn apply_rules(rules: &[&CompiledRule], item: &FilterItem) -> Verdict {
+    let mut verdict = Verdict { skip: false, reaction: Reaction::Unreaded, tags: Vec::new() };
+    let mut reaction = None;
+    for rule in rules.iter().filter(|rule| rule.matches(item)) {
+        match rule.rule.action {
+            FilterAction::Skip => verdict.skip = true,
+            FilterAction::SetReaction => reaction = reaction.or(rule.rule.reaction),
+            FilterAction::Tag => {
+                if let Some(ref tag) = rule.rule.tag {
+                    if !verdict.tags.contains(tag) {
+                        verdict.tags.push(tag.clone());
+                    }
+                }
+            },
+        }
+    }
+    verdict.reaction = reaction.unwrap_or(Reaction::Unreaded);
+    verdict
+}
+
+/// Compiles the rules, the ones whose pattern stopped compiling are left out
+pub fn compile_rules(rules: &Vec<FilterRule>) -> Vec<CompiledRule> {
+    rules.iter().filter_map(|rule| CompiledRule::new(rule).ok()).collect()
+}
+
+/// Compiled rules of the subscribers of a source grouped by user, built once for a batch of items
+pub struct SubscriberRules<'a> {
+    by_user: HashMap<Uuid, Vec<CompiledRule<'a>>>,
+}
+
+impl<'a> SubscriberRules<'a> {
+    pub fn new(rules: &'a Vec<FilterRule>) -> Self {
+        let mut by_user: HashMap<Uuid, Vec<CompiledRule<'a>>> = HashMap::new();
+        for rule in compile_rules(rules) {
+            by_user.entry(rule.rule.user_uuid).or_insert_with(Vec::new).push(rule);
+        }
+        SubscriberRules { by_user }
+    }
+
+    pub fn verdict(&self, user: &User, item: &FilterItem) -> Verdict {
+        let rules: Vec<&CompiledRule> = self.by_user.get(&user.uuid)
+            .map(|rules| rules.iter().collect())
+            .unwrap_or_else(Vec::new);
+        apply_rules(&rules, item)
+    }
+}
+
+/// Enabled rules of the users, in position order
+pub fn find_enabled_rules(pg: &PgDatabase, users: &Vec<User>) -> Result<Vec<FilterRule>> {
+    let user_uuids: Vec<Uuid> = users.iter().map(|user| user.uuid).collect();
+    let query = r#"
+        SELECT * FROM filter_rules
+        WHERE enabled AND user_uuid = ANY($1)
+        ORDER BY position, created;
+    "#;
+    Ok(pg.find(query, &[&user_uuids])?)
+}
+
+fn check_source(pg: &PgDatabase, rule: &FilterRule, user: &User) -> Result<()> {
+    match rule.source_uuid {
+        Some(ref source_uuid) if !user_source_exist(pg, source_uuid, user)? => Err(ErrorKind::NotFound.into()),
+        _ => Ok(()),
+    }
+}
+
+pub fn create_filter_rule_resolver(pool: Pool<PostgresConnectionManager>, input: FilterRuleInput, user: &User) -> Result<FilterRule> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let rule = FilterRule::new(user, input)?;
+    check_source(&pg, &rule, user)?;
+    pg.insert(&rule)?;
+    Ok(rule)
+}
+
+pub fn update_filter_rule_resolver(pool: Pool<PostgresConnectionManager>, uuid: &str, input: FilterRuleInput, user: &User) -> Result<FilterRule> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let uuid = Uuid::parse_str(uuid)?;
+    let query = "SELECT * FROM filter_rules WHERE uuid = $1::uuid AND user_uuid = $2::uuid;";
+    let mut rule: FilterRule = pg.find_one(query, &[&uuid, &user.uuid])?.ok_or(ErrorKind::NotFound)?;
+    rule.apply(input)?;
+    check_source(&pg, &rule, user)?;
+    let query = r#"
+        UPDATE filter_rules SET name = $1, field = $2, match_type = $3, pattern = $4, source_uuid = $5,
+            action = $6, reaction = $7, tag = $8, position = $9, enabled = $10, updated = $11
+        WHERE uuid = $12::uuid AND user_uuid = $13::uuid;
+    "#;
+    pg.update(query, &[
+        &rule.name,
+        &rule.field,
+        &rule.match_type,
+        &rule.pattern,
+        &rule.source_uuid,
+        &rule.action,
+        &rule.reaction,
+        &rule.tag,
+        &rule.position,
+        &rule.enabled,
+        &rule.updated,
+        &uuid,
+        &user.uuid,
+    ])?;
+    Ok(rule)
+}
+
+pub fn delete_filter_rule_resolver(pool: Pool<PostgresConnectionManager>, uuid: &str, user: &User) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let uuid = Uuid::parse_str(uuid)?;
+    let query = "DELETE FROM filter_rules WHERE uuid = $1::uuid AND user_uuid = $2::uuid;";
+    if pg.update(query, &[&uuid, &user.uuid])? == 0 {
+        return Err(ErrorKind::NotFound.into());
+    }
+    Ok(true)
+}
+
+pub fn filter_rules_resolver(pool: Pool<PostgresConnectionManager>, user: &User) -> Result<Vec<FilterRule>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let query = "SELECT * FROM filter_rules WHERE user_uuid = $1::uuid ORDER BY position, created;";
+    Ok(pg.find(query, &[&user.uuid])?)
+}
+
+/// Dry run of a rule against the recent feeds of the user, nothing is changed
+pub fn preview_filter_rule_resolver(pool: Pool<PostgresConnectionManager>, input: FilterRuleInput, limit: i32, user: &User) -> Result<Vec<Feed>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let rule = FilterRule::new(user, input)?;
+    let compiled = CompiledRule::new(&rule)?;
+    let query = r#"
+        SELECT feeds.* FROM feeds
+        JOIN users_feeds ON users_feeds.feed_uuid = feeds.uuid
+        WHERE users_feeds.user_uuid = $1::uuid
+        ORDER BY feeds.created DESC
+        LIMIT $2;
+    "#;
+    let feeds: Vec<Feed> = pg.find(query, &[&user.uuid, &PREVIEW_SCAN])?;
+    Ok(feeds.into_iter()
+        .filter(|feed| compiled.matches(&FilterItem::from(feed)))
+        .take(limit as usize)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(field: FilterField, match_type: FilterMatch, pattern: &str, action: FilterAction) -> FilterRule {
+        FilterRule {
+            uuid: Uuid::new_v4(),
+            user_uuid: Uuid::new_v4(),
+            name: pattern.to_owned(),
+            field,
+            match_type,
+            pattern: pattern.to_owned(),
+            source_uuid: None,
+            action,
+            reaction: None,
+            tag: None,
+            position: 0,
+            enabled: true,
+            created: Utc::now().naive_utc(),
+            updated: Utc::now().naive_utc(),
+        }
+    }
+
+    fn item(title: &str) -> FilterItem {
+        FilterItem {
+            title: title.to_owned(),
+            content: "Some body text".to_owned(),
+            author: "Jane Doe".to_owned(),
+            keywords: vec!["rust".to_owned(), "web".to_owned()],
+            source_uuid: None,
+        }
+    }
+
+    #[test]
+    fn substring_matches_case_insensitively_and_literally() {
+        let substring = rule(FilterField::Title, FilterMatch::Substring, "RELEASE (beta)", FilterAction::Skip);
+        let compiled = CompiledRule::new(&substring).unwrap();
+        assert!(compiled.matches(&item("New release (Beta) is out")));
+        assert!(!compiled.matches(&item("New release beta is out")));
+    }
+
+    #[test]
+    fn regex_matches_on_its_field_only() {
+        let regex = rule(FilterField::Author, FilterMatch::Regex, "^jane", FilterAction::Skip);
+        let compiled = CompiledRule::new(&regex).unwrap();
+        assert!(compiled.matches(&item("Anything")));
+        let title = rule(FilterField::Title, FilterMatch::Regex, "^jane", FilterAction::Skip);
+        assert!(!CompiledRule::new(&title).unwrap().matches(&item("Anything")));
+    }
+
+    #[test]
+    fn keywords_and_any_fields() {
+        let keywords = rule(FilterField::Keywords, FilterMatch::Regex, "^web$", FilterAction::Skip);
+        assert!(CompiledRule::new(&keywords).unwrap().matches(&item("Anything")));
+        let any = rule(FilterField::Any, FilterMatch::Substring, "body", FilterAction::Skip);
+        assert!(CompiledRule::new(&any).unwrap().matches(&item("Anything")));
+        let missing = rule(FilterField::Any, FilterMatch::Substring, "nowhere", FilterAction::Skip);
+        assert!(!CompiledRule::new(&missing).unwrap().matches(&item("Anything")));
+    }
+
+    #[test]
+    fn rule_of_a_source_only_matches_its_items() {
+        let source_uuid = Uuid::new_v4();
+        let mut scoped = rule(FilterField::Title, FilterMatch::Substring, "news", FilterAction::Skip);
+        scoped.source_uuid = Some(source_uuid);
+        let compiled = CompiledRule::new(&scoped).unwrap();
+        assert!(!compiled.matches(&item("news")));
+        let mut from_source = item("news");
+        from_source.source_uuid = Some(source_uuid);
+        assert!(compiled.matches(&from_source));
+    }
+
+    #[test]
+    fn invalid_regex_is_refused() {
+        let invalid = rule(FilterField::Title, FilterMatch::Regex, "(unclosed", FilterAction::Skip);
+        assert!(CompiledRule::new(&invalid).is_err());
+    }
+
+    #[test]
+    fn apply_rules_combines_the_matching_rules() {
+        let mut liked = rule(FilterField::Title, FilterMatch::Substring, "rust", FilterAction::SetReaction);
+        liked.reaction = Some(Reaction::Liked);
+        let mut archived = rule(FilterField::Title, FilterMatch::Substring, "rust", FilterAction::SetReaction);
+        archived.reaction = Some(Reaction::Archived);
+        let mut tag = rule(FilterField::Any, FilterMatch::Substring, "rust", FilterAction::Tag);
+        tag.tag = Some("lang".to_owned());
+        let mut same_tag = rule(FilterField::Keywords, FilterMatch::Substring, "rust", FilterAction::Tag);
+        same_tag.tag = Some("lang".to_owned());
+        let mut other_tag = rule(FilterField::Title, FilterMatch::Substring, "python", FilterAction::Tag);
+        other_tag.tag = Some("snake".to_owned());
+        let rules = vec![liked, archived, tag, same_tag, other_tag];
+        let compiled = compile_rules(&rules);
+        let compiled: Vec<&CompiledRule> = compiled.iter().collect();
+
+        let verdict = apply_rules(&compiled, &item("Rust 2018"));
+        assert_eq!(verdict, Verdict { skip: false, reaction: Reaction::Liked, tags: vec!["lang".to_owned()] });
+    }
+
+    #[test]
+    fn apply_rules_skips_and_defaults_to_unreaded() {
+        let skip = rule(FilterField::Title, FilterMatch::Substring, "sponsored", FilterAction::Skip);
+        let rules = vec![skip];
+        let compiled = compile_rules(&rules);
+        let compiled: Vec<&CompiledRule> = compiled.iter().collect();
+
+        assert!(apply_rules(&compiled, &item("Sponsored post")).skip);
+        assert_eq!(apply_rules(&compiled, &item("Regular post")), Verdict { skip: false, reaction: Reaction::Unreaded, tags: Vec::new() });
+    }
+}
@@ -1,25 +1,42 @@
 use juniper::{FieldError, FieldResult};
+use uuid::Uuid;
 
 use graphql::query::Query;
 use user::User;
+use token::AuthData;
 use source::Source;
 use users_sources;
 use users_feeds;
+use opml::{self, OpmlImport};
+use source_option::ExtractorInput;
+use sources_resolvers;
+use email;
+use filter_rules::{self, FilterRule, FilterRuleInput};
+use smart_feeds::{self, SmartFeed, SmartFeedInput};
+use webhook::{self, WebhookIngest};
+use refresh_tokens;
+use sessions;
+use account_tokens;
+use users_resolvers;
+use data_export::{self, DataExport};
+use config;
 
 #[derive(Debug)]
 pub struct AuthMutation {
-    pub user: User
+    pub user: User,
+    /// Session of the token the user authenticated with
+    pub session_uuid: Uuid,
 }
 
 impl AuthMutation {
-    pub fn new(user: User) -> Self {
-        AuthMutation { user }
+    pub fn new(user: User, session_uuid: Uuid) -> Self {
+        AuthMutation { user, session_uuid }
     }
 }
 
-impl From<User> for AuthMutation {
-    fn from(user: User) -> Self {
-        AuthMutation::new(user)
+impl From<(User, AuthData)> for AuthMutation {
+    fn from((user, auth): (User, AuthData)) -> Self {
+        AuthMutation::new(user, auth.session_uuid)
     }
 }
 
@@ -38,6 +55,14 @@ graphql_object!(AuthMutation: Query as "AuthMutation" |&self| {
             .map_err(|e| FieldError::from(e.to_string()))
     }
 
+    field retry_source(
+        &executor,
+        source_uuid: String as "source_uuid",
+    ) -> FieldResult<Source> as "Re-enable a followed source and clear its fetch failures" {
+        users_sources::retry_source_resolver(executor.context().connection.clone(), &source_uuid, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
     field feed_reaction(
         &executor,
         feed_uuid: String as "feed_uuid",
@@ -47,4 +72,166 @@ graphql_object!(AuthMutation: Query as "AuthMutation" |&self| {
             .map(|_| String::from("ok"))
             .map_err(|e| FieldError::from(e.to_string()))
     }
+
+    field import_opml(
+        &executor,
+        opml: String as "OPML 2.0 document",
+    ) -> FieldResult<OpmlImport> as "Follow every feed of an OPML document, folders become categories" {
+        opml::import_opml_resolver(executor.context().connection.clone(), &opml, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field set_source_extractor(
+        &executor,
+        source_uuid: String as "source_uuid",
+        extractor: ExtractorInput as "How the readable content of the source items is obtained",
+    ) -> FieldResult<Source> {
+        sources_resolvers::set_source_extractor_resolver(executor.context().connection.clone(), &source_uuid, extractor, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field set_playback_position(
+        &executor,
+        feed_uuid: String as "feed_uuid",
+        position: i32 as "Seconds already played of the enclosure",
+    ) -> FieldResult<i32> {
+        users_feeds::set_playback_position_resolver(executor.context().connection.clone(), &feed_uuid, position, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field create_inbound_address(
+        &executor,
+        title: Option<String> as "Name of the newsletters source",
+    ) -> FieldResult<Source> as "Email source with a private address to subscribe newsletters with" {
+        email::create_inbound_address_resolver(executor.context().connection.clone(), title, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field create_webhook_source(
+        &executor,
+        title: String as "Name of the source",
+    ) -> FieldResult<WebhookIngest> as "Source fed by JSON items posted on its secret ingest url" {
+        webhook::create_webhook_source_resolver(executor.context().connection.clone(), &title, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field create_smart_feed(
+        &executor,
+        smart_feed: SmartFeedInput as "Name, words and filters of the saved search",
+    ) -> FieldResult<SmartFeed> {
+        smart_feeds::create_smart_feed_resolver(executor.context().connection.clone(), smart_feed, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field update_smart_feed(
+        &executor,
+        uuid: String as "Smart feed Uuid",
+        smart_feed: SmartFeedInput as "Name, words and filters of the saved search",
+    ) -> FieldResult<SmartFeed> {
+        smart_feeds::update_smart_feed_resolver(executor.context().connection.clone(), &uuid, smart_feed, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field delete_smart_feed(
+        &executor,
+        uuid: String as "Smart feed Uuid",
+    ) -> FieldResult<bool> {
+        smart_feeds::delete_smart_feed_resolver(executor.context().connection.clone(), &uuid, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field create_filter_rule(
+        &executor,
+        rule: FilterRuleInput as "Match and action of the rule",
+    ) -> FieldResult<FilterRule> as "Rule run on the new items before they reach the stream" {
+        filter_rules::create_filter_rule_resolver(executor.context().connection.clone(), rule, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field update_filter_rule(
+        &executor,
+        uuid: String as "Filter rule Uuid",
+        rule: FilterRuleInput as "Match and action of the rule",
+    ) -> FieldResult<FilterRule> {
+        filter_rules::update_filter_rule_resolver(executor.context().connection.clone(), &uuid, rule, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field delete_filter_rule(
+        &executor,
+        uuid: String as "Filter rule Uuid",
+    ) -> FieldResult<bool> {
+        filter_rules::delete_filter_rule_resolver(executor.context().connection.clone(), &uuid, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field logout(
+        &executor,
+    ) -> FieldResult<bool> as "Revokes the session of the token, its refresh token stops working" {
+        refresh_tokens::logout_resolver(executor.context().connection.clone(), &self.session_uuid, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field revoke_all_sessions(
+        &executor,
+    ) -> FieldResult<i32> as "Revokes every session of the user, this one included, returns how many were active" {
+        refresh_tokens::revoke_all_sessions_resolver(executor.context().connection.clone(), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field revoke_session(
+        &executor,
+        session_uuid: String as "Session Uuid",
+    ) -> FieldResult<bool> as "Logs out a device, its tokens stop working" {
+        sessions::revoke_session_resolver(executor.context().connection.clone(), &session_uuid, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field request_email_verification(
+        &executor,
+    ) -> FieldResult<bool> as "Mails a new verification link to the email of the user" {
+        account_tokens::request_email_verification_resolver(executor.context().connection.clone(), &config::CONFIG, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field change_password(
+        &executor,
+        current_password: String as "Password the user logged in with",
+        new_password: String as "New password",
+    ) -> FieldResult<bool> as "Changes the password, the other sessions are logged out" {
+        users_resolvers::change_password_resolver(executor.context().connection.clone(), &current_password, &new_password, &self.session_uuid, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field change_email(
+        &executor,
+        password: String as "Current password",
+        email: String as "New email",
+    ) -> FieldResult<bool> as "Mails a verification link to the new address, the email changes once it is followed" {
+        users_resolvers::change_email_resolver(executor.context().connection.clone(), &config::CONFIG, &password, &email, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field change_login(
+        &executor,
+        login: String as "New login",
+    ) -> FieldResult<User> {
+        users_resolvers::change_login_resolver(executor.context().connection.clone(), &login, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field request_data_export(
+        &executor,
+    ) -> FieldResult<DataExport> as "Archive of the profile, subscriptions, reactions and annotations, built in the background" {
+        data_export::request_data_export_resolver(executor.context().connection.clone(), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field delete_account(
+        &executor,
+        password: String as "Current password",
+    ) -> FieldResult<bool> as "Deletes the user and all its data, it can not be undone" {
+        users_resolvers::delete_account_resolver(executor.context().connection.clone(), &config::CONFIG, &password, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
 });
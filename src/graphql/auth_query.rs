@@ -1,29 +1,39 @@
 use std::error::Error;
 
 use juniper::{FieldError, FieldResult};
+use uuid::Uuid;
 
 use graphql::query::Query;
 use user::User;
+use token::AuthData;
+use sessions::{Session, sessions_resolver};
+use data_export::{DataExport, data_exports_resolver};
 use feeds;
 use feeds::Feed;
-use users_feeds::{unreaded_feeds, users_feeds_resolver, feeds_by_reaction_resolver, unreaded_feeds_by_source_resolver};
+use users_feeds::{unreaded_feeds, users_feeds_resolver, feeds_by_reaction_resolver, feeds_by_tag_resolver, unreaded_feeds_by_source_resolver, playback_position_resolver};
 use source::Source;
+use opml::export_opml_resolver;
+use filter_rules::{FilterRule, FilterRuleInput, filter_rules_resolver, preview_filter_rule_resolver};
+use smart_feeds::{SmartFeed, smart_feeds_resolver, smart_feed_feeds_resolver};
+use search::{SearchResult, SearchFilters, search_resolver};
 use users_sources::{SourceStat, unfollowed_sources_resolver, users_sources_resolver, total_my_rss_sources_resolver, sources_stats_resolver};
 
 #[derive(Debug)]
 pub struct AuthQuery {
-    pub user: User
+    pub user: User,
+    /// Session of the token the user authenticated with
+    pub session_uuid: Uuid,
 }
 
 impl AuthQuery {
-    pub fn new(user: User) -> Self {
-        AuthQuery { user }
+    pub fn new(user: User, session_uuid: Uuid) -> Self {
+        AuthQuery { user, session_uuid }
     }
 }
 
-impl From<User> for AuthQuery {
-    fn from(user: User) -> Self {
-        AuthQuery::new(user)
+impl From<(User, AuthData)> for AuthQuery {
+    fn from((user, auth): (User, AuthData)) -> Self {
+        AuthQuery::new(user, auth.session_uuid)
     }
 }
 
@@ -114,4 +124,87 @@ graphql_object!(AuthQuery: Query as "AuthQuery" |&self| {
         sources_stats_resolver(executor.context().connection.clone(), &self.user)
             .map_err(|e| FieldError::from(e.to_string()))
     }
+
+    field export_opml(
+        &executor,
+    ) -> FieldResult<String> as "Followed sources as an OPML 2.0 document" {
+        export_opml_resolver(executor.context().connection.clone(), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field playback_position(
+        &executor,
+        feed_uuid: String as "feed_uuid",
+    ) -> FieldResult<Option<i32>> as "Seconds already played of the item enclosure" {
+        playback_position_resolver(executor.context().connection.clone(), &feed_uuid, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field search(
+        &executor,
+        query: String as "Words to look for in the titles and contents",
+        filters: Option<SearchFilters> as "Source, reaction and date range",
+        limit: Option<i32> as "Limit",
+        offset: Option<i32> as "Offset",
+    ) -> FieldResult<Vec<SearchResult>> as "Feeds of the user matching the query, most relevant first" {
+        search_resolver(executor.context().connection.clone(), &query, filters, limit.unwrap_or(DEFAULT_LIMIT), offset.unwrap_or(0), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field my_smart_feeds(
+        &executor,
+    ) -> FieldResult<Vec<SmartFeed>> as "Saved searches of the user" {
+        smart_feeds_resolver(executor.context().connection.clone(), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field smart_feed_feeds(
+        &executor,
+        smart_feed_uuid: String as "Smart feed Uuid",
+        limit: Option<i32> as "Limit",
+        offset: Option<i32> as "Offset",
+    ) -> FieldResult<Vec<Feed>> as "Feeds matching a smart feed, the unread ones when it has no reactions" {
+        smart_feed_feeds_resolver(executor.context().connection.clone(), &smart_feed_uuid, limit.unwrap_or(DEFAULT_LIMIT), offset.unwrap_or(0), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field feeds_by_tag(
+        &executor,
+        tag: String as "Tag applied by a filter rule",
+        limit: Option<i32> as "Limit",
+        offset: Option<i32> as "Offset",
+    ) -> FieldResult<Vec<Feed>> {
+        feeds_by_tag_resolver(executor.context().connection.clone(), &tag, limit.unwrap_or(DEFAULT_LIMIT), offset.unwrap_or(0), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field my_filter_rules(
+        &executor,
+    ) -> FieldResult<Vec<FilterRule>> as "Rules run on the new items, in position order" {
+        filter_rules_resolver(executor.context().connection.clone(), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field preview_filter_rule(
+        &executor,
+        rule: FilterRuleInput as "Rule to try",
+        limit: Option<i32> as "Limit",
+    ) -> FieldResult<Vec<Feed>> as "Recent feeds the rule would have matched, nothing is changed" {
+        preview_filter_rule_resolver(executor.context().connection.clone(), rule, limit.unwrap_or(DEFAULT_LIMIT), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field sessions(
+        &executor,
+    ) -> FieldResult<Vec<Session>> as "Devices the user is logged in on" {
+        sessions_resolver(executor.context().connection.clone(), &self.session_uuid, &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field my_data_exports(
+        &executor,
+    ) -> FieldResult<Vec<DataExport>> as "Requested archives of the user data, the newest first" {
+        data_exports_resolver(executor.context().connection.clone(), &self.user)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
 });
@@ -4,10 +4,13 @@ use juniper::{FieldError, FieldResult};
 use config;
 use graphql::query::Query;
 use graphql::auth_mutation::AuthMutation;
+use sources_resolvers::{add_rss_source_resolver, add_twitter_source_resolver, add_mastodon_source_resolver, add_web_page_source_resolver, AddRssSource};
+use source_option::WebPageInput;
 use source::Source;
-use sources_resolvers::add_rss_source_resolver;
 use user::User;
 use users_resolvers;
+use refresh_tokens::{self, AuthTokens};
+use account_tokens;
 use errors;
 
 #[derive(Debug)]
@@ -20,10 +23,11 @@ graphql_object!(Mutation: Query as "Mutation" |&self| {
         &executor,
         login: String as "Login",
         email: String as "Email",
-        password: String as "Password"
-    ) -> FieldResult<String> as "Token" {
+        password: String as "Password",
+        device_label: Option<String> as "Name of the device, shown in the sessions"
+    ) -> FieldResult<AuthTokens> as "Tokens of a new session" {
         let user = User::new_secure(login, email, password)?;
-        users_resolvers::signup_resolver(&executor.context().diesel_pool.clone(), &config::CONFIG, user)
+        users_resolvers::signup_resolver(&executor.context().diesel_pool.clone(), executor.context().connection.clone(), &config::CONFIG, &executor.context().client, user, device_label)
             .map_err(|e| FieldError::from(&e.description().to_string()))
     }
 
@@ -31,16 +35,76 @@ graphql_object!(Mutation: Query as "Mutation" |&self| {
         &executor,
         token: String as "Auth token"
     ) -> FieldResult<AuthMutation> as "Auth" {
-        users_resolvers::auth_resolver(&executor.context().diesel_pool.clone(), &config::CONFIG, token)
+        users_resolvers::auth_resolver(&executor.context().diesel_pool.clone(), executor.context().connection.clone(), &config::CONFIG, &executor.context().client, token)
             .map_err(|e| errors::ErrorKind::WrongCredentials)
             .map_err(|e| FieldError::from(&e.description().to_string()))
     }
 
+    field refresh_token(
+        &executor,
+        refresh_token: String as "Refresh token of the session, it can only be used once"
+    ) -> FieldResult<AuthTokens> as "New tokens of the same session" {
+        refresh_tokens::refresh_token_resolver(executor.context().connection.clone(), &config::CONFIG, &refresh_token)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field verify_email(
+        &executor,
+        token: String as "Token of the verification mail"
+    ) -> FieldResult<bool> as "Confirms the address the verification mail was sent to" {
+        account_tokens::verify_email_resolver(executor.context().connection.clone(), &token)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field request_password_reset(
+        &executor,
+        email: String as "Email of the account"
+    ) -> FieldResult<bool> as "Mails a password reset link, succeeds for unknown addresses too" {
+        account_tokens::request_password_reset_resolver(executor.context().connection.clone(), &config::CONFIG, &email)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
+    field reset_password(
+        &executor,
+        token: String as "Token of the password reset mail",
+        password: String as "New password"
+    ) -> FieldResult<bool> as "Sets the new password, every session is logged out" {
+        account_tokens::reset_password_resolver(executor.context().connection.clone(), &token, password)
+            .map_err(|e| FieldError::from(e.to_string()))
+    }
+
     field add_rss_source(
         &executor,
-        xml_url: String as "xml_url",
-    ) -> FieldResult<Source> {
+        xml_url: String as "Feed url, or a website url to discover its feeds",
+    ) -> FieldResult<AddRssSource> {
         add_rss_source_resolver(executor.context().connection.clone(), &xml_url)
             .map_err(|e| FieldError::from(&e.description().to_string()))
     }
+
+    field add_twitter_source(
+        &executor,
+        hashtag: Option<String> as "Hashtag to follow",
+        username: Option<String> as "User whose timeline to follow",
+    ) -> FieldResult<Source> {
+        add_twitter_source_resolver(executor.context().connection.clone(), hashtag, username)
+            .map_err(|e| FieldError::from(&e.description().to_string()))
+    }
+
+    field add_mastodon_source(
+        &executor,
+        account: Option<String> as "Account to follow, as user@instance",
+        hashtag: Option<String> as "Hashtag to follow",
+        instance_url: Option<String> as "Instance the timeline is read from, required for hashtags",
+    ) -> FieldResult<Source> {
+        add_mastodon_source_resolver(executor.context().connection.clone(), account, hashtag, instance_url)
+            .map_err(|e| FieldError::from(&e.description().to_string()))
+    }
+
+    field add_web_page_source(
+        &executor,
+        web_page: WebPageInput as "Page without feed and the CSS selectors of its items",
+    ) -> FieldResult<Source> {
+        add_web_page_source_resolver(executor.context().connection.clone(), web_page)
+            .map_err(|e| FieldError::from(&e.description().to_string()))
+    }
 });
@@ -13,15 +13,28 @@ use graphql::mutation::Mutation;
 use source::Source;
 use sources::find_sources_resolver;
 use users_resolvers;
+use refresh_tokens::AuthTokens;
+use sessions::ClientInfo;
 
 pub struct Query {
     pub connection: Pool<PostgresConnectionManager>,
     pub diesel_pool: Pool<ConnectionManager<PgConnection>>,
+    /// Client of the request being executed, recorded on the sessions
+    pub client: ClientInfo,
 }
 
 impl Query {
     pub fn new(connection: Pool<PostgresConnectionManager>, diesel_pool: Pool<ConnectionManager<PgConnection>>) -> Self {
-        Query { connection, diesel_pool }
+        Query { connection, diesel_pool, client: ClientInfo::default() }
+    }
+
+    /// Context of one request, the pools are shared
+    pub fn with_client(&self, client: ClientInfo) -> Self {
+        Query {
+            connection: self.connection.clone(),
+            diesel_pool: self.diesel_pool.clone(),
+            client,
+        }
     }
 }
 
@@ -38,7 +51,7 @@ graphql_object!(Query: Query as "Query" |&self| {
         &executor,
         token: String as "Auth token"
     ) -> FieldResult<AuthQuery> as "Auth" {
-        users_resolvers::auth_resolver(&executor.context().diesel_pool.clone(), &config::CONFIG, token)
+        users_resolvers::auth_resolver(&executor.context().diesel_pool.clone(), executor.context().connection.clone(), &config::CONFIG, &executor.context().client, token)
             .map_err(|e| errors::ErrorKind::WrongCredentials)
             .map_err(|e| FieldError::from(&e.description().to_string()))
     }
@@ -46,9 +59,10 @@ graphql_object!(Query: Query as "Query" |&self| {
     field login(
         &executor,
         email: String as "Email",
-        password: String as "Password"
-    ) -> FieldResult<String> as "Token" {
-        users_resolvers::login_resolver(&executor.context().diesel_pool.clone(), &config::CONFIG, email, password)
+        password: String as "Password",
+        device_label: Option<String> as "Name of the device, shown in the sessions"
+    ) -> FieldResult<AuthTokens> as "Tokens of a new session" {
+        users_resolvers::login_resolver(&executor.context().diesel_pool.clone(), executor.context().connection.clone(), &config::CONFIG, &executor.context().client, email, password, device_label)
             .map_err(|e| FieldError::from(&e.description().to_string()))
     }
 
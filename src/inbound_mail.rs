@@ -0,0 +1,367 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration as StdDuration, Instant};
+use mailparse::{self, MailHeaderMap};
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+
+use errors::*;
+use pg::PgDatabase;
+use source::Source;
+use email::{deliver, find_inbound_source};
+
+/// Larger messages are refused, newsletters are far below it
+const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+/// RFC 5321 limit of a command or text line, CRLF included
+const MAX_LINE_LENGTH: usize = 1000;
+/// Idle clients are dropped instead of holding a thread and a db connection forever
+const SESSION_TIMEOUT_SECONDS: u64 = 60;
+/// Sessions served at once, each one holds a db connection
+pub const MAX_SESSIONS: usize = 8;
+/// Headers an MTA writing into a maildir leaves the envelope recipient in
+const RECIPIENT_HEADERS: [&str; 3] = ["Delivered-To", "X-Original-To", "To"];
+
+/// Address of a `MAIL FROM:<...>` or `RCPT TO:<...>` argument
+fn path_address(argument: &str) -> Option<String> {
+    let address = argument.splitn(2, ':').nth(1)?.trim();
+    let address = address.split_whitespace().next().unwrap_or("");
+    Some(address.trim_matches(|c| c == '<' || c == '>').to_lowercase())
+}
+
+fn reply(stream: &mut TcpStream, line: &str) -> Result<()> {
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\r\n")?;
+    Ok(())
+}
+
+enum Line {
+    Complete,
+    TooLong,
+    Closed,
+}
+
+/// Reads up to the next `\n`, or MAX_LINE_LENGTH bytes of a longer line
+fn read_line(reader: &mut BufRead, line: &mut Vec<u8>) -> Result<Line> {
+    line.clear();
+    let read = Read::take(&mut *reader, MAX_LINE_LENGTH as u64).read_until(b'\n', line)?;
+    Ok(if line.ends_with(b"\n") {
+        Line::Complete
+    } else if read == MAX_LINE_LENGTH {
+        Line::TooLong
+    } else {
+        Line::Closed
+    })
+}
+
+enum Data {
+    Message(Vec<u8>),
+    /// Reply explaining why the message is not accepted
+    Refused(&'static str),
+}
+
+/// Reads the DATA lines up to the lone dot, the whole message is read even when it is refused
+fn read_data(reader: &mut BufRead) -> Result<Data> {
+    let mut message = Vec::new();
+    let mut refused = None;
+    let mut in_long_line = false;
+    let mut line = Vec::new();
+    loop {
+        match read_line(reader, &mut line)? {
+            Line::Closed => bail!("connection closed during DATA"),
+            Line::TooLong => {
+                in_long_line = true;
+                refused = Some("500 Line too long");
+                continue;
+            },
+            Line::Complete if in_long_line => {
+                in_long_line = false;
+                continue;
+            },
+            Line::Complete => (),
+        }
+        if line == b".\r\n" || line == b".\n" {
+            break;
+        }
+        let line = if line.starts_with(b"..") { &line[1..] } else { &line[..] };
+        if message.len() + line.len() > MAX_MESSAGE_SIZE {
+            refused = refused.or(Some("552 Message too large"));
+        } else if refused.is_none() {
+            message.extend_from_slice(line);
+        }
+    }
+    Ok(match refused {
+        Some(reply) => Data::Refused(reply),
+        None => Data::Message(message),
+    })
+}
+
+/// One SMTP session, every accepted recipient is an email source of the message
+fn handle_session(stream: TcpStream, domain: &str, pg: &PgDatabase) -> Result<()> {
+    stream.set_read_timeout(Some(StdDuration::from_secs(SESSION_TIMEOUT_SECONDS)))?;
+    stream.set_write_timeout(Some(StdDuration::from_secs(SESSION_TIMEOUT_SECONDS)))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut recipients: Vec<Source> = Vec::new();
+    let mut raw_line = Vec::new();
+    reply(&mut writer, &format!("220 {} ESMTP mindstream", domain))?;
+    loop {
+        match read_line(&mut reader, &mut raw_line)? {
+            Line::Complete => (),
+            Line::TooLong => return reply(&mut writer, "500 Line too long"),
+            Line::Closed => return Ok(()),
+        }
+        let line = String::from_utf8_lossy(&raw_line).trim_right().to_owned();
+        let command = line.split_whitespace().next().unwrap_or("").to_uppercase();
+        match command.as_str() {
+            "HELO" => reply(&mut writer, &format!("250 {}", domain))?,
+            "EHLO" => {
+                reply(&mut writer, &format!("250-{}", domain))?;
+                reply(&mut writer, &format!("250-SIZE {}", MAX_MESSAGE_SIZE))?;
+                reply(&mut writer, "250 8BITMIME")?;
+            },
+            "MAIL" => {
+                recipients.clear();
+                reply(&mut writer, "250 OK")?;
+            },
+            "RCPT" => {
+                let source = match path_address(&line) {
+                    Some(address) => find_inbound_source(pg, &address)?,
+                    None => None,
+                };
+                match source {
+                    Some(source) => {
+                        recipients.push(source);
+                        reply(&mut writer, "250 OK")?;
+                    },
+                    None => reply(&mut writer, "550 No such mailbox")?,
+                }
+            },
+            "DATA" => {
+                if recipients.is_empty() {
+                    reply(&mut writer, "554 No valid recipients")?;
+                    continue;
+                }
+                reply(&mut writer, "354 End data with <CR><LF>.<CR><LF>")?;
+                match read_data(&mut reader)? {
+                    Data::Message(message) => {
+                        let mut delivered = true;
+                        for source in &recipients {
+                            if let Err(error) = deliver(pg, source, &message) {
+                                println!("inbound mail {:?} error {}", source.uuid, error);
+                                delivered = false;
+                            }
+                        }
+                        if delivered {
+                            reply(&mut writer, "250 OK")?;
+                        } else {
+                            reply(&mut writer, "451 Message not stored")?;
+                        }
+                    },
+                    Data::Refused(refusal) => reply(&mut writer, refusal)?,
+                }
+                recipients.clear();
+            },
+            "RSET" => {
+                recipients.clear();
+                reply(&mut writer, "250 OK")?;
+            },
+            "NOOP" => reply(&mut writer, "250 OK")?,
+            "QUIT" => {
+                reply(&mut writer, "221 Bye")?;
+                return Ok(());
+            },
+            _ => reply(&mut writer, "502 Command not implemented")?,
+        }
+    }
+}
+
+/// Counts a running session until it is dropped
+struct SessionSlot(Arc<AtomicUsize>);
+
+impl SessionSlot {
+    fn acquire(sessions: &Arc<AtomicUsize>) -> Option<SessionSlot> {
+        if sessions.fetch_add(1, Ordering::SeqCst) >= MAX_SESSIONS {
+            sessions.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(SessionSlot(sessions.clone()))
+    }
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Receives the newsletters sent to the inbound addresses, one thread per connection up to MAX_SESSIONS
+pub fn run_smtp_listener(addr: String, domain: String, pool: Pool<PostgresConnectionManager>) {
+    thread::spawn(move || {
+        let listener = match TcpListener::bind(&addr) {
+            Ok(listener) => listener,
+            Err(err) => {
+                println!("smtp listener {} error {:?}", addr, err);
+                return;
+            }
+        };
+        let sessions = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    let slot = match SessionSlot::acquire(&sessions) {
+                        Some(slot) => slot,
+                        None => {
+                            let _ = reply(&mut stream, "421 Too many connections, try again later");
+                            continue;
+                        },
+                    };
+                    let domain = domain.clone();
+                    let pool = pool.clone();
+                    thread::spawn(move || {
+                        let _slot = slot;
+                        let session = PgDatabase::from_pool(pool)
+                            .and_then(|pg| handle_session(stream, &domain, &pg));
+                        if let Err(err) = session {
+                            println!("smtp session error {:?}", err);
+                        }
+                    });
+                },
+                Err(err) => println!("smtp accept error {:?}", err),
+            }
+        }
+    });
+}
+
+fn maildir_recipients(raw: &[u8]) -> Result<Vec<String>> {
+    let (headers, _) = mailparse::parse_headers(raw)?;
+    let mut recipients = Vec::new();
+    for name in RECIPIENT_HEADERS.iter() {
+        for value in headers.get_all_values(name)? {
+            for address in value.split(',') {
+                let address = address.rsplit('<').next().unwrap_or("").trim().trim_right_matches('>').to_lowercase();
+                if !address.is_empty() && !recipients.contains(&address) {
+                    recipients.push(address);
+                }
+            }
+        }
+    }
+    Ok(recipients)
+}
+
+fn deliver_maildir_message(pg: &PgDatabase, path: &Path) -> Result<()> {
+    let mut raw = Vec::new();
+    fs::File::open(path)?.read_to_end(&mut raw)?;
+    for address in maildir_recipients(&raw)? {
+        if let Some(source) = find_inbound_source(pg, &address)? {
+            deliver(pg, &source, &raw)?;
+        }
+    }
+    Ok(())
+}
+
+/// Delivers the messages of `new/` and moves them to `cur/`, like a mail client reading them.
+/// A message that fails is moved to `failed/` so it is not retried every interval, it can be moved back to `new/` by hand
+fn process_maildir(dir: &Path, pool: &Pool<PostgresConnectionManager>) -> Result<()> {
+    let pg = PgDatabase::from_pool(pool.clone())?;
+    fs::create_dir_all(dir.join("cur"))?;
+    fs::create_dir_all(dir.join("failed"))?;
+    for entry in fs::read_dir(dir.join("new"))? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let destination = match deliver_maildir_message(&pg, &path) {
+            Ok(_) => "cur",
+            Err(error) => {
+                println!("maildir {:?} error {}", path, error);
+                "failed"
+            },
+        };
+        if let Some(name) = path.file_name() {
+            fs::rename(&path, dir.join(destination).join(name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Watches a maildir for local testing or behind an MTA doing the SMTP part
+pub fn run_maildir_job(dir: String, interval: StdDuration, pool: Pool<PostgresConnectionManager>) {
+    thread::spawn(move || {
+        loop {
+            let started = Instant::now();
+            if let Err(err) = process_maildir(Path::new(&dir), &pool) {
+                println!("process_maildir error {:?}", err);
+            }
+            let elapsed = started.elapsed();
+            if elapsed < interval {
+                thread::sleep(interval - elapsed);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn data(input: &str) -> Data {
+        read_data(&mut Cursor::new(input.as_bytes().to_vec())).unwrap()
+    }
+
+    #[test]
+    fn data_stops_at_the_lone_dot() {
+        match data("Subject: hi\r\n\r\n..dot\r\n.\r\nQUIT\r\n") {
+            Data::Message(message) => assert_eq!(message, b"Subject: hi\r\n\r\n.dot\r\n".to_vec()),
+            Data::Refused(reply) => panic!("refused {}", reply),
+        }
+    }
+
+    #[test]
+    fn long_lines_are_refused_without_ending_the_data() {
+        let input = format!("{}.\r\nbody\r\n.\r\n", "a".repeat(3 * MAX_LINE_LENGTH));
+        let mut reader = Cursor::new(input.into_bytes());
+        match read_data(&mut reader).unwrap() {
+            Data::Refused(reply) => assert_eq!(reply, "500 Line too long"),
+            Data::Message(_) => panic!("long line accepted"),
+        }
+        assert_eq!(reader.position() as usize, reader.get_ref().len());
+    }
+
+    #[test]
+    fn closed_connection_during_data_fails() {
+        assert!(read_data(&mut Cursor::new(b"body\r\n".to_vec())).is_err());
+    }
+
+    #[test]
+    fn command_lines_are_capped() {
+        let mut line = Vec::new();
+        let mut reader = Cursor::new(format!("{}\r\n", "x".repeat(MAX_LINE_LENGTH)).into_bytes());
+        match read_line(&mut reader, &mut line).unwrap() {
+            Line::TooLong => assert_eq!(line.len(), MAX_LINE_LENGTH),
+            _ => panic!("line not capped"),
+        }
+    }
+
+    #[test]
+    fn sessions_are_capped() {
+        let sessions = Arc::new(AtomicUsize::new(0));
+        let slots: Vec<SessionSlot> = (0..MAX_SESSIONS).filter_map(|_| SessionSlot::acquire(&sessions)).collect();
+        assert_eq!(slots.len(), MAX_SESSIONS);
+        assert!(SessionSlot::acquire(&sessions).is_none());
+        drop(slots);
+        assert_eq!(sessions.load(Ordering::SeqCst), 0);
+        assert!(SessionSlot::acquire(&sessions).is_some());
+    }
+
+    #[test]
+    fn path_address_is_lowercased() {
+        assert_eq!(path_address("TO:<News@In.Example.com> SIZE=100"), Some("news@in.example.com".to_owned()));
+        assert_eq!(path_address("TO"), None);
+    }
+}
@@ -0,0 +1,239 @@
+use chrono::prelude::*;
+use chrono::{DateTime, NaiveDateTime};
+use serde_json::{self, Value};
+
+use errors::*;
+use feeds::{Rss, item_fingerprint};
+use feed_meta::FeedMeta;
+use podcast::{Enclosure, PodcastEpisode};
+use rss::{Channel, ChannelItem};
+
+const VERSION_PREFIX: &str = "https://jsonfeed.org/version/";
+
+#[derive(Debug, Deserialize)]
+struct JsonFeed {
+    version: String,
+    title: Option<String>,
+    home_page_url: Option<String>,
+    feed_url: Option<String>,
+    #[serde(default)]
+    hubs: Vec<JsonFeedHub>,
+    #[serde(default)]
+    authors: Vec<JsonFeedAuthor>,
+    /// JSON Feed 1.0, replaced by `authors` in 1.1
+    author: Option<JsonFeedAuthor>,
+    #[serde(default)]
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedHub {
+    #[serde(rename = "type")]
+    hub_type: String,
+    url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct JsonFeedAuthor {
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: Option<String>,
+    title: Option<String>,
+    size_in_bytes: Option<f64>,
+    duration_in_seconds: Option<f64>,
+}
+
+impl From<JsonFeedAttachment> for Enclosure {
+    fn from(attachment: JsonFeedAttachment) -> Self {
+        Enclosure {
+            url: attachment.url,
+            mime_type: attachment.mime_type,
+            length: attachment.size_in_bytes,
+            duration: attachment.duration_in_seconds.map(|duration| duration as i32),
+            title: attachment.title,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonFeedItem {
+    /// A string by the spec, some publishers send numbers
+    id: Value,
+    url: Option<String>,
+    external_url: Option<String>,
+    title: Option<String>,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    summary: Option<String>,
+    image: Option<String>,
+    date_published: Option<String>,
+    date_modified: Option<String>,
+    #[serde(default)]
+    authors: Vec<JsonFeedAuthor>,
+    author: Option<JsonFeedAuthor>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    attachments: Vec<JsonFeedAttachment>,
+}
+
+/// JSON Feed documents are recognized by their `version` url
+pub fn is_json_feed(document: &str) -> bool {
+    document.trim_left().starts_with('{') && document.contains(VERSION_PREFIX)
+}
+
+fn parse_date(date: &Option<String>) -> Option<NaiveDateTime> {
+    date.as_ref()
+        .and_then(|date| DateTime::parse_from_rfc3339(date.trim()).ok())
+        .map(|date| date.naive_utc())
+}
+
+fn author_names(authors: &Vec<JsonFeedAuthor>) -> Option<String> {
+    let names: Vec<String> = authors.iter().filter_map(|author| author.name.clone()).collect();
+    if names.is_empty() { None } else { Some(names.join(", ")) }
+}
+
+/// Maps an item on the `Rss` shape of xml entries, items fall back on the feed authors
+fn to_channel_item(item: JsonFeedItem, feed_authors: &Vec<JsonFeedAuthor>) -> Option<ChannelItem> {
+    let id = match item.id {
+        Value::String(ref id) => id.clone(),
+        Value::Number(ref id) => id.to_string(),
+        _ => return None,
+    };
+    let link = item.url.clone().or_else(|| item.external_url.clone())?;
+    let mut authors = item.authors.clone();
+    authors.extend(item.author.clone());
+    if authors.is_empty() {
+        authors = feed_authors.clone();
+    }
+    let published = parse_date(&item.date_published)
+        .or_else(|| parse_date(&item.date_modified))
+        .unwrap_or_else(|| Utc::now().naive_utc());
+    let content = item.content_html.clone().or_else(|| item.content_text.clone());
+    let enclosures: Vec<Enclosure> = item.attachments.into_iter().map(Enclosure::from).collect();
+    let podcast = if enclosures.is_empty() || item.image.is_none() {
+        None
+    } else {
+        Some(PodcastEpisode { image: item.image.clone(), ..PodcastEpisode::default() })
+    };
+    let rss = Rss {
+        fingerprint: item_fingerprint(&id, &item.title, &content),
+        id,
+        title: item.title,
+        content,
+        summary: item.summary,
+        author: author_names(&authors),
+        published: published.to_string(),
+        updated: parse_date(&item.date_modified).map(|updated| updated.to_string()),
+        alternate: Some(link.clone()),
+        keywords: item.tags,
+        enclosure: enclosures.first().map(|enclosure| enclosure.url.clone()),
+        enclosures,
+        podcast,
+    };
+    Some(ChannelItem { links: vec![link], published, rss })
+}
+
+/// Parses a JSON Feed 1.0 or 1.1 document, with the hub it advertises for WebSub
+pub fn parse_json_feed(document: &str) -> Result<(Channel, FeedMeta)> {
+    let feed: JsonFeed = serde_json::from_str(document).map_err(|_| ErrorKind::InvalidFeed)?;
+    if !feed.version.starts_with(VERSION_PREFIX) {
+        bail!(ErrorKind::InvalidFeed);
+    }
+    let mut feed_authors = feed.authors;
+    feed_authors.extend(feed.author);
+    let meta = FeedMeta {
+        hub: feed.hubs.iter()
+            .find(|hub| hub.hub_type.eq_ignore_ascii_case("websub"))
+            .map(|hub| hub.url.clone()),
+        self_url: feed.feed_url,
+        ..FeedMeta::default()
+    };
+    let channel = Channel {
+        title: feed.title,
+        website: feed.home_page_url,
+        items: feed.items.into_iter().filter_map(|item| to_channel_item(item, &feed_authors)).collect(),
+    };
+    Ok((channel, meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = r#"{
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": "Example",
+        "home_page_url": "https://example.com/",
+        "feed_url": "https://example.com/feed.json",
+        "hubs": [{ "type": "rssCloud", "url": "https://cloud.example" }, { "type": "WebSub", "url": "https://hub.example" }],
+        "authors": [{ "name": "Feed Author" }],
+        "items": [
+            {
+                "id": "first",
+                "url": "https://example.com/first",
+                "title": "First",
+                "content_html": "<p>Hello</p>",
+                "content_text": "Hello",
+                "date_published": "2018-04-01T10:00:00+02:00",
+                "authors": [{ "name": "Jane" }, { "name": "John" }],
+                "tags": ["rust"],
+                "image": "https://example.com/first.jpg",
+                "attachments": [{ "url": "https://example.com/first.mp3", "mime_type": "audio/mpeg", "size_in_bytes": 1024, "duration_in_seconds": 61.5 }]
+            },
+            { "id": 2, "external_url": "https://elsewhere.example/second", "content_text": "Plain" },
+            { "id": "no-link", "title": "Dropped" },
+            { "id": null, "url": "https://example.com/null" }
+        ]
+    }"#;
+
+    #[test]
+    fn recognizes_json_feeds() {
+        assert!(is_json_feed(FEED));
+        assert!(!is_json_feed("<rss version=\"2.0\"></rss>"));
+        assert!(!is_json_feed(r#"{ "version": "1.0" }"#));
+    }
+
+    #[test]
+    fn parses_feed_and_meta() {
+        let (channel, meta) = parse_json_feed(FEED).unwrap();
+        assert_eq!(channel.title, Some("Example".to_owned()));
+        assert_eq!(channel.website, Some("https://example.com/".to_owned()));
+        assert_eq!(meta.hub, Some("https://hub.example".to_owned()));
+        assert_eq!(meta.self_url, Some("https://example.com/feed.json".to_owned()));
+        assert_eq!(channel.items.len(), 2);
+    }
+
+    #[test]
+    fn maps_items_on_rss() {
+        let (channel, _) = parse_json_feed(FEED).unwrap();
+        let first = &channel.items[0];
+        assert_eq!(first.links, vec!["https://example.com/first".to_owned()]);
+        assert_eq!(first.published, NaiveDate::from_ymd(2018, 4, 1).and_hms(8, 0, 0));
+        assert_eq!(first.rss.id, "first");
+        assert_eq!(first.rss.content, Some("<p>Hello</p>".to_owned()));
+        assert_eq!(first.rss.author, Some("Jane, John".to_owned()));
+        assert_eq!(first.rss.keywords, vec!["rust".to_owned()]);
+        assert_eq!(first.rss.enclosure, Some("https://example.com/first.mp3".to_owned()));
+        assert_eq!(first.rss.enclosures[0].duration, Some(61));
+        assert_eq!(first.rss.enclosures[0].length, Some(1024.0));
+        assert_eq!(first.rss.podcast.as_ref().and_then(|podcast| podcast.image.clone()), Some("https://example.com/first.jpg".to_owned()));
+
+        let second = &channel.items[1];
+        assert_eq!(second.rss.id, "2");
+        assert_eq!(second.rss.alternate, Some("https://elsewhere.example/second".to_owned()));
+        assert_eq!(second.rss.content, Some("Plain".to_owned()));
+        assert_eq!(second.rss.author, Some("Feed Author".to_owned()));
+        assert!(second.rss.podcast.is_none());
+    }
+
+    #[test]
+    fn refuses_other_documents() {
+        assert!(parse_json_feed(r#"{ "version": "https://example.com/version/1", "items": [] }"#).is_err());
+        assert!(parse_json_feed("not json").is_err());
+    }
+}
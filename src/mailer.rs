@@ -0,0 +1,210 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration as StdDuration;
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use errors::*;
+use config::{Config, CONFIG};
+
+const SMTP_TIMEOUT_SECONDS: u64 = 30;
+
+/// Plain text mail sent to a user
+#[derive(Debug, Clone)]
+pub struct OutgoingMail {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+}
+
+/// Header values come from users, a line break would add headers
+fn header_value(value: &str) -> String {
+    value.replace(|c| c == '\r' || c == '\n', " ")
+}
+
+fn domain_of(address: &str) -> &str {
+    address.rsplit('@').next().unwrap_or("localhost")
+}
+
+impl OutgoingMail {
+    /// RFC 5322 message with CRLF line endings, the text is sent as 8bit UTF-8
+    pub fn to_message(&self, from: &str) -> String {
+        let mut message = String::new();
+        message.push_str(&format!("From: {}\r\n", header_value(from)));
+        message.push_str(&format!("To: {}\r\n", header_value(&self.to)));
+        message.push_str(&format!("Subject: {}\r\n", header_value(&self.subject)));
+        message.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+        message.push_str(&format!("Message-ID: <{}@{}>\r\n", Uuid::new_v4().simple(), domain_of(from)));
+        message.push_str("MIME-Version: 1.0\r\n");
+        message.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+        message.push_str("Content-Transfer-Encoding: 8bit\r\n");
+        message.push_str("\r\n");
+        for line in self.text.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message
+    }
+}
+
+pub trait Mailer {
+    fn send(&self, mail: &OutgoingMail) -> Result<()>;
+}
+
+/// Hands the mails to an SMTP relay accepting them from this host without authentication, like a local MTA
+pub struct SmtpMailer {
+    relay_addr: String,
+    from: String,
+}
+
+/// Reads a possibly multiline reply, fails when its code is not the expected one
+fn expect_reply(reader: &mut BufRead, expected: &str) -> Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("smtp connection closed");
+        }
+        if !line.starts_with(expected) {
+            bail!("unexpected smtp reply: {}", line.trim_right());
+        }
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+fn command(stream: &mut TcpStream, reader: &mut BufRead, line: &str, expected: &str) -> Result<()> {
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\r\n")?;
+    expect_reply(reader, expected)
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: &OutgoingMail) -> Result<()> {
+        let mut stream = TcpStream::connect(self.relay_addr.as_str())?;
+        stream.set_read_timeout(Some(StdDuration::from_secs(SMTP_TIMEOUT_SECONDS)))?;
+        stream.set_write_timeout(Some(StdDuration::from_secs(SMTP_TIMEOUT_SECONDS)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        expect_reply(&mut reader, "220")?;
+        command(&mut stream, &mut reader, &format!("EHLO {}", domain_of(&self.from)), "250")?;
+        command(&mut stream, &mut reader, &format!("MAIL FROM:<{}>", self.from), "250")?;
+        command(&mut stream, &mut reader, &format!("RCPT TO:<{}>", header_value(&mail.to)), "25")?;
+        command(&mut stream, &mut reader, "DATA", "354")?;
+        for line in mail.to_message(&self.from).lines() {
+            if line.starts_with('.') {
+                stream.write_all(b".")?;
+            }
+            stream.write_all(line.as_bytes())?;
+            stream.write_all(b"\r\n")?;
+        }
+        command(&mut stream, &mut reader, ".", "250")?;
+        command(&mut stream, &mut reader, "QUIT", "221")
+    }
+}
+
+/// Writes every mail as an .eml file of a directory, for local testing
+pub struct FileMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: &OutgoingMail) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let name = format!("{}-{}.eml", Utc::now().format("%Y%m%d%H%M%S"), Uuid::new_v4().simple());
+        let mut file = fs::File::create(self.dir.join(name))?;
+        file.write_all(mail.to_message(&self.from).as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Prints the mails, only when MAIL_TRANSPORT=log, verification and reset links would be lost otherwise
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, mail: &OutgoingMail) -> Result<()> {
+        println!("mail to {} subject {}\n{}", mail.to, mail.subject, mail.text);
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Transport {
+    Smtp(String),
+    File(String),
+    Log,
+}
+
+/// MAIL_TRANSPORT when set, else SMTP when a relay is configured, else the outbox directory
+fn transport(mail_transport: Option<&str>, relay_addr: Option<&String>, outbox_dir: Option<&String>) -> Result<Transport> {
+    match (mail_transport, relay_addr, outbox_dir) {
+        (Some("log"), _, _) => Ok(Transport::Log),
+        (Some("smtp"), Some(relay_addr), _) | (None, Some(relay_addr), _) => Ok(Transport::Smtp(relay_addr.clone())),
+        (Some("file"), _, Some(dir)) | (None, None, Some(dir)) => Ok(Transport::File(dir.clone())),
+        (Some("smtp"), None, _) => bail!("MAIL_TRANSPORT=smtp needs SMTP_RELAY_ADDR"),
+        (Some("file"), _, None) => bail!("MAIL_TRANSPORT=file needs MAIL_OUTBOX_DIR"),
+        (Some(other), _, _) => bail!("unknown MAIL_TRANSPORT {}, expected smtp, file or log", other),
+        (None, None, None) => bail!("no mail transport, set SMTP_RELAY_ADDR, MAIL_OUTBOX_DIR or MAIL_TRANSPORT=log"),
+    }
+}
+
+/// Checked at startup, the server must not run with account mails going nowhere
+pub fn from_config(config: &Config) -> Result<Box<Mailer>> {
+    let transport = transport(
+        config.mail_transport.as_ref().map(|transport| transport.as_str()),
+        config.smtp_relay_addr.as_ref(),
+        config.mail_outbox_dir.as_ref(),
+    )?;
+    Ok(match transport {
+        Transport::Smtp(relay_addr) => Box::new(SmtpMailer { relay_addr, from: config.mail_from.clone() }),
+        Transport::File(dir) => Box::new(FileMailer { dir: PathBuf::from(dir), from: config.mail_from.clone() }),
+        Transport::Log => Box::new(LogMailer),
+    })
+}
+
+/// Sends without making the request wait on the relay, failures are only logged
+pub fn send_in_background(mail: OutgoingMail) {
+    thread::spawn(move || {
+        if let Err(error) = from_config(&CONFIG).and_then(|mailer| mailer.send(&mail)) {
+            println!("mail to {} error {}", mail.to, error);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_owned())
+    }
+
+    #[test]
+    fn relay_then_outbox_are_deduced() {
+        assert_eq!(transport(None, some("localhost:25").as_ref(), some("/tmp").as_ref()).unwrap(), Transport::Smtp("localhost:25".to_owned()));
+        assert_eq!(transport(None, None, some("/tmp").as_ref()).unwrap(), Transport::File("/tmp".to_owned()));
+    }
+
+    #[test]
+    fn explicit_transport_wins() {
+        assert_eq!(transport(Some("log"), some("localhost:25").as_ref(), None).unwrap(), Transport::Log);
+        assert_eq!(transport(Some("file"), some("localhost:25").as_ref(), some("/tmp").as_ref()).unwrap(), Transport::File("/tmp".to_owned()));
+    }
+
+    #[test]
+    fn missing_transport_fails() {
+        assert!(transport(None, None, None).is_err());
+        assert!(transport(Some("smtp"), None, some("/tmp").as_ref()).is_err());
+        assert!(transport(Some("file"), some("localhost:25").as_ref(), None).is_err());
+        assert!(transport(Some("pigeon"), None, None).is_err());
+    }
+
+    #[test]
+    fn header_values_stay_on_one_line() {
+        let mail = OutgoingMail { to: "a@example.com\r\nBcc: b@example.com".to_owned(), subject: "s".to_owned(), text: "t".to_owned() };
+        assert!(mail.to_message("from@example.com").contains("To: a@example.com  Bcc: b@example.com\r\n"));
+    }
+}
@@ -38,6 +38,12 @@ extern crate hyper;
 extern crate reqwest;
 extern crate rocket;
 extern crate url;
+extern crate quick_xml;
+extern crate scraper;
+extern crate mailparse;
+extern crate whatlang;
+extern crate regex;
+extern crate zip;
 #[macro_use]
 extern crate juniper;
 #[macro_use]
@@ -50,6 +56,11 @@ extern crate validator;
 mod errors;
 mod pg;
 mod token;
+mod refresh_tokens;
+mod sessions;
+mod mailer;
+mod account_tokens;
+mod data_export;
 mod graphql;
 mod config;
 mod schema;
@@ -69,7 +80,27 @@ mod sources_resolvers;
 mod users_feeds; 
 mod users_sources; 
 mod mercury; 
+mod readability;
+mod extractors;
+mod extraction_jobs;
 mod rss; 
+mod worker_pool;
+mod feed_meta;
+mod json_feed;
+mod podcast;
+mod schedule;
+mod websub;
+mod twitter;
+mod mastodon;
+mod web_page;
+mod email;
+mod inbound_mail;
+mod webhook;
+mod search;
+mod smart_feeds;
+mod filter_rules;
+mod feed_discovery;
+mod opml;
 
 embed_migrations!("migrations");
 
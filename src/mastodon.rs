@@ -0,0 +1,295 @@
+use reqwest::Client;
+use url::Url;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use errors::*;
+use pg::PgDatabase;
+use feeds::{is_feed_exist, insert_feed, Feed};
+use source::Source;
+use source_option::MastodonSource;
+use sources::{newest_id, update_mastodon_source, record_fetch_success, record_fetch_failure};
+use user::User;
+use rss::{insert_subscribers_feeds, error_status};
+use filter_rules::{SubscriberRules, find_enabled_rules};
+use schedule::DEFAULT_FETCH_INTERVAL;
+use readability::{clean_fragment, escape_text, safe_url, text_of};
+
+/// Statuses asked per request, the most the timeline endpoints return
+const LIMIT: &str = "40";
+
+#[derive(GraphQLObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TootAccount {
+    /// `user@instance` handle, only `user` for accounts of the instance itself
+    pub acct: String,
+    pub display_name: String,
+    pub url: String,
+    pub avatar: Option<String>,
+}
+
+#[derive(GraphQLObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TootMedia {
+    /// image, gifv, video or audio
+    pub media_type: String,
+    pub url: String,
+    pub preview_url: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Status as stored in `feeds.mastodon`, a boost is stored as the boosted status with `boosted_by` set
+#[derive(GraphQLObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Toot {
+    pub id: String,
+    pub url: String,
+    pub created_at: String,
+    /// Sanitized html content, to be hidden behind `spoiler_text` when there is one
+    pub content: String,
+    /// Content warning
+    pub spoiler_text: Option<String>,
+    pub sensitive: bool,
+    pub language: Option<String>,
+    pub account: TootAccount,
+    pub boosted_by: Option<TootAccount>,
+    pub media: Vec<TootMedia>,
+    pub replies_count: i32,
+    pub reblogs_count: i32,
+    pub favourites_count: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiAccount {
+    id: String,
+    acct: String,
+    #[serde(default)]
+    display_name: String,
+    url: String,
+    avatar: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiMedia {
+    #[serde(rename = "type")]
+    media_type: String,
+    url: Option<String>,
+    remote_url: Option<String>,
+    preview_url: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiStatus {
+    id: String,
+    uri: String,
+    url: Option<String>,
+    created_at: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    spoiler_text: String,
+    #[serde(default)]
+    sensitive: bool,
+    language: Option<String>,
+    account: ApiAccount,
+    reblog: Option<Box<ApiStatus>>,
+    #[serde(default)]
+    media_attachments: Vec<ApiMedia>,
+    #[serde(default)]
+    replies_count: i32,
+    #[serde(default)]
+    reblogs_count: i32,
+    #[serde(default)]
+    favourites_count: i32,
+}
+
+impl From<ApiAccount> for TootAccount {
+    fn from(account: ApiAccount) -> Self {
+        TootAccount {
+            acct: account.acct,
+            display_name: account.display_name,
+            url: account.url,
+            avatar: account.avatar,
+        }
+    }
+}
+
+impl From<ApiStatus> for Toot {
+    fn from(mut status: ApiStatus) -> Self {
+        let id = status.id.clone();
+        let (status, boosted_by) = match status.reblog.take() {
+            Some(reblog) => (*reblog, Some(status.account.into())),
+            None => (status, None),
+        };
+        let url = status.url.unwrap_or(status.uri);
+        // Rendered as html by the client, the instance decides what it sends
+        let base_url = Url::parse(&url).ok();
+        let content = match base_url {
+            Some(ref base_url) => clean_fragment(&status.content, base_url),
+            None => escape_text(&text_of(&status.content)),
+        };
+        Toot {
+            id,
+            created_at: status.created_at,
+            content,
+            spoiler_text: if status.spoiler_text.is_empty() { None } else { Some(status.spoiler_text) },
+            sensitive: status.sensitive,
+            language: status.language,
+            account: status.account.into(),
+            boosted_by,
+            media: status.media_attachments.into_iter().filter_map(|media| {
+                let base_url = base_url.as_ref()?;
+                let url = media.url.or(media.remote_url).and_then(|url| safe_url(base_url, &url))?;
+                Some(TootMedia {
+                    media_type: media.media_type,
+                    url,
+                    preview_url: media.preview_url.and_then(|preview_url| safe_url(base_url, &preview_url)),
+                    description: media.description,
+                })
+            }).collect(),
+            url,
+            replies_count: status.replies_count,
+            reblogs_count: status.reblogs_count,
+            favourites_count: status.favourites_count,
+        }
+    }
+}
+
+fn get<T: DeserializeOwned>(client: &Client, instance_url: &str, path: &str, params: &[(&str, &str)]) -> Result<T> {
+    let url = Url::parse_with_params(&format!("{}{}", instance_url, path), params)?;
+    let mut response = client.get(url).send()?;
+    if !response.status().is_success() {
+        bail!(ErrorKind::HttpStatus(response.status().as_u16(), None));
+    }
+    Ok(response.json()?)
+}
+
+fn timeline(client: &Client, instance_url: &str, path: &str, mut params: Vec<(&str, &str)>, since_id: &Option<String>) -> Result<Vec<Toot>> {
+    params.push(("limit", LIMIT));
+    if let Some(ref since_id) = *since_id {
+        params.push(("since_id", since_id));
+    }
+    let statuses: Vec<ApiStatus> = get(client, instance_url, path, &params)?;
+    Ok(statuses.into_iter().map(Toot::from).collect())
+}
+
+/// The hashtag comes from users, `/`, `?` or `#` must not change the requested path
+fn tag_timeline_path(hashtag: &str) -> String {
+    let hashtag = hashtag.trim_left_matches('#');
+    format!("/api/v1/timelines/tag/{}", utf8_percent_encode(hashtag, PATH_SEGMENT_ENCODE_SET))
+}
+
+/// Instance url of a `user@instance` handle
+pub fn account_instance_url(account: &str) -> Option<String> {
+    let mut parts = account.trim_left_matches('@').splitn(2, '@');
+    match (parts.next(), parts.next()) {
+        (Some(user), Some(host)) if !user.is_empty() && !host.is_empty() => Some(format!("https://{}", host)),
+        _ => None,
+    }
+}
+
+fn lookup_account_id(client: &Client, instance_url: &str, account: &str) -> Result<String> {
+    let account: ApiAccount = get(client, instance_url, "/api/v1/accounts/lookup", &[("acct", account)])?;
+    Ok(account.id)
+}
+
+/// Public statuses of the source newer than its `since_id`, resolving the account id when it is not known yet
+fn fetch_toots(client: &Client, mastodon_source: &mut MastodonSource) -> Result<Vec<Toot>> {
+    if let Some(ref hashtag) = mastodon_source.hashtag {
+        let path = tag_timeline_path(hashtag);
+        return timeline(client, &mastodon_source.instance_url, &path, Vec::new(), &mastodon_source.since_id);
+    }
+    let account = mastodon_source.account.clone().ok_or("mastodon source without account nor hashtag")?;
+    let account_id = match mastodon_source.account_id.clone() {
+        Some(account_id) => account_id,
+        None => lookup_account_id(client, &mastodon_source.instance_url, account.trim_left_matches('@'))?,
+    };
+    mastodon_source.account_id = Some(account_id.clone());
+    let path = format!("/api/v1/accounts/{}/statuses", account_id);
+    timeline(client, &mastodon_source.instance_url, &path, vec![("exclude_replies", "true")], &mastodon_source.since_id)
+}
+
+pub fn ingest_toots(subscribers: &Vec<User>, source: &Source, toots: &Vec<Toot>, pg: &PgDatabase) -> Result<()> {
+    let filter_rules = find_enabled_rules(pg, subscribers)?;
+    let rules = SubscriberRules::new(&filter_rules);
+    for toot in toots {
+        if !is_feed_exist(pg, &toot.url, source)? {
+            let feed = Feed::new(&toot.url, None, None, None, serde_json::to_value(toot).ok(), source.uuid);
+            if insert_feed(pg, &feed).is_ok() {
+                insert_subscribers_feeds(subscribers, &rules, &feed, pg)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn process_mastodon_source(subscribers: &Vec<User>, source: &Source, mastodon_source: &MastodonSource, client: &Client, pg: &PgDatabase) -> Result<()> {
+    let mut updated_source = mastodon_source.clone();
+    match fetch_toots(client, &mut updated_source) {
+        Ok(toots) => {
+            ingest_toots(subscribers, source, &toots, pg)?;
+            updated_source.since_id = newest_id(toots.iter().map(|toot| &toot.id), &mastodon_source.since_id);
+            if updated_source.since_id != mastodon_source.since_id || updated_source.account_id != mastodon_source.account_id {
+                update_mastodon_source(pg, source, &updated_source)?;
+            }
+            record_fetch_success(pg, source, 200, DEFAULT_FETCH_INTERVAL)?;
+        },
+        Err(error) => {
+            println!("mastodon {:?} error {}", source.uuid, error);
+            record_fetch_failure(pg, source, error_status(&error), &error.to_string(), None)?;
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(content: &str) -> ApiStatus {
+        serde_json::from_value(json!({
+            "id": "1",
+            "uri": "https://mastodon.social/users/rust/statuses/1",
+            "url": "https://mastodon.social/@rust/1",
+            "created_at": "2018-03-23T10:00:00.000Z",
+            "content": content,
+            "account": {
+                "id": "7",
+                "acct": "rust",
+                "display_name": "Rust",
+                "url": "https://mastodon.social/@rust"
+            },
+            "media_attachments": [
+                {"type": "image", "url": "javascript:alert(1)"},
+                {"type": "image", "url": "/media/1.png", "preview_url": "data:image/png;base64,AAAA"}
+            ]
+        })).unwrap()
+    }
+
+    #[test]
+    fn hashtag_is_a_single_path_segment() {
+        assert_eq!(tag_timeline_path("rust"), "/api/v1/timelines/tag/rust");
+        assert_eq!(tag_timeline_path("#rust"), "/api/v1/timelines/tag/rust");
+        assert_eq!(tag_timeline_path("../accounts/1?x=#y"), "/api/v1/timelines/tag/..%2Faccounts%2F1%3Fx=%23y");
+    }
+
+    #[test]
+    fn toot_content_is_sanitized() {
+        let toot = Toot::from(status(r#"<p onclick="alert(1)">hi <a href="javascript:alert(1)">#rust</a></p><script>alert(1)</script>"#));
+        assert_eq!(toot.content, "<p>hi <a>#rust</a></p>");
+        assert_eq!(toot.url, "https://mastodon.social/@rust/1");
+    }
+
+    #[test]
+    fn toot_media_keeps_safe_urls() {
+        let toot = Toot::from(status("<p>hi</p>"));
+        assert_eq!(toot.media.len(), 1);
+        assert_eq!(toot.media[0].url, "https://mastodon.social/media/1.png");
+        assert_eq!(toot.media[0].preview_url, None);
+    }
+
+    #[test]
+    fn account_handle_gives_the_instance() {
+        assert_eq!(account_instance_url("@rust@mastodon.social"), Some("https://mastodon.social".to_owned()));
+        assert_eq!(account_instance_url("rust"), None);
+    }
+}
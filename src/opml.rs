@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use chrono::prelude::*;
+use postgres::rows::Row;
+use quick_xml::reader::Reader;
+use quick_xml::events::{Event, BytesStart};
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use url::Url;
+
+use errors::*;
+use pg::PgDatabase;
+use user::User;
+use source::Source;
+use source_option::{SourceOption, RssSource};
+use sources::{source_existe, find_source_by_xml_url};
+use users_sources::{UserSource, user_source_exist};
+
+/// Feed outline of an OPML document, `category` is the path of the folders it is nested in
+#[derive(Debug, PartialEq)]
+pub struct OpmlOutline {
+    pub title: Option<String>,
+    pub xml_url: String,
+    pub html_url: Option<String>,
+    pub category: Option<String>,
+}
+
+#[derive(GraphQLObject, Debug, Default)]
+pub struct OpmlImport {
+    /// Sources that did not exist yet
+    pub created: i32,
+    /// Sources the user now follows
+    pub followed: i32,
+    /// Sources the user was already following
+    pub already_followed: i32,
+    /// Outlines that could not be imported
+    pub errors: Vec<String>,
+}
+
+fn outline_attributes(element: &BytesStart, reader: &Reader<&[u8]>) -> BTreeMap<String, String> {
+    let mut attributes = BTreeMap::new();
+    for attribute in element.attributes() {
+        if let Ok(attribute) = attribute {
+            if let Ok(value) = attribute.unescape_and_decode_value(reader) {
+                attributes.insert(String::from_utf8_lossy(attribute.key).to_lowercase(), value);
+            }
+        }
+    }
+    attributes
+}
+
+fn to_outline(attributes: &BTreeMap<String, String>, categories: &Vec<Option<String>>) -> Option<OpmlOutline> {
+    let xml_url = attributes.get("xmlurl")?.trim().to_owned();
+    let folders: Vec<String> = categories.iter().filter_map(|category| category.clone()).collect();
+    Some(OpmlOutline {
+        title: attributes.get("title").or_else(|| attributes.get("text")).map(|title| title.to_owned()),
+        xml_url,
+        html_url: attributes.get("htmlurl").map(|html_url| html_url.to_owned()),
+        category: if folders.is_empty() { None } else { Some(folders.join("/")) },
+    })
+}
+
+pub fn parse_opml(document: &str) -> Result<Vec<OpmlOutline>> {
+    let mut outlines = Vec::new();
+    let mut reader = Reader::from_str(document);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    // One entry per opened outline, the folder name when the outline is a folder
+    let mut categories: Vec<Option<String>> = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref element)) if element.name() == b"outline" => {
+                let attributes = outline_attributes(element, &reader);
+                match to_outline(&attributes, &categories) {
+                    Some(outline) => {
+                        outlines.push(outline);
+                        categories.push(None);
+                    },
+                    None => {
+                        let folder = attributes.get("title").or_else(|| attributes.get("text")).map(|folder| folder.to_owned());
+                        categories.push(folder);
+                    }
+                }
+            },
+            Ok(Event::Empty(ref element)) if element.name() == b"outline" => {
+                let attributes = outline_attributes(element, &reader);
+                if let Some(outline) = to_outline(&attributes, &categories) {
+                    outlines.push(outline);
+                }
+            },
+            Ok(Event::End(ref element)) if element.name() == b"outline" => {
+                categories.pop();
+            },
+            Ok(Event::Eof) => break,
+            Err(error) => bail!("invalid opml at {}: {:?}", reader.buffer_position(), error),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(outlines)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn outline_line(indent: &str, rss_source: &RssSource) -> String {
+    format!(
+        "{}<outline type=\"rss\" text=\"{}\" title=\"{}\" xmlUrl=\"{}\" htmlUrl=\"{}\"/>\n",
+        indent,
+        escape(&rss_source.title),
+        escape(&rss_source.title),
+        escape(&rss_source.xml_url),
+        escape(&rss_source.html_url)
+    )
+}
+
+pub fn write_opml(title: &str, subscriptions: &Vec<OpmlSubscription>) -> String {
+    let mut by_category: BTreeMap<Option<String>, Vec<RssSource>> = BTreeMap::new();
+    for subscription in subscriptions {
+        if let Ok(SourceOption::Rss(rss_source)) = subscription.source.options() {
+            by_category.entry(subscription.category.clone()).or_insert_with(Vec::new).push(rss_source);
+        }
+    }
+    let mut opml = String::new();
+    opml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    opml.push_str("<opml version=\"2.0\">\n");
+    opml.push_str("  <head>\n");
+    opml.push_str(&format!("    <title>{}</title>\n", escape(title)));
+    opml.push_str(&format!("    <dateCreated>{}</dateCreated>\n", Utc::now().to_rfc2822()));
+    opml.push_str("  </head>\n");
+    opml.push_str("  <body>\n");
+    for (category, rss_sources) in &by_category {
+        match *category {
+            Some(ref category) => {
+                opml.push_str(&format!("    <outline text=\"{}\" title=\"{}\">\n", escape(category), escape(category)));
+                for rss_source in rss_sources {
+                    opml.push_str(&outline_line("      ", rss_source));
+                }
+                opml.push_str("    </outline>\n");
+            },
+            None => {
+                for rss_source in rss_sources {
+                    opml.push_str(&outline_line("    ", rss_source));
+                }
+            }
+        }
+    }
+    opml.push_str("  </body>\n");
+    opml.push_str("</opml>\n");
+    opml
+}
+
+/// A followed source with the folder the user filed it in
+#[derive(Debug)]
+pub struct OpmlSubscription {
+    pub source: Source,
+    pub category: Option<String>,
+}
+
+impl<'a> From<Row<'a>> for OpmlSubscription {
+    fn from(row: Row) -> Self {
+        let category = row.get("category");
+        OpmlSubscription {
+            source: row.into(),
+            category,
+        }
+    }
+}
+
+fn import_outline(pg: &PgDatabase, outline: &OpmlOutline, user: &User, report: &mut OpmlImport) -> Result<()> {
+    Url::parse(&outline.xml_url)?;
+    let source = match find_source_by_xml_url(pg, &outline.xml_url)? {
+        Some(source) => source,
+        None => {
+            let title = outline.title.clone().unwrap_or_else(|| outline.xml_url.clone());
+            let html_url = outline.html_url.clone().unwrap_or_else(|| outline.xml_url.clone());
+            let source = Source::new_rss(RssSource::new(&title, &outline.xml_url, &html_url))?;
+            if !source_existe(pg, &outline.xml_url)? {
+                pg.insert(&source)?;
+                report.created += 1;
+            }
+            source
+        }
+    };
+    if user_source_exist(pg, &source.uuid, user)? {
+        report.already_followed += 1;
+    } else {
+        let user_source = UserSource::new(user.uuid, source.uuid, outline.category.clone());
+        pg.insert(&user_source)?;
+        report.followed += 1;
+    }
+    Ok(())
+}
+
+pub fn import_opml(pg: &PgDatabase, document: &str, user: &User) -> Result<OpmlImport> {
+    let outlines = parse_opml(document)?;
+    let mut report = OpmlImport::default();
+    for outline in &outlines {
+        if let Err(error) = import_outline(pg, outline, user, &mut report) {
+            report.errors.push(format!("{}: {}", outline.xml_url, error));
+        }
+    }
+    Ok(report)
+}
+
+pub fn export_opml(pg: &PgDatabase, user: &User) -> Result<String> {
+    let query = r#"
+        SELECT sources.*, users_sources.category FROM sources
+        JOIN users_sources ON users_sources.source_uuid = sources.uuid
+        WHERE users_sources.user_uuid = $1::uuid;
+    "#;
+    let subscriptions: Vec<OpmlSubscription> = pg.find(query, &[&user.uuid])?;
+    Ok(write_opml(&format!("{} subscriptions", user.login), &subscriptions))
+}
+
+pub fn import_opml_resolver(pool: Pool<PostgresConnectionManager>, document: &str, user: &User) -> Result<OpmlImport> {
+    let pg = PgDatabase::from_pool(pool)?;
+    import_opml(&pg, document, user)
+}
+
+pub fn export_opml_resolver(pool: Pool<PostgresConnectionManager>, user: &User) -> Result<String> {
+    let pg = PgDatabase::from_pool(pool)?;
+    export_opml(&pg, user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outline(title: &str, xml_url: &str, html_url: Option<&str>, category: Option<&str>) -> OpmlOutline {
+        OpmlOutline {
+            title: Some(title.to_owned()),
+            xml_url: xml_url.to_owned(),
+            html_url: html_url.map(|html_url| html_url.to_owned()),
+            category: category.map(|category| category.to_owned()),
+        }
+    }
+
+    #[test]
+    fn parses_nested_folders() {
+        let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <opml version="2.0">
+              <head><title>Subscriptions</title></head>
+              <body>
+                <outline type="rss" text="Top" xmlUrl=" https://top.example/feed " htmlUrl="https://top.example"/>
+                <outline text="Tech">
+                  <outline type="rss" title="Rust &amp; co" text="ignored" xmlUrl="https://rust.example/feed"/>
+                  <outline title="Web">
+                    <outline type="rss" text="Css" XMLURL="https://css.example/feed"></outline>
+                  </outline>
+                </outline>
+                <outline type="rss" text="After" xmlUrl="https://after.example/feed"/>
+              </body>
+            </opml>"#;
+        assert_eq!(parse_opml(document).unwrap(), vec![
+            outline("Top", "https://top.example/feed", Some("https://top.example"), None),
+            outline("Rust & co", "https://rust.example/feed", None, Some("Tech")),
+            outline("Css", "https://css.example/feed", None, Some("Tech/Web")),
+            outline("After", "https://after.example/feed", None, None),
+        ]);
+    }
+
+    #[test]
+    fn folders_without_feeds_give_nothing() {
+        let document = r#"<opml version="1.0"><body><outline text="Empty"/><outline text="Folder"></outline></body></opml>"#;
+        assert!(parse_opml(document).unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_document_is_refused() {
+        assert!(parse_opml(r#"<opml><body><outline text="a"></body></opml>"#).is_err());
+    }
+}
@@ -20,6 +20,17 @@ pub fn create_db_pool(app_config: &config::Config) -> Pool<PostgresConnectionMan
     Pool::new(manager).expect("Failed to create pool")
 }
 
+/// Pool of a background job, one connection per worker plus one for the job loop, opened on demand
+pub fn create_job_pool(app_config: &config::Config, workers: usize) -> Pool<PostgresConnectionManager> {
+    let database_url = app_config.database_url.clone();
+    let manager = PostgresConnectionManager::new(database_url, TlsMode::None).expect("Create PostgresConnectionManager error");
+    Pool::builder()
+        .max_size(workers as u32 + 1)
+        .min_idle(Some(0))
+        .build(manager)
+        .expect("Failed to create pool")
+}
+
 pub trait Insertable {
     fn insert_query(&self) -> String;
     fn insert_params(&self) -> Box<[&ToSql]>;
@@ -74,6 +85,23 @@ impl PgDatabase {
         Ok(rows.iter().map(|row| row.into()).collect())
     }
 
+    /// Runs `body` in a transaction of this connection, committed when it succeeds and rolled back otherwise
+    pub fn transaction<T, F>(&self, body: F) -> Result<T> where F: FnOnce(&PgDatabase) -> Result<T> {
+        self.connection.batch_execute("BEGIN;")?;
+        match body(self) {
+            Ok(value) => {
+                self.connection.batch_execute("COMMIT;")?;
+                Ok(value)
+            },
+            Err(error) => {
+                if let Err(rollback_error) = self.connection.batch_execute("ROLLBACK;") {
+                    println!("ROLLBACK Error -> {:?}", rollback_error);
+                }
+                Err(error)
+            },
+        }
+    }
+
     pub fn find_one<'a, E>(&self, query: &str, params: &[&'a ToSql]) -> Result<Option<E>> where E: for<'b> From<Row<'b>> {
         let rows = self.connection.query(query, params)?;
         let mut items: Vec<E> = rows.iter().map(|row| row.into()).collect();
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use quick_xml::reader::Reader;
+use quick_xml::events::{Event, BytesStart};
+
+/// Media file attached to an item, the audio or video of a podcast episode
+#[derive(GraphQLObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Enclosure {
+    pub url: String,
+    pub mime_type: Option<String>,
+    /// Size in bytes
+    pub length: Option<f64>,
+    /// Duration in seconds
+    pub duration: Option<i32>,
+    pub title: Option<String>,
+}
+
+/// iTunes and Podcasting 2.0 metadata of an episode
+#[derive(GraphQLObject, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PodcastEpisode {
+    /// Episode artwork, the show artwork when the episode has none
+    pub image: Option<String>,
+    pub episode: Option<i32>,
+    pub season: Option<i32>,
+    pub explicit: Option<bool>,
+    pub chapters_url: Option<String>,
+    pub chapters_type: Option<String>,
+    pub transcript_url: Option<String>,
+}
+
+/// Media found in one `<item>` or `<entry>`, with the guid and links it can be matched on
+#[derive(Debug, Default)]
+pub struct ItemMedia {
+    pub keys: Vec<String>,
+    pub enclosures: Vec<Enclosure>,
+    pub podcast: Option<PodcastEpisode>,
+    /// `itunes:duration` of the item, it applies to its first enclosure
+    pub duration: Option<i32>,
+}
+
+fn attributes(element: &BytesStart, reader: &Reader<&[u8]>) -> HashMap<Vec<u8>, String> {
+    let mut attributes = HashMap::new();
+    for attribute in element.attributes() {
+        if let Ok(attribute) = attribute {
+            if let Ok(value) = attribute.unescape_and_decode_value(reader) {
+                attributes.insert(attribute.key.to_vec(), value.trim().to_owned());
+            }
+        }
+    }
+    attributes
+}
+
+/// `itunes:duration` is either seconds or `[HH:]MM:SS`
+pub fn parse_duration(duration: &str) -> Option<i32> {
+    let mut seconds = 0.0;
+    for part in duration.trim().split(':') {
+        seconds = seconds * 60.0 + part.trim().parse::<f64>().ok()?;
+    }
+    Some(seconds as i32)
+}
+
+fn is_item(name: &[u8]) -> bool {
+    name == b"item" || name == b"entry"
+}
+
+fn push_enclosure(media: &mut ItemMedia, attributes: &HashMap<Vec<u8>, String>, url_key: &[u8], length_key: &[u8]) {
+    if let Some(url) = attributes.get(url_key) {
+        if url.is_empty() || media.enclosures.iter().any(|enclosure| &enclosure.url == url) {
+            return;
+        }
+        media.enclosures.push(Enclosure {
+            url: url.to_owned(),
+            mime_type: attributes.get(&b"type"[..]).map(|mime_type| mime_type.to_owned()),
+            length: attributes.get(length_key).and_then(|length| length.parse::<f64>().ok()).and_then(|length| if length > 0.0 { Some(length) } else { None }),
+            duration: attributes.get(&b"duration"[..]).and_then(|duration| parse_duration(duration)),
+            title: attributes.get(&b"title"[..]).map(|title| title.to_owned()),
+        });
+    }
+}
+
+fn podcast(media: &mut ItemMedia) -> &mut PodcastEpisode {
+    media.podcast.get_or_insert_with(PodcastEpisode::default)
+}
+
+fn read_item_element(media: &mut ItemMedia, element: &BytesStart, reader: &Reader<&[u8]>) {
+    let attributes = attributes(element, reader);
+    match element.name() {
+        b"enclosure" => push_enclosure(media, &attributes, b"url", b"length"),
+        b"media:content" => push_enclosure(media, &attributes, b"url", b"fileSize"),
+        b"link" => {
+            let rel = attributes.get(&b"rel"[..]).map(|rel| rel.as_str()).unwrap_or("alternate");
+            match rel {
+                "enclosure" => push_enclosure(media, &attributes, b"href", b"length"),
+                "alternate" => media.keys.extend(attributes.get(&b"href"[..]).cloned()),
+                _ => (),
+            }
+        },
+        b"itunes:image" => podcast(media).image = attributes.get(&b"href"[..]).cloned(),
+        b"podcast:chapters" => {
+            podcast(media).chapters_url = attributes.get(&b"url"[..]).cloned();
+            podcast(media).chapters_type = attributes.get(&b"type"[..]).cloned();
+        },
+        b"podcast:transcript" => {
+            if podcast(media).transcript_url.is_none() {
+                podcast(media).transcript_url = attributes.get(&b"url"[..]).cloned();
+            }
+        },
+        _ => (),
+    }
+}
+
+fn read_item_text(media: &mut ItemMedia, name: &[u8], value: &str) {
+    match name {
+        b"guid" | b"id" | b"link" => media.keys.push(value.to_owned()),
+        b"itunes:duration" => media.duration = parse_duration(value),
+        b"itunes:episode" | b"podcast:episode" => podcast(media).episode = value.parse().ok(),
+        b"itunes:season" | b"podcast:season" => podcast(media).season = value.parse().ok(),
+        b"itunes:explicit" => podcast(media).explicit = Some(value == "yes" || value == "true" || value == "explicit"),
+        _ => (),
+    }
+}
+
+/// Enclosures and podcast metadata of every item of an RSS or Atom document, in document order
+pub fn parse_item_media(document: &str) -> Vec<ItemMedia> {
+    let mut items = Vec::new();
+    let mut show_image: Option<String> = None;
+    let mut current: Option<ItemMedia> = None;
+    let mut reader = Reader::from_str(document);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref element)) => {
+                if current.is_none() && is_item(element.name()) {
+                    current = Some(ItemMedia::default());
+                }
+                match current {
+                    Some(ref mut media) => read_item_element(media, element, &reader),
+                    None if element.name() == b"itunes:image" => show_image = attributes(element, &reader).get(&b"href"[..]).cloned(),
+                    None => (),
+                }
+                path.push(element.name().to_vec());
+            },
+            Ok(Event::Empty(ref element)) => {
+                match current {
+                    Some(ref mut media) => read_item_element(media, element, &reader),
+                    None if element.name() == b"itunes:image" => show_image = attributes(element, &reader).get(&b"href"[..]).cloned(),
+                    None => (),
+                }
+            },
+            Ok(Event::End(ref element)) => {
+                path.pop();
+                if is_item(element.name()) && !path.iter().any(|name| is_item(name)) {
+                    if let Some(media) = current.take() {
+                        items.push(media);
+                    }
+                }
+            },
+            Ok(Event::Text(ref text)) | Ok(Event::CData(ref text)) => {
+                if let (Some(media), Some(name)) = (current.as_mut(), path.last()) {
+                    let value = text.unescape_and_decode(&reader).unwrap_or_default();
+                    read_item_text(media, name, value.trim());
+                }
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    for media in items.iter_mut() {
+        if let Some(enclosure) = media.enclosures.first_mut() {
+            if enclosure.duration.is_none() {
+                enclosure.duration = media.duration;
+            }
+        }
+        if media.podcast.is_some() || !media.enclosures.is_empty() {
+            if show_image.is_some() && media.podcast.as_ref().and_then(|podcast| podcast.image.as_ref()).is_none() {
+                podcast(media).image = show_image.clone();
+            }
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("01:30"), Some(90));
+        assert_eq!(parse_duration(" 1:02:03 "), Some(3723));
+        assert_eq!(parse_duration("soon"), None);
+    }
+
+    #[test]
+    fn rss_podcast_items() {
+        let document = r#"<?xml version="1.0"?>
+            <rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:podcast="https://podcastindex.org/namespace/1.0">
+              <channel>
+                <itunes:image href="https://example.com/show.jpg"/>
+                <item>
+                  <guid>episode-1</guid>
+                  <link>https://example.com/1</link>
+                  <enclosure url="https://example.com/1.mp3" type="audio/mpeg" length="2048"/>
+                  <enclosure url="https://example.com/1.mp3" type="audio/mpeg" length="2048"/>
+                  <itunes:duration>10:00</itunes:duration>
+                  <itunes:episode>3</itunes:episode>
+                  <itunes:season>2</itunes:season>
+                  <itunes:explicit>yes</itunes:explicit>
+                  <podcast:chapters url="https://example.com/1.json" type="application/json+chapters"/>
+                  <podcast:transcript url="https://example.com/1.vtt"/>
+                  <podcast:transcript url="https://example.com/1.srt"/>
+                </item>
+                <item>
+                  <guid>episode-2</guid>
+                  <itunes:image href="https://example.com/2.jpg"/>
+                  <enclosure url="https://example.com/2.mp3" length="0"/>
+                </item>
+                <item>
+                  <guid>post</guid>
+                </item>
+              </channel>
+            </rss>"#;
+        let items = parse_item_media(document);
+        assert_eq!(items.len(), 3);
+
+        let first = &items[0];
+        assert_eq!(first.keys, vec!["episode-1".to_owned(), "https://example.com/1".to_owned()]);
+        assert_eq!(first.enclosures, vec![Enclosure {
+            url: "https://example.com/1.mp3".to_owned(),
+            mime_type: Some("audio/mpeg".to_owned()),
+            length: Some(2048.0),
+            duration: Some(600),
+            title: None,
+        }]);
+        assert_eq!(first.podcast, Some(PodcastEpisode {
+            image: Some("https://example.com/show.jpg".to_owned()),
+            episode: Some(3),
+            season: Some(2),
+            explicit: Some(true),
+            chapters_url: Some("https://example.com/1.json".to_owned()),
+            chapters_type: Some("application/json+chapters".to_owned()),
+            transcript_url: Some("https://example.com/1.vtt".to_owned()),
+        }));
+
+        let second = &items[1];
+        assert_eq!(second.enclosures[0].length, None);
+        assert_eq!(second.podcast.as_ref().and_then(|podcast| podcast.image.clone()), Some("https://example.com/2.jpg".to_owned()));
+
+        assert!(items[2].enclosures.is_empty());
+        assert!(items[2].podcast.is_none());
+    }
+
+    #[test]
+    fn atom_enclosure_links_and_media_content() {
+        let document = r#"<feed xmlns="http://www.w3.org/2005/Atom" xmlns:media="http://search.yahoo.com/mrss/">
+              <entry>
+                <id>tag:example.com,2018:1</id>
+                <link href="https://example.com/1"/>
+                <link rel="enclosure" href="https://example.com/1.ogg" type="audio/ogg" length="512"/>
+                <media:content url="https://example.com/1.mp4" type="video/mp4" fileSize="4096" duration="65"/>
+              </entry>
+            </feed>"#;
+        let items = parse_item_media(document);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].keys, vec!["tag:example.com,2018:1".to_owned(), "https://example.com/1".to_owned()]);
+        let urls: Vec<&str> = items[0].enclosures.iter().map(|enclosure| enclosure.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://example.com/1.ogg", "https://example.com/1.mp4"]);
+        assert_eq!(items[0].enclosures[1].length, Some(4096.0));
+        assert_eq!(items[0].enclosures[1].duration, Some(65));
+    }
+}
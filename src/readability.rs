@@ -0,0 +1,467 @@
+use std::cmp::Ordering;
+use std::io::Read;
+use reqwest::Client;
+use scraper::{Html, Selector, ElementRef};
+use scraper::node::Node;
+use url::Url;
+
+use errors::*;
+use config::CONFIG;
+use mercury;
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct ReadableData {
+    pub url: String,
+    pub domain: Option<String>,
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub date_published: Option<String>,
+    pub lead_image_url: Option<String>,
+    pub dek: Option<String>,
+    pub excerpt: Option<String>,
+    pub word_count: Option<i32>,
+    pub direction: Option<String>,
+    pub total_pages: Option<i32>,
+    pub rendered_pages: Option<i32>,
+    pub next_page_url: Option<String>,
+}
+
+/// Paragraphs shorter than this do not vote for their container
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+const EXCERPT_LENGTH: usize = 200;
+
+const POSITIVE_NAMES: [&str; 10] = ["article", "body", "content", "entry", "main", "page", "post", "text", "blog", "story"];
+const NEGATIVE_NAMES: [&str; 15] = [
+    "comment", "footer", "sidebar", "sponsor", "share", "social", "nav", "menu", "related",
+    "promo", "widget", "masthead", "banner", "cookie", "popup",
+];
+/// Dropped with their content, `base` would rewrite every relative link of the reader
+const STRIPPED_TAGS: [&str; 22] = [
+    "script", "style", "noscript", "iframe", "form", "input", "button", "select", "textarea",
+    "nav", "aside", "footer", "header", "svg", "object", "embed", "link", "meta",
+    "head", "base", "title", "template",
+];
+/// Allow-list, any other tag is unwrapped and only its children are kept
+const KEPT_TAGS: [&str; 56] = [
+    "p", "a", "img", "br", "hr", "h1", "h2", "h3", "h4", "h5", "h6",
+    "ul", "ol", "li", "dl", "dt", "dd", "blockquote", "pre", "code",
+    "em", "strong", "b", "i", "u", "s", "sub", "sup", "small", "mark", "q", "cite", "abbr", "del", "ins", "time",
+    "table", "caption", "colgroup", "col", "thead", "tbody", "tfoot", "tr", "th", "td",
+    "figure", "figcaption", "div", "span", "section", "article", "details", "summary", "kbd", "samp",
+];
+const VOID_TAGS: [&str; 4] = ["img", "br", "hr", "col"];
+/// Allow-list, event handlers and `style` are never kept
+const KEPT_ATTRIBUTES: [&str; 7] = ["href", "src", "alt", "title", "datetime", "colspan", "rowspan"];
+/// `javascript:` or `data:` links would run in the reader
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+lazy_static! {
+    static ref PARAGRAPHS: Selector = Selector::parse("p, pre, td").expect("valid paragraphs selector");
+    static ref LINKS: Selector = Selector::parse("a").expect("valid links selector");
+    static ref METAS: Selector = Selector::parse("meta[content]").expect("valid metas selector");
+    static ref TITLE: Selector = Selector::parse("title").expect("valid title selector");
+    static ref TIME: Selector = Selector::parse("time[datetime]").expect("valid time selector");
+    static ref HTML: Selector = Selector::parse("html[dir]").expect("valid html selector");
+    static ref NEXT_PAGE: Selector = Selector::parse("link[rel=next][href]").expect("valid next page selector");
+}
+
+fn class_weight(element: &ElementRef) -> f64 {
+    let names = format!(
+        "{} {}",
+        element.value().attr("class").unwrap_or(""),
+        element.value().attr("id").unwrap_or("")
+    ).to_lowercase();
+    let mut weight = 0.0;
+    if NEGATIVE_NAMES.iter().any(|name| names.contains(name)) {
+        weight -= 25.0;
+    }
+    if POSITIVE_NAMES.iter().any(|name| names.contains(name)) {
+        weight += 25.0;
+    }
+    weight
+}
+
+fn tag_weight(name: &str) -> f64 {
+    match name {
+        "article" => 10.0,
+        "div" | "section" | "main" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "address" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    }
+}
+
+fn text_length(element: &ElementRef) -> usize {
+    element.text().map(|text| text.trim().chars().count()).sum()
+}
+
+/// Share of the text that sits inside links, menus and link lists are mostly links
+fn link_density(element: &ElementRef) -> f64 {
+    let total = text_length(element);
+    if total == 0 {
+        return 0.0;
+    }
+    let links: usize = element.select(&LINKS).map(|link| text_length(&link)).sum();
+    links as f64 / total as f64
+}
+
+fn add_score<'a>(candidates: &mut Vec<(ElementRef<'a>, f64)>, element: ElementRef<'a>, score: f64) {
+    if let Some(candidate) = candidates.iter_mut().find(|candidate| candidate.0.id() == element.id()) {
+        candidate.1 += score;
+        return;
+    }
+    let initial_score = tag_weight(element.value().name()) + class_weight(&element);
+    candidates.push((element, initial_score + score));
+}
+
+/// Every long enough paragraph votes for its parent and, half as much, for its grand parent,
+/// the container with the most votes once discounted by its link density holds the article
+fn best_candidate<'a>(document: &'a Html) -> Option<ElementRef<'a>> {
+    let mut candidates: Vec<(ElementRef<'a>, f64)> = Vec::new();
+    for paragraph in document.select(&PARAGRAPHS) {
+        let text: String = paragraph.text().collect();
+        let length = text.trim().chars().count();
+        if length < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f64 + f64::min((length / 100) as f64, 3.0);
+        let parent = paragraph.parent().and_then(ElementRef::wrap);
+        let grand_parent = parent.and_then(|parent| parent.parent()).and_then(ElementRef::wrap);
+        if let Some(parent) = parent {
+            add_score(&mut candidates, parent, score);
+        }
+        if let Some(grand_parent) = grand_parent {
+            add_score(&mut candidates, grand_parent, score / 2.0);
+        }
+    }
+    candidates.into_iter()
+        .map(|(element, score)| (element, score * (1.0 - link_density(&element))))
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+        .map(|(element, _)| element)
+}
+
+pub fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn escape_attribute(value: &str) -> String {
+    escape_text(value).replace('"', "&quot;")
+}
+
+/// Absolute form of `link`, `None` when it does not resolve to a safe scheme
+pub fn safe_url(base_url: &Url, link: &str) -> Option<String> {
+    base_url.join(link.trim()).ok()
+        .and_then(|url| if SAFE_SCHEMES.contains(&url.scheme()) { Some(url.into_string()) } else { None })
+}
+
+/// Serializes the children of `element` without scripts, forms, chrome, link farms and whatever `removed` matches,
+/// keeping only content tags and presentational attributes with absolute urls
+pub fn clean_html(element: ElementRef, base_url: &Url, removed: &[Selector], output: &mut String) {
+    for child in element.children() {
+        match *child.value() {
+            Node::Text(ref text) => output.push_str(&escape_text(text)),
+            Node::Element(ref child_element) => {
+                let name = child_element.name();
+                if STRIPPED_TAGS.contains(&name) {
+                    continue;
+                }
+                if let Some(child_ref) = ElementRef::wrap(child) {
+                    if class_weight(&child_ref) < 0.0 && link_density(&child_ref) > 0.5 {
+                        continue;
+                    }
+                    if removed.iter().any(|selector| selector.matches(&child_ref)) {
+                        continue;
+                    }
+                    if !KEPT_TAGS.contains(&name) {
+                        clean_html(child_ref, base_url, removed, output);
+                        continue;
+                    }
+                    output.push('<');
+                    output.push_str(name);
+                    for (attribute, value) in child_element.attrs() {
+                        if !KEPT_ATTRIBUTES.contains(&attribute) {
+                            continue;
+                        }
+                        let value = if attribute == "href" || attribute == "src" {
+                            match safe_url(base_url, value) {
+                                Some(url) => url,
+                                None => continue,
+                            }
+                        } else {
+                            value.to_owned()
+                        };
+                        output.push_str(&format!(" {}=\"{}\"", attribute, escape_attribute(&value)));
+                    }
+                    if VOID_TAGS.contains(&name) {
+                        output.push_str(" />");
+                    } else {
+                        output.push('>');
+                        clean_html(child_ref, base_url, removed, output);
+                        output.push_str(&format!("</{}>", name));
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Cleaned version of an html fragment coming from a third party
+pub fn clean_fragment(html: &str, base_url: &Url) -> String {
+    let fragment = Html::parse_fragment(html);
+    let mut cleaned = String::new();
+    clean_html(fragment.root_element(), base_url, &[], &mut cleaned);
+    cleaned
+}
+
+/// Content from feeds and extraction services goes through the same cleaning as the built-in extractor
+pub fn sanitize_readable(mut data: ReadableData, url: &str) -> ReadableData {
+    let base_url = match Url::parse(url) {
+        Ok(base_url) => base_url,
+        Err(_) => return ReadableData { content: None, lead_image_url: None, next_page_url: None, ..data },
+    };
+    data.content = data.content.map(|content| clean_fragment(&content, &base_url));
+    data.lead_image_url = data.lead_image_url.and_then(|image| safe_url(&base_url, &image));
+    data.next_page_url = data.next_page_url.and_then(|next_page| safe_url(&base_url, &next_page));
+    data
+}
+
+/// Content of the first `<meta>` whose property or name is one of `names`
+fn meta_content(document: &Html, names: &[&str]) -> Option<String> {
+    for name in names {
+        for meta in document.select(&METAS) {
+            let meta = meta.value();
+            let key = meta.attr("property").or_else(|| meta.attr("name")).unwrap_or("");
+            if key.eq_ignore_ascii_case(name) {
+                if let Some(content) = meta.attr("content") {
+                    if !content.trim().is_empty() {
+                        return Some(content.trim().to_owned());
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+pub fn first_text(document: &Html, selector: &Selector) -> Option<String> {
+    document.select(selector).next()
+        .map(|element| element.text().collect::<String>().trim().to_owned())
+        .and_then(|text| if text.is_empty() { None } else { Some(text) })
+}
+
+/// Plain text of an html fragment
+pub fn text_of(html: &str) -> String {
+    Html::parse_fragment(html).root_element().text().collect::<Vec<&str>>().join(" ")
+}
+
+pub fn truncate_words(text: &str, length: usize) -> String {
+    let mut excerpt = String::new();
+    for word in text.split_whitespace() {
+        if excerpt.chars().count() + word.chars().count() > length {
+            excerpt.push_str("…");
+            break;
+        }
+        if !excerpt.is_empty() {
+            excerpt.push(' ');
+        }
+        excerpt.push_str(word);
+    }
+    excerpt
+}
+
+/// Extracts the article of a web page in the shape the Mercury parser used to return
+pub fn extract(html: &str, url: &str) -> Option<ReadableData> {
+    let base_url = Url::parse(url).ok()?;
+    let document = Html::parse_document(html);
+    let candidate = best_candidate(&document)?;
+    let mut content = String::new();
+    clean_html(candidate, &base_url, &[], &mut content);
+    let text = candidate.text().collect::<Vec<&str>>().join(" ");
+    Some(ReadableData {
+        url: url.to_owned(),
+        domain: base_url.host_str().map(|host| host.to_owned()),
+        title: meta_content(&document, &["og:title", "twitter:title"]).or_else(|| first_text(&document, &TITLE)),
+        content: Some(content),
+        date_published: meta_content(&document, &["article:published_time", "date", "dc.date"])
+            .or_else(|| document.select(&TIME).next().and_then(|time| time.value().attr("datetime").map(|date| date.to_owned()))),
+        lead_image_url: meta_content(&document, &["og:image", "twitter:image"]).and_then(|image| safe_url(&base_url, &image)),
+        dek: None,
+        excerpt: meta_content(&document, &["description", "og:description"]).or_else(|| Some(truncate_words(&text, EXCERPT_LENGTH))),
+        word_count: Some(text.split_whitespace().count() as i32),
+        direction: document.select(&HTML).next().and_then(|html| html.value().attr("dir").map(|dir| dir.to_owned())).or_else(|| Some("ltr".to_owned())),
+        total_pages: Some(1),
+        rendered_pages: Some(1),
+        next_page_url: document.select(&NEXT_PAGE).next().and_then(|link| link.value().attr("href").and_then(|href| safe_url(&base_url, href))),
+    })
+}
+
+pub fn fetch_html(client: &Client, url: &str) -> Result<String> {
+    let mut response = client.get(url).send()?;
+    if !response.status().is_success() {
+        bail!(ErrorKind::HttpStatus(response.status().as_u16(), None));
+    }
+    let mut html = String::new();
+    response.read_to_string(&mut html)?;
+    Ok(html)
+}
+
+/// Readable version of the page, through Mercury when a MERCURY_API_KEY is configured
+pub fn fetch_readable(client: &Client, url: &str) -> Result<Option<ReadableData>> {
+    match CONFIG.mercury_api_key {
+        Some(ref api_key) => mercury::fetch_readable(client, url, api_key),
+        None => {
+            let html = fetch_html(client, url)?;
+            Ok(extract(&html, url))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE: &str = r#"
+        <html dir="rtl">
+        <head>
+            <title>Page title</title>
+            <meta property="og:title" content="Article title">
+            <meta property="og:image" content="/lead.png">
+            <meta property="article:published_time" content="2018-03-01T10:00:00Z">
+        </head>
+        <body>
+            <nav class="menu"><a href="/">Home</a> <a href="/about">About</a></nav>
+            <div class="sidebar"><a href="/a">A link farm entry</a><a href="/b">Another link farm entry</a></div>
+            <article class="post">
+                <p>The first paragraph of the article is long enough, and has commas, to vote.</p>
+                <p>The second paragraph is as long as the first one, so the article clearly wins.</p>
+                <script>alert("x")</script>
+            </article>
+        </body>
+        </html>
+    "#;
+
+    fn clean(fragment: &str) -> String {
+        let document = Html::parse_fragment(fragment);
+        let base_url = Url::parse("http://example.com/post").unwrap();
+        let mut output = String::new();
+        clean_html(document.root_element(), &base_url, &[], &mut output);
+        output
+    }
+
+    #[test]
+    fn extract_finds_the_article() {
+        let data = extract(ARTICLE, "http://example.com/post").unwrap();
+        let content = data.content.unwrap();
+        assert!(content.contains("The first paragraph"));
+        assert!(!content.contains("link farm"));
+        assert!(!content.contains("alert"));
+        assert_eq!(data.title, Some("Article title".to_owned()));
+        assert_eq!(data.lead_image_url, Some("http://example.com/lead.png".to_owned()));
+        assert_eq!(data.date_published, Some("2018-03-01T10:00:00Z".to_owned()));
+        assert_eq!(data.direction, Some("rtl".to_owned()));
+        assert_eq!(data.domain, Some("example.com".to_owned()));
+    }
+
+    #[test]
+    fn extract_needs_paragraphs() {
+        assert!(extract("<html><body><a href=\"/\">Home</a></body></html>", "http://example.com/").is_none());
+    }
+
+    #[test]
+    fn dangerous_schemes_are_dropped() {
+        let output = clean(r#"<a href="javascript:alert(1)">a</a><a href=" JavaScript:alert(1)">b</a><img src="data:text/html;base64,PHNjcmlwdD4=">"#);
+        assert!(!output.to_lowercase().contains("javascript"));
+        assert!(!output.contains("data:"));
+        assert!(output.contains("<a>a</a>"));
+    }
+
+    #[test]
+    fn safe_links_are_absolute() {
+        let output = clean(r#"<a href="/next">n</a><a href="mailto:me@example.com">m</a><img src="https://cdn.example.com/i.png">"#);
+        assert!(output.contains(r#"href="http://example.com/next""#));
+        assert!(output.contains(r#"href="mailto:me@example.com""#));
+        assert!(output.contains(r#"src="https://cdn.example.com/i.png""#));
+    }
+
+    #[test]
+    fn handlers_and_styles_are_stripped() {
+        let output = clean(r#"<p onclick="alert(1)" style="background:url(javascript:alert(1))" title="t">text<img src="/i.png" onerror="alert(1)"></p>"#);
+        assert!(!output.contains("onclick"));
+        assert!(!output.contains("onerror"));
+        assert!(!output.contains("style"));
+        assert!(output.contains(r#"title="t""#));
+    }
+
+    #[test]
+    fn sanitize_cleans_foreign_content() {
+        let data = ReadableData {
+            url: "http://example.com/post".to_owned(),
+            domain: None,
+            title: None,
+            content: Some(r#"<p onmouseover="alert(1)">text<a href="javascript:alert(1)">link</a></p><script>alert(1)</script>"#.to_owned()),
+            date_published: None,
+            lead_image_url: Some("javascript:alert(1)".to_owned()),
+            dek: None,
+            excerpt: None,
+            word_count: None,
+            direction: None,
+            total_pages: None,
+            rendered_pages: None,
+            next_page_url: Some("/page/2".to_owned()),
+        };
+        let data = sanitize_readable(data, "http://example.com/post");
+        assert_eq!(data.content, Some("<p>text<a>link</a></p>".to_owned()));
+        assert_eq!(data.lead_image_url, None);
+        assert_eq!(data.next_page_url, Some("http://example.com/page/2".to_owned()));
+    }
+
+    #[test]
+    fn base_and_unknown_tags_do_not_survive() {
+        let output = clean(r#"<base href="https://evil.example/"><template><p>hidden</p></template><frame src="/f"><math><mi>x</mi></math><custom-tag title="t"><a href="/next">n</a></custom-tag>"#);
+        assert!(!output.contains("evil"));
+        assert!(!output.contains("hidden"));
+        assert!(!output.contains("<frame"));
+        assert!(!output.contains("<math"));
+        assert!(!output.contains("<mi"));
+        assert!(!output.contains("custom-tag"));
+        assert!(output.contains(r#"<a href="http://example.com/next">n</a>"#));
+    }
+
+    #[test]
+    fn full_email_document_keeps_the_body_content() {
+        let document = Html::parse_document(r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+                <title>Newsletter</title>
+                <base href="https://evil.example/">
+                <style>p { color: red; }</style>
+            </head>
+            <body bgcolor="#fff">
+                <table><tr><td><h1>Issue 12</h1><p>Read <a href="https://example.com/post">the post</a></p></td></tr></table>
+                <center><font face="Arial">Unsubscribe</font></center>
+            </body>
+            </html>
+        "#);
+        let base_url = Url::parse("about:blank").unwrap();
+        let mut output = String::new();
+        clean_html(document.root_element(), &base_url, &[], &mut output);
+        assert!(!output.contains("Newsletter"));
+        assert!(!output.contains("evil"));
+        assert!(!output.contains("color"));
+        assert!(!output.contains("<body"));
+        assert!(!output.contains("<center"));
+        assert!(!output.contains("<font"));
+        assert!(output.contains("<h1>Issue 12</h1>"));
+        assert!(output.contains(r#"<a href="https://example.com/post">the post</a>"#));
+        assert!(output.contains("Unsubscribe"));
+    }
+
+    #[test]
+    fn scripts_and_markup_in_text_are_escaped() {
+        let output = clean(r#"<script>alert(1)</script><p>&lt;script&gt;alert(1)&lt;/script&gt;</p><svg onload="alert(1)"></svg>"#);
+        assert_eq!(output, "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>");
+    }
+}
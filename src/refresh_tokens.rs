@@ -0,0 +1,207 @@
+use uuid::Uuid;
+use chrono::prelude::*;
+use chrono::{Duration, NaiveDateTime};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use postgres::rows::Row;
+use postgres::types::ToSql;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+
+use errors::*;
+use config::Config;
+use pg::{Insertable, PgDatabase};
+use token::{self, ACCESS_TOKEN_SECONDS};
+use sessions::{Session, ClientInfo};
+use user::User;
+
+/// A session not refreshed for this long has to log in again
+const REFRESH_TOKEN_DAYS: i64 = 30;
+
+/// Refresh token as stored, only its hash is kept
+#[derive(Debug)]
+pub struct RefreshToken {
+    pub uuid: Uuid,
+    pub user_uuid: Uuid,
+    /// Tokens rotated from the same login, the session the access tokens belong to
+    pub family_uuid: Uuid,
+    pub token_hash: String,
+    pub created: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    /// Set when the token was exchanged, a second exchange is a reuse
+    pub used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl RefreshToken {
+    fn new(user_uuid: Uuid, family_uuid: Uuid, token: &str) -> Self {
+        let now = Utc::now().naive_utc();
+        RefreshToken {
+            uuid: Uuid::new_v4(),
+            user_uuid,
+            family_uuid,
+            token_hash: hash_token(token),
+            created: now,
+            expires_at: now + Duration::days(REFRESH_TOKEN_DAYS),
+            used_at: None,
+            revoked_at: None,
+        }
+    }
+}
+
+impl<'a> From<Row<'a>> for RefreshToken {
+    fn from(row: Row) -> Self {
+        RefreshToken {
+            uuid: row.get("uuid"),
+            user_uuid: row.get("user_uuid"),
+            family_uuid: row.get("family_uuid"),
+            token_hash: row.get("token_hash"),
+            created: row.get("created"),
+            expires_at: row.get("expires_at"),
+            used_at: row.get("used_at"),
+            revoked_at: row.get("revoked_at"),
+        }
+    }
+}
+
+impl Insertable for RefreshToken {
+    fn insert_query(&self) -> String {
+        r#"
+            INSERT INTO refresh_tokens (uuid, user_uuid, family_uuid, token_hash, created, expires_at, used_at, revoked_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8);
+        "#.to_owned()
+    }
+
+    fn insert_params(&self) -> Box<[&ToSql]> {
+        Box::new([
+            &self.uuid,
+            &self.user_uuid,
+            &self.family_uuid,
+            &self.token_hash,
+            &self.created,
+            &self.expires_at,
+            &self.used_at,
+            &self.revoked_at,
+        ])
+    }
+}
+
+/// Short lived access token with the refresh token to get the next one
+#[derive(Debug)]
+pub struct AuthTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub session_uuid: Uuid,
+}
+
+graphql_object!(AuthTokens: () as "AuthTokens" |&self| {
+    description: "Access token to authenticate with, and the refresh token to renew it"
+
+    field access_token() -> &String as "Token given to auth" {
+        &self.access_token
+    }
+
+    field refresh_token() -> &String as "Exchanged for new tokens by refreshToken, once" {
+        &self.refresh_token
+    }
+
+    field expires_in() -> i32 as "Seconds the access token is valid" {
+        ACCESS_TOKEN_SECONDS as i32
+    }
+});
+
+/// Stored in place of the tokens, a leaked table does not give working tokens
+pub fn hash_token(token: &str) -> String {
+    let mut sha = Sha256::new();
+    sha.input_str(token);
+    sha.result_str()
+}
+
+fn issue_tokens(pg: &PgDatabase, config: &Config, user: &User, family_uuid: Uuid) -> Result<AuthTokens> {
+    let refresh_token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    pg.insert(&RefreshToken::new(user.uuid, family_uuid, &refresh_token))?;
+    let access_token = token::create_token(user.uuid, user.email.clone(), family_uuid, config.secret_key.as_ref())?;
+    Ok(AuthTokens { access_token, refresh_token, session_uuid: family_uuid })
+}
+
+/// Tokens of a new session, after a login or a signup
+pub fn start_session(pg: &PgDatabase, config: &Config, user: &User, device_label: Option<String>, client: &ClientInfo) -> Result<AuthTokens> {
+    let query = "DELETE FROM refresh_tokens WHERE user_uuid = $1::uuid AND expires_at < $2;";
+    pg.update(query, &[&user.uuid, &Utc::now().naive_utc()])?;
+    let session = Session::new(Uuid::new_v4(), user, device_label, client);
+    pg.insert(&session)?;
+    issue_tokens(pg, config, user, session.uuid)
+}
+
+/// Whether access tokens of the session are still accepted
+pub fn is_session_active(pg: &PgDatabase, family_uuid: &Uuid) -> Result<bool> {
+    let query = r#"
+        SELECT COUNT(*) AS exist FROM refresh_tokens
+        WHERE family_uuid = $1::uuid AND revoked_at IS NULL AND expires_at > $2;
+    "#;
+    Ok(pg.exist(query, &[family_uuid, &Utc::now().naive_utc()])?)
+}
+
+/// Revokes every token of the session
+pub fn revoke_family(pg: &PgDatabase, family_uuid: &Uuid, user_uuid: &Uuid) -> Result<u64> {
+    let query = r#"
+        UPDATE refresh_tokens SET revoked_at = $1
+        WHERE family_uuid = $2::uuid AND user_uuid = $3::uuid AND revoked_at IS NULL;
+    "#;
+    Ok(pg.update(query, &[&Utc::now().naive_utc(), family_uuid, user_uuid])?)
+}
+
+/// Revokes every session of the user, returns how many were active
+pub fn revoke_all_families(pg: &PgDatabase, user_uuid: &Uuid) -> Result<i64> {
+    let query = r#"
+        SELECT COUNT(DISTINCT family_uuid) AS total FROM refresh_tokens
+        WHERE user_uuid = $1::uuid AND revoked_at IS NULL AND expires_at > $2;
+    "#;
+    let active = pg.total(query, &[user_uuid, &Utc::now().naive_utc()])?;
+    let query = "UPDATE refresh_tokens SET revoked_at = $1 WHERE user_uuid = $2::uuid AND revoked_at IS NULL;";
+    pg.update(query, &[&Utc::now().naive_utc(), user_uuid])?;
+    Ok(active)
+}
+
+/// Revokes every session of the user but the one given, returns how many were revoked
+pub fn revoke_other_families(pg: &PgDatabase, user_uuid: &Uuid, kept_family_uuid: &Uuid) -> Result<u64> {
+    let query = r#"
+        UPDATE refresh_tokens SET revoked_at = $1
+        WHERE user_uuid = $2::uuid AND family_uuid <> $3::uuid AND revoked_at IS NULL;
+    "#;
+    Ok(pg.update(query, &[&Utc::now().naive_utc(), user_uuid, kept_family_uuid])?)
+}
+
+/// Exchanges a refresh token for new tokens of the same session.
+/// A token exchanged twice was stolen or leaked, the whole session is revoked.
+pub fn rotate(pg: &PgDatabase, config: &Config, refresh_token: &str) -> Result<AuthTokens> {
+    let query = "SELECT * FROM refresh_tokens WHERE token_hash = $1;";
+    let stored: RefreshToken = pg.find_one(query, &[&hash_token(refresh_token)])?.ok_or(ErrorKind::WrongCredentials)?;
+    if stored.revoked_at.is_some() || stored.expires_at < Utc::now().naive_utc() {
+        return Err(ErrorKind::WrongCredentials.into());
+    }
+    let query = "UPDATE refresh_tokens SET used_at = $1 WHERE uuid = $2::uuid AND used_at IS NULL;";
+    if stored.used_at.is_some() || pg.update(query, &[&Utc::now().naive_utc(), &stored.uuid])? == 0 {
+        println!("refresh token reuse, session {:?} revoked", stored.family_uuid);
+        revoke_family(pg, &stored.family_uuid, &stored.user_uuid)?;
+        return Err(ErrorKind::WrongCredentials.into());
+    }
+    let user: User = pg.find_one("SELECT * FROM users WHERE uuid = $1::uuid;", &[&stored.user_uuid])?.ok_or(ErrorKind::WrongCredentials)?;
+    issue_tokens(pg, config, &user, stored.family_uuid)
+}
+
+pub fn refresh_token_resolver(pool: Pool<PostgresConnectionManager>, config: &Config, refresh_token: &str) -> Result<AuthTokens> {
+    let pg = PgDatabase::from_pool(pool)?;
+    rotate(&pg, config, refresh_token)
+}
+
+pub fn logout_resolver(pool: Pool<PostgresConnectionManager>, session_uuid: &Uuid, user: &User) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pool)?;
+    revoke_family(&pg, session_uuid, &user.uuid)?;
+    Ok(true)
+}
+
+pub fn revoke_all_sessions_resolver(pool: Pool<PostgresConnectionManager>, user: &User) -> Result<i32> {
+    let pg = PgDatabase::from_pool(pool)?;
+    Ok(revoke_all_families(&pg, &user.uuid)? as i32)
+}
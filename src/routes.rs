@@ -1,10 +1,28 @@
+use std::fs::File;
+use std::io::{Cursor, Read};
 use std::path::{Path, PathBuf};
 
-use rocket::response::{NamedFile, content};
-use rocket::State;
+use rocket::response::{NamedFile, Response, content};
+use rocket::http::{ContentType, Status};
+use rocket::{Data, State};
 use juniper_rocket;
 
 use graphql::query::{Schema, Query};
+use pg::{DbConn, PgDatabase};
+use websub::{self, HubSignature};
+use token::AuthData;
+use sessions::ClientInfo;
+use users_repository;
+use opml::export_opml;
+use data_export::find_export_file;
+use config::Config;
+use webhook;
+use errors::{Error, ErrorKind};
+
+/// Pushed feed documents bigger than this are rejected
+const MAX_PUSH_SIZE: u64 = 5 * 1024 * 1024;
+/// Webhook payloads bigger than this are rejected
+const MAX_WEBHOOK_SIZE: u64 = 1024 * 1024;
 
 #[get("/graphql")]
 pub fn graphiql() -> content::Html<String> {
@@ -14,10 +32,11 @@ pub fn graphiql() -> content::Html<String> {
 #[post("/graphql", data="<request>")]
 pub fn post_graphql_handler(
     context: State<Query>,
+    client: ClientInfo,
     request: juniper_rocket::GraphQLRequest,
     schema: State<Schema>
 ) -> juniper_rocket::GraphQLResponse {
-    request.execute(&schema, &context)
+    request.execute(&schema, &context.with_client(client))
 }
 
 #[get("/")]
@@ -31,3 +50,95 @@ fn files(file: PathBuf) -> Option<NamedFile> {
     let path = Path::new("./static/").join(file);
     NamedFile::open(path).ok()
 }
+
+#[derive(FromForm)]
+pub struct HubVerification {
+    #[form(field = "hub.mode")]
+    mode: String,
+    #[form(field = "hub.topic")]
+    topic: String,
+    #[form(field = "hub.challenge")]
+    challenge: Option<String>,
+    #[form(field = "hub.lease_seconds")]
+    lease_seconds: Option<i32>,
+}
+
+#[get("/websub/callback/<uuid>?<verification>")]
+pub fn websub_verify(conn: DbConn, uuid: String, verification: HubVerification) -> Option<String> {
+    let pg: PgDatabase = conn.into();
+    websub::verify_intent(&pg, &uuid, &verification.mode, &verification.topic, verification.challenge, verification.lease_seconds)
+        .ok()
+        .and_then(|challenge| challenge)
+}
+
+/// Reads the whole body, larger ones are refused rather than truncated
+fn read_body(body: Data, max_size: u64) -> Result<Vec<u8>, Status> {
+    let mut payload = Vec::new();
+    body.open().take(max_size + 1).read_to_end(&mut payload).map_err(|_| Status::BadRequest)?;
+    if payload.len() as u64 > max_size {
+        return Err(Status::PayloadTooLarge);
+    }
+    Ok(payload)
+}
+
+#[post("/websub/callback/<uuid>", data = "<body>")]
+pub fn websub_push(conn: DbConn, signature: HubSignature, uuid: String, body: Data) -> Status {
+    let payload = match read_body(body, MAX_PUSH_SIZE) {
+        Ok(payload) => payload,
+        Err(status) => return status,
+    };
+    let pg: PgDatabase = conn.into();
+    match websub::process_push(&pg, &uuid, signature.0.as_ref(), &payload) {
+        Ok(_) => Status::Ok,
+        Err(error) => {
+            println!("websub push error {:?}", error);
+            Status::NotFound
+        }
+    }
+}
+
+#[post("/webhooks/<token>", data = "<body>")]
+pub fn webhook_push(conn: DbConn, token: String, body: Data) -> Status {
+    let payload = match read_body(body, MAX_WEBHOOK_SIZE) {
+        Ok(payload) => payload,
+        Err(status) => return status,
+    };
+    let pg: PgDatabase = conn.into();
+    match webhook::process_webhook(&pg, &token, &payload) {
+        Ok(_) => Status::Accepted,
+        Err(Error(ErrorKind::NotFound, _)) => Status::NotFound,
+        Err(Error(ErrorKind::Validation(_), _)) => Status::UnprocessableEntity,
+        Err(Error(ErrorKind::Json(_), _)) => Status::BadRequest,
+        Err(error) => {
+            println!("webhook push error {:?}", error);
+            Status::InternalServerError
+        }
+    }
+}
+
+#[get("/opml/export")]
+pub fn opml_export(auth: AuthData, context: State<Query>) -> Result<Response<'static>, Status> {
+    let connection = context.diesel_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let user = users_repository::find_by_email(&connection, &auth.email).map_err(|_| Status::Unauthorized)?;
+    let pg = PgDatabase::from_pool(context.connection.clone()).map_err(|_| Status::ServiceUnavailable)?;
+    let opml = export_opml(&pg, &user).map_err(|_| Status::InternalServerError)?;
+    Response::build()
+        .header(ContentType::new("text", "x-opml"))
+        .raw_header("Content-Disposition", "attachment; filename=\"mindstream.opml\"")
+        .sized_body(Cursor::new(opml))
+        .ok()
+}
+
+#[get("/exports/<uuid>")]
+pub fn data_export_download(auth: AuthData, uuid: String, context: State<Query>, config: State<Config>) -> Result<Response<'static>, Status> {
+    let connection = context.diesel_pool.get().map_err(|_| Status::ServiceUnavailable)?;
+    let user = users_repository::find_by_email(&connection, &auth.email).map_err(|_| Status::Unauthorized)?;
+    let pg = PgDatabase::from_pool(context.connection.clone()).map_err(|_| Status::ServiceUnavailable)?;
+    let path = find_export_file(&pg, &config.export_dir, &uuid, &user).map_err(|_| Status::NotFound)?;
+    let file = File::open(path).map_err(|_| Status::NotFound)?;
+    Response::build()
+        .header(ContentType::new("application", "zip"))
+        .raw_header("Content-Disposition", "attachment; filename=\"mindstream-export.zip\"")
+        .sized_body(file)
+        .ok()
+}
@@ -1,66 +1,293 @@
+use std::io::Read;
+use std::str;
 use std::thread;
-use std::time::Duration;
+use std::cmp;
+use std::time::{Duration, Instant, SystemTime};
 
 use r2d2::Pool;
 use r2d2_postgres::PostgresConnectionManager;
-use reqwest;
-use reqwest::Client;
+use chrono::NaiveDateTime;
+use chrono::prelude::*;
+use reqwest::{Client, StatusCode};
+use reqwest::header::{Headers, ETag, LastModified, IfNoneMatch, IfModifiedSince, EntityTag, HttpDate, CacheControl, CacheDirective};
 use feed_rs::parser;
 use feed_rs::feed::{Feed as RssFeed};
 
 use errors::*;
-use feeds::{is_feed_exist, insert_feed, Feed};
+use feeds::{is_feed_exist, insert_feed, Feed, Rss};
 use source::Source;
-use sources::find_rss_sources;
-use source_option::{SourceOption, RssSource };
+use sources::{find_due_sources, update_rss_validators, record_fetch_success, record_fetch_failure};
+use source_option::{SourceOption, RssSource, ExtractorOption};
 use users_sources::find_users_by_source;
-use users_feeds::{UserFeed, Reaction, is_user_feed_already_inserted};
-use mercury::{fetch_readable};
+use users_feeds::{UserFeed, is_user_feed_already_inserted};
+use filter_rules::{FilterItem, SubscriberRules, find_enabled_rules};
+use extraction_jobs::enqueue_extraction;
 use user::User;
 use pg::PgDatabase;
+use worker_pool::{run_pool, host_of};
+use feed_meta::{FeedMeta, parse_feed_meta};
+use schedule::{self, ScheduleHints, MAX_HINTED_FETCH_INTERVAL};
+use websub;
+use json_feed::{is_json_feed, parse_json_feed};
+use podcast::{ItemMedia, parse_item_media};
+use twitter::{HttpTwitterClient, process_twitter_source};
+use mastodon::process_mastodon_source;
+use web_page::process_web_page_source;
+use config::CONFIG;
 
-pub fn fetch_feeds_channel(url: &str) -> Result<Option<RssFeed>> {
-    let mut response = reqwest::get(url)?;
-    let feed = parser::parse(&mut response);
-    Ok(feed)
+pub enum FeedsChannel {
+    Modified {
+        status: i32,
+        feed: Channel,
+        meta: FeedMeta,
+        max_age: Option<i64>,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+    NotModified {
+        max_age: Option<i64>,
+    },
 }
 
-pub fn run_rss_job(rss_job_interval: Duration, client: Client, pool: Pool<PostgresConnectionManager>) {
+/// Parsed feed document, whatever its format
+#[derive(Debug)]
+pub struct Channel {
+    pub title: Option<String>,
+    pub website: Option<String>,
+    pub items: Vec<ChannelItem>,
+}
+
+/// An entry with the urls it is published at
+#[derive(Debug)]
+pub struct ChannelItem {
+    pub links: Vec<String>,
+    pub published: NaiveDateTime,
+    pub rss: Rss,
+}
+
+impl From<RssFeed> for Channel {
+    fn from(feed: RssFeed) -> Self {
+        Channel {
+            title: feed.title,
+            website: feed.website,
+            items: feed.entries.into_iter().map(|entry| ChannelItem {
+                links: entry.alternate.iter().map(|link| link.href.clone()).collect(),
+                published: entry.published,
+                rss: entry.into(),
+            }).collect(),
+        }
+    }
+}
+
+/// Parses RSS, Atom and JSON Feed documents
+pub fn parse_channel(document: &str) -> Result<(Channel, FeedMeta)> {
+    if is_json_feed(document) {
+        return parse_json_feed(document);
+    }
+    let feed = parser::parse(&mut document.as_bytes()).ok_or_else(|| ErrorKind::InvalidFeed)?;
+    let mut channel: Channel = feed.into();
+    attach_item_media(&mut channel, parse_item_media(document));
+    Ok((channel, parse_feed_meta(document)))
+}
+
+/// feed_rs drops enclosures, they are read from the document and matched to the items by guid or link,
+/// or by position when nothing matches
+fn attach_item_media(channel: &mut Channel, medias: Vec<ItemMedia>) {
+    let same_count = medias.len() == channel.items.len();
+    let mut medias: Vec<Option<ItemMedia>> = medias.into_iter().map(Some).collect();
+    for (index, item) in channel.items.iter_mut().enumerate() {
+        let position = medias.iter().position(|media| match *media {
+            Some(ref media) => media.keys.iter().any(|key| key == &item.rss.id || item.links.contains(key)),
+            None => false,
+        });
+        let media = match position {
+            Some(position) => medias[position].take(),
+            None if same_count => medias[index].take(),
+            None => None,
+        };
+        if let Some(media) = media {
+            item.rss.enclosure = media.enclosures.first().map(|enclosure| enclosure.url.clone());
+            item.rss.enclosures = media.enclosures;
+            item.rss.podcast = media.podcast;
+        }
+    }
+}
+
+fn cache_max_age(headers: &Headers) -> Option<i64> {
+    headers.get::<CacheControl>().and_then(|cache_control| {
+        cache_control.iter().filter_map(|directive| match *directive {
+            CacheDirective::MaxAge(seconds) => Some(seconds as i64),
+            _ => None,
+        }).next()
+    })
+}
+
+/// `Retry-After` in seconds, it is either a delay or an http date
+fn retry_after(headers: &Headers) -> Option<i64> {
+    let value = headers.get_raw("Retry-After")
+        .and_then(|raw| raw.one())
+        .and_then(|bytes| str::from_utf8(bytes).ok())?
+        .trim();
+    match value.parse::<i64>() {
+        Ok(seconds) => Some(seconds),
+        Err(_) => value.parse::<HttpDate>().ok().map(|date| {
+            let date: SystemTime = date.into();
+            date.duration_since(SystemTime::now()).map(|delay| delay.as_secs() as i64).unwrap_or(0)
+        }),
+    }
+}
+
+/// Fetch the feed sending the validators of the previous fetch, publishers answer 304 when nothing changed.
+pub fn fetch_feeds_channel_conditional(client: &Client, url: &str, etag: &Option<String>, last_modified: &Option<String>) -> Result<FeedsChannel> {
+    let mut request = client.get(url);
+    if let Some(entity_tag) = etag.as_ref().and_then(|etag| etag.parse::<EntityTag>().ok()) {
+        request.header(IfNoneMatch::Items(vec![entity_tag]));
+    }
+    if let Some(date) = last_modified.as_ref().and_then(|date| date.parse::<HttpDate>().ok()) {
+        request.header(IfModifiedSince(date));
+    }
+    let mut response = request.send()?;
+    let max_age = cache_max_age(response.headers());
+    if response.status() == StatusCode::NotModified {
+        return Ok(FeedsChannel::NotModified { max_age });
+    }
+    if !response.status().is_success() {
+        bail!(ErrorKind::HttpStatus(response.status().as_u16(), retry_after(response.headers())));
+    }
+    let status = response.status().as_u16() as i32;
+    let etag = response.headers().get::<ETag>().map(|etag| etag.0.to_string());
+    let last_modified = response.headers().get::<LastModified>().map(|date| date.0.to_string());
+    let mut document = String::new();
+    response.read_to_string(&mut document)?;
+    let (feed, meta) = parse_channel(&document)?;
+    Ok(FeedsChannel::Modified { status, feed, meta, max_age, etag, last_modified })
+}
+
+pub fn run_rss_job(rss_job_interval: Duration, workers: usize, max_per_host: usize, client: Client, pool: Pool<PostgresConnectionManager>) {
     thread::spawn(move || {
         loop {
-            if let Err(err) = process_feeds(&client, &pool) {
+            let started = Instant::now();
+            if let Err(err) = process_feeds(&client, &pool, workers, max_per_host) {
                 println!("process_rss error {:?}", err);
             }
-            thread::sleep(rss_job_interval);
+            let elapsed = started.elapsed();
+            if elapsed < rss_job_interval {
+                thread::sleep(rss_job_interval - elapsed);
+            } else {
+                println!("process_rss took {:?}, longer than the {:?} interval", elapsed, rss_job_interval);
+            }
         }
     });
 }
 
-fn process_feeds(client: &Client, pool: &Pool<PostgresConnectionManager>) -> Result<()> {
-    let conn = pool.get()?;
-    let pg = PgDatabase::new(conn);
-    let sources = find_rss_sources(&pg, i32::max_value(), 0)?;
-    for source in sources {
-        let subscribers = find_users_by_source(&pg, &source)?;
-        match source.options()? {
-            SourceOption::Rss(rss_source) => {
-                process_rss_source(&subscribers, &source, &rss_source, client, &pg)?;
-            },
-            SourceOption::Twitter(_) => {}
+fn process_feeds(client: &Client, pool: &Pool<PostgresConnectionManager>, workers: usize, max_per_host: usize) -> Result<()> {
+    let sources = {
+        let pg = PgDatabase::from_pool(pool.clone())?;
+        if let Err(err) = websub::renew_subscriptions(client, &pg) {
+            println!("websub renew error {:?}", err);
         }
+        find_due_sources(&pg, &Utc::now().naive_utc(), i32::max_value(), 0)?
+    };
+    let jobs = sources.into_iter().map(|source| (source_host(&source), source)).collect();
+    let client = client.clone();
+    let pool = pool.clone();
+    run_pool(jobs, workers, max_per_host, move |source: Source| {
+        if let Err(err) = process_source(&client, &pool, &source) {
+            println!("process_source {:?} error {:?}", source.uuid, err);
+        }
+    });
+    Ok(())
+}
+
+fn source_host(source: &Source) -> String {
+    match source.options() {
+        Ok(SourceOption::Rss(rss_source)) => host_of(&rss_source.xml_url),
+        Ok(SourceOption::Twitter(_)) => host_of(&CONFIG.twitter_api_url),
+        Ok(SourceOption::Mastodon(mastodon_source)) => host_of(&mastodon_source.instance_url),
+        Ok(SourceOption::WebPage(web_page_source)) => host_of(&web_page_source.url),
+        _ => String::new(),
+    }
+}
+
+fn process_source(client: &Client, pool: &Pool<PostgresConnectionManager>, source: &Source) -> Result<()> {
+    let pg = PgDatabase::from_pool(pool.clone())?;
+    let subscribers = find_users_by_source(&pg, source)?;
+    match source.options()? {
+        SourceOption::Rss(rss_source) => {
+            process_rss_source(&subscribers, source, &rss_source, client, &pg)?;
+        },
+        SourceOption::Twitter(twitter_source) => {
+            match HttpTwitterClient::from_config(client) {
+                Some(twitter_client) => process_twitter_source(&subscribers, source, &twitter_source, &twitter_client, &pg)?,
+                // Still scheduled, backing off until the source gets disabled
+                None => {
+                    record_fetch_failure(&pg, source, None, "TWITTER_BEARER_TOKEN is not configured", None)?;
+                },
+            }
+        },
+        SourceOption::Mastodon(mastodon_source) => {
+            process_mastodon_source(&subscribers, source, &mastodon_source, client, &pg)?;
+        },
+        SourceOption::WebPage(web_page_source) => {
+            process_web_page_source(&subscribers, source, &web_page_source, client, &pg)?;
+        },
+        // Pushed by the inbound mail listeners and the webhook route, never polled
+        SourceOption::Email(_) | SourceOption::Webhook(_) => {},
     }
     Ok(())
 }
 
 fn process_rss_source(subscribers: &Vec<User>, source: &Source, rss_source: &RssSource, client: &Client, pg: &PgDatabase) -> Result<()> {
-    if let Ok(Some(feeds_channel)) = fetch_feeds_channel(&rss_source.xml_url) {
-        for rss_feed in &feeds_channel.entries {
-            for link in &rss_feed.alternate {
-                if !is_feed_exist(&pg, &link.href, source)? {
-                    let readable = fetch_readable(client, &link.href).ok().and_then(|readable| readable);
-                    let feed = Feed::new(&link.href, Some(rss_feed.clone().into()), readable, None, source.uuid);
-                    if insert_feed(&pg, &feed).is_ok() {
-                        insert_subscribers_feeds(subscribers, &feed, pg)?;
+    match fetch_feeds_channel_conditional(client, &rss_source.xml_url, &rss_source.etag, &rss_source.last_modified) {
+        Ok(FeedsChannel::NotModified { max_age }) => {
+            let fetch_interval = schedule::not_modified_interval(source.fetch_interval.map(|interval| interval as i64), max_age);
+            record_fetch_success(pg, source, 304, fetch_interval)?;
+        },
+        Ok(FeedsChannel::Modified { status, feed: feeds_channel, meta, max_age, etag, last_modified }) => {
+            ingest_rss_entries(subscribers, source, rss_source, &feeds_channel.items, pg)?;
+            if etag != rss_source.etag || last_modified != rss_source.last_modified {
+                update_rss_validators(pg, source, &etag, &last_modified)?;
+            }
+            let published: Vec<_> = feeds_channel.items.iter().map(|item| item.published).collect();
+            let mut fetch_interval = schedule::fetch_interval(schedule::estimate_interval(&published), &ScheduleHints::new(&meta, max_age));
+            if let Some(ref hub) = meta.hub {
+                let topic = meta.self_url.clone().unwrap_or_else(|| rss_source.xml_url.clone());
+                if let Err(error) = websub::subscribe(client, pg, source, hub, &topic) {
+                    println!("websub subscribe {} error {}", topic, error);
+                }
+            }
+            if websub::has_active_subscription(pg, source)? {
+                fetch_interval = cmp::max(fetch_interval, MAX_HINTED_FETCH_INTERVAL);
+            }
+            record_fetch_success(pg, source, status, fetch_interval)?;
+        },
+        Err(error) => {
+            println!("fetch {} error {}", rss_source.xml_url, error);
+            let retry_after = match *error.kind() {
+                ErrorKind::HttpStatus(_, retry_after) => retry_after,
+                _ => None,
+            };
+            record_fetch_failure(pg, source, error_status(&error), &error.to_string(), retry_after)?;
+        },
+    }
+    Ok(())
+}
+
+/// Inserts the entries not seen yet and fans them out to the subscribers, shared by polling and WebSub pushes,
+/// readable content is filled in later by the extraction queue
+pub fn ingest_rss_entries(subscribers: &Vec<User>, source: &Source, rss_source: &RssSource, items: &Vec<ChannelItem>, pg: &PgDatabase) -> Result<()> {
+    let filter_rules = find_enabled_rules(pg, subscribers)?;
+    let rules = SubscriberRules::new(&filter_rules);
+    for item in items {
+        for link in &item.links {
+            if !is_feed_exist(&pg, link, source)? {
+                let feed = Feed::new(link, Some(item.rss.clone()), None, None, None, source.uuid);
+                if insert_feed(&pg, &feed).is_ok() {
+                    insert_subscribers_feeds(subscribers, &rules, &feed, pg)?;
+                    if rss_source.extractor != ExtractorOption::None {
+                        if let Err(error) = enqueue_extraction(pg, &feed) {
+                            println!("extraction enqueue {:?} error {}", feed.uuid, error);
+                        }
                     }
                 }
             }
@@ -69,9 +296,24 @@ fn process_rss_source(subscribers: &Vec<User>, source: &Source, rss_source: &Rss
     Ok(())
 }
 
-fn insert_subscribers_feeds(subscribers: &Vec<User>, feed: &Feed, pg: &PgDatabase) -> Result<()> {
+pub fn error_status(error: &Error) -> Option<i32> {
+    match *error.kind() {
+        ErrorKind::HttpStatus(status, _) => Some(status as i32),
+        ErrorKind::WS(ref error) => error.status().map(|status| status.as_u16() as i32),
+        _ => None,
+    }
+}
+
+/// Adds the feed to the stream of the subscribers, their filter rules can skip it, react to it or tag it
+pub fn insert_subscribers_feeds(subscribers: &Vec<User>, rules: &SubscriberRules, feed: &Feed, pg: &PgDatabase) -> Result<()> {
+    let item = FilterItem::from(feed);
     for subscriber in subscribers {
-        let user_feed = UserFeed::new(subscriber.uuid, feed.uuid.clone(), Reaction::Unreaded);
+        let verdict = rules.verdict(subscriber, &item);
+        if verdict.skip {
+            continue;
+        }
+        let mut user_feed = UserFeed::new(subscriber.uuid, feed.uuid.clone(), verdict.reaction);
+        user_feed.tags = verdict.tags;
         if !is_user_feed_already_inserted(pg, &feed.url, &subscriber)? {
             if pg.insert(&user_feed).is_ok() {
                 println!("insert subscriber {:?} -> {:?}", &feed.url, subscriber.login);
@@ -0,0 +1,136 @@
+use std::cmp;
+use chrono::NaiveDateTime;
+
+use feed_meta::FeedMeta;
+
+pub const MIN_FETCH_INTERVAL: i64 = 5 * 60;
+pub const MAX_FETCH_INTERVAL: i64 = 6 * 60 * 60;
+/// Publisher hints may push the interval further than our own estimate, but not past a day
+pub const MAX_HINTED_FETCH_INTERVAL: i64 = 24 * 60 * 60;
+pub const DEFAULT_FETCH_INTERVAL: i64 = 60 * 60;
+const RECENT_ENTRIES: usize = 10;
+
+/// Publisher provided lower bounds on the polling interval, in seconds
+#[derive(Debug, Default)]
+pub struct ScheduleHints {
+    pub ttl: Option<i64>,
+    pub update_period: Option<i64>,
+    pub max_age: Option<i64>,
+}
+
+impl ScheduleHints {
+    pub fn new(meta: &FeedMeta, max_age: Option<i64>) -> Self {
+        ScheduleHints {
+            ttl: meta.ttl.map(|minutes| minutes * 60),
+            update_period: meta.update_period.as_ref().and_then(|period| update_period_seconds(period, meta.update_frequency)),
+            max_age,
+        }
+    }
+
+    fn max(&self) -> Option<i64> {
+        vec![self.ttl, self.update_period, self.max_age].into_iter().filter_map(|hint| hint).max()
+    }
+}
+
+fn update_period_seconds(period: &str, frequency: Option<i64>) -> Option<i64> {
+    let period = match period.trim() {
+        "hourly" => 60 * 60,
+        "daily" => 24 * 60 * 60,
+        "weekly" => 7 * 24 * 60 * 60,
+        "monthly" => 30 * 24 * 60 * 60,
+        "yearly" => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(period / cmp::max(frequency.unwrap_or(1), 1))
+}
+
+/// Half the mean gap between the most recent entries, so we usually pick up new items well before the next one lands
+pub fn estimate_interval(published: &[NaiveDateTime]) -> Option<i64> {
+    let mut dates = published.to_vec();
+    dates.sort_by(|a, b| b.cmp(a));
+    dates.truncate(RECENT_ENTRIES);
+    if dates.len() < 2 {
+        return None;
+    }
+    let span = dates[0].signed_duration_since(dates[dates.len() - 1]).num_seconds();
+    Some(span / (dates.len() as i64 - 1) / 2)
+}
+
+fn clamp(interval: i64, min: i64, max: i64) -> i64 {
+    cmp::min(cmp::max(interval, min), max)
+}
+
+/// Seconds to wait before fetching a feed again
+pub fn fetch_interval(estimate: Option<i64>, hints: &ScheduleHints) -> i64 {
+    let estimated = clamp(estimate.unwrap_or(DEFAULT_FETCH_INTERVAL), MIN_FETCH_INTERVAL, MAX_FETCH_INTERVAL);
+    match hints.max() {
+        Some(hinted) if hinted > estimated => cmp::min(hinted, MAX_HINTED_FETCH_INTERVAL),
+        _ => estimated,
+    }
+}
+
+/// On a 304 there is nothing new to learn from, keep the previous interval unless the cache headers ask for more
+pub fn not_modified_interval(previous: Option<i64>, max_age: Option<i64>) -> i64 {
+    let previous = previous.unwrap_or(DEFAULT_FETCH_INTERVAL);
+    clamp(cmp::max(previous, max_age.unwrap_or(0)), MIN_FETCH_INTERVAL, MAX_HINTED_FETCH_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn hours_ago(hours: &[i64]) -> Vec<NaiveDateTime> {
+        let now = NaiveDateTime::from_timestamp(1_500_000_000, 0);
+        hours.iter().map(|hours| now - Duration::hours(*hours)).collect()
+    }
+
+    #[test]
+    fn estimate_is_half_the_mean_gap_of_recent_entries() {
+        assert_eq!(estimate_interval(&hours_ago(&[4, 0, 2])), Some(60 * 60));
+        assert_eq!(estimate_interval(&hours_ago(&[0])), None);
+        assert_eq!(estimate_interval(&[]), None);
+    }
+
+    #[test]
+    fn estimate_only_looks_at_the_recent_entries() {
+        let mut hours: Vec<i64> = (0..10).collect();
+        hours.push(1000);
+        assert_eq!(estimate_interval(&hours_ago(&hours)), Some(30 * 60));
+    }
+
+    #[test]
+    fn interval_is_clamped() {
+        let hints = ScheduleHints::default();
+        assert_eq!(fetch_interval(None, &hints), DEFAULT_FETCH_INTERVAL);
+        assert_eq!(fetch_interval(Some(10), &hints), MIN_FETCH_INTERVAL);
+        assert_eq!(fetch_interval(Some(MAX_FETCH_INTERVAL * 10), &hints), MAX_FETCH_INTERVAL);
+    }
+
+    #[test]
+    fn hints_only_lengthen_the_interval_up_to_a_day() {
+        let hints = ScheduleHints { ttl: Some(2 * 60 * 60), ..ScheduleHints::default() };
+        assert_eq!(fetch_interval(Some(30 * 60), &hints), 2 * 60 * 60);
+        assert_eq!(fetch_interval(Some(3 * 60 * 60), &hints), 3 * 60 * 60);
+        let hints = ScheduleHints { max_age: Some(7 * 24 * 60 * 60), ..ScheduleHints::default() };
+        assert_eq!(fetch_interval(None, &hints), MAX_HINTED_FETCH_INTERVAL);
+    }
+
+    #[test]
+    fn hints_from_feed_meta() {
+        let meta = FeedMeta { ttl: Some(90), update_period: Some("daily".to_owned()), update_frequency: Some(4), ..FeedMeta::default() };
+        let hints = ScheduleHints::new(&meta, None);
+        assert_eq!(hints.ttl, Some(90 * 60));
+        assert_eq!(hints.update_period, Some(6 * 60 * 60));
+        assert_eq!(update_period_seconds("sometimes", None), None);
+        assert_eq!(update_period_seconds("hourly", Some(0)), Some(60 * 60));
+    }
+
+    #[test]
+    fn not_modified_keeps_the_previous_interval() {
+        assert_eq!(not_modified_interval(Some(2 * 60 * 60), None), 2 * 60 * 60);
+        assert_eq!(not_modified_interval(Some(2 * 60 * 60), Some(3 * 60 * 60)), 3 * 60 * 60);
+        assert_eq!(not_modified_interval(None, None), DEFAULT_FETCH_INTERVAL);
+        assert_eq!(not_modified_interval(Some(1), None), MIN_FETCH_INTERVAL);
+    }
+}
@@ -1,6 +1,6 @@
 table! {
     use source_type::SqlSourceType;
-    use diesel::sql_types::{Nullable, Text, Timestamp, Jsonb, Uuid};
+    use diesel::sql_types::{Nullable, Text, Timestamp, Jsonb, Uuid, Integer, Bool};
     sources (uuid) {
         uuid -> Uuid,
         source_type -> SqlSourceType,
@@ -8,6 +8,13 @@ table! {
         error -> Nullable<Text>,
         created -> Nullable<Timestamp>,
         updated -> Nullable<Timestamp>,
+        last_success -> Nullable<Timestamp>,
+        last_failure -> Nullable<Timestamp>,
+        failure_count -> Integer,
+        last_status -> Nullable<Integer>,
+        next_fetch_at -> Nullable<Timestamp>,
+        disabled -> Bool,
+        fetch_interval -> Nullable<Integer>,
     }
 }
 
@@ -19,5 +26,6 @@ table! {
         password -> Text,
         created -> Nullable<Timestamp>,
         updated -> Nullable<Timestamp>,
+        email_verified -> Bool,
     }
 }
@@ -0,0 +1,259 @@
+use std::str::FromStr;
+use uuid::Uuid;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use postgres::rows::Row;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use serde_json::Value;
+use whatlang;
+
+use errors::*;
+use graphql::query::Query;
+use pg::PgDatabase;
+use feeds::{Feed, Rss};
+use users_feeds::Reaction;
+use readability::{escape_text, text_of};
+use user::User;
+
+/// Configuration of feeds whose language is unknown, words are indexed without stemming
+const DEFAULT_SEARCH_CONFIG: &str = "simple";
+/// Private use characters ts_headline marks the matches with, replaced by `<mark>` once the text is escaped
+const MARK_START: char = '\u{E000}';
+const MARK_STOP: char = '\u{E001}';
+/// Shorter texts are not detected, a title alone often looks like another language
+const MIN_DETECTION_LENGTH: usize = 40;
+/// Every configuration `search_config` can give a feed
+const SEARCH_CONFIGS: [&str; 16] = [
+    DEFAULT_SEARCH_CONFIG, "danish", "german", "english", "spanish", "finnish", "french", "hungarian",
+    "italian", "dutch", "norwegian", "portuguese", "romanian", "russian", "swedish", "turkish",
+];
+
+/// Text search configuration shipped with Postgres for an ISO 639-1 or 639-3 code
+fn language_config(code: &str) -> Option<&'static str> {
+    match code.to_lowercase().as_str() {
+        "da" | "dan" => Some("danish"),
+        "de" | "deu" => Some("german"),
+        "en" | "eng" => Some("english"),
+        "es" | "spa" => Some("spanish"),
+        "fi" | "fin" => Some("finnish"),
+        "fr" | "fra" => Some("french"),
+        "hu" | "hun" => Some("hungarian"),
+        "it" | "ita" => Some("italian"),
+        "nl" | "nld" => Some("dutch"),
+        "no" | "nb" | "nob" => Some("norwegian"),
+        "pt" | "por" => Some("portuguese"),
+        "ro" | "ron" => Some("romanian"),
+        "ru" | "rus" => Some("russian"),
+        "sv" | "swe" => Some("swedish"),
+        "tr" | "tur" => Some("turkish"),
+        _ => None,
+    }
+}
+
+/// Language declared by mastodon statuses, detected from the text of the other items
+pub fn search_config(rss: &Option<Rss>, mastodon: &Option<Value>) -> &'static str {
+    let declared = mastodon.as_ref()
+        .and_then(|mastodon| mastodon.get("language"))
+        .and_then(|language| language.as_str())
+        .and_then(language_config);
+    if let Some(config) = declared {
+        return config;
+    }
+    let text = match *rss {
+        Some(ref rss) => {
+            let parts: Vec<String> = vec![&rss.title, &rss.summary, &rss.content].into_iter()
+                .filter_map(|part| part.as_ref().map(|part| text_of(part)))
+                .collect();
+            parts.join(" ")
+        },
+        None => return DEFAULT_SEARCH_CONFIG,
+    };
+    if text.len() < MIN_DETECTION_LENGTH {
+        return DEFAULT_SEARCH_CONFIG;
+    }
+    whatlang::detect(&text)
+        .and_then(|info| language_config(info.lang().code()))
+        .unwrap_or(DEFAULT_SEARCH_CONFIG)
+}
+
+/// Terms parsed with every search configuration at once, the query is the same for every feed so
+/// `search_vector @@` can use the GIN index instead of building a query per row
+pub fn search_tsquery(terms: &str) -> String {
+    let queries: Vec<String> = SEARCH_CONFIGS.iter()
+        .map(|config| format!("plainto_tsquery('{}', {})", config, terms))
+        .collect();
+    format!("({})", queries.join(" || "))
+}
+
+/// Feed matching a search, with its rank and the matching words highlighted
+#[derive(Debug)]
+pub struct SearchResult {
+    pub feed: Feed,
+    pub reaction: Reaction,
+    pub rank: f32,
+    /// Fragments of the text around the matches, between `<mark>` tags
+    pub headline: Option<String>,
+}
+
+/// The few entities left by stripping the tags in Postgres, the text is escaped again afterwards
+fn decode_entities(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Html of a headline built from plain text, only the `<mark>` tags are markup
+fn highlight(headline: &str) -> String {
+    escape_text(&decode_entities(headline))
+        .replace(MARK_START, "<mark>")
+        .replace(MARK_STOP, "</mark>")
+}
+
+impl<'a> From<Row<'a>> for SearchResult {
+    fn from(row: Row) -> Self {
+        let headline: Option<String> = row.get("headline");
+        SearchResult {
+            reaction: row.get("reaction"),
+            rank: row.get("rank"),
+            headline: headline.map(|headline| highlight(&headline)),
+            feed: Feed::from(row),
+        }
+    }
+}
+
+graphql_object!(SearchResult: Query as "SearchResult" |&self| {
+    description: "Feed matching a search"
+
+    field feed() -> &Feed {
+        &self.feed
+    }
+
+    field reaction() -> String {
+        self.reaction.to_string()
+    }
+
+    field rank() -> f64 as "Relevance, higher is better" {
+        self.rank as f64
+    }
+
+    field headline() -> &Option<String> as "Text around the matches, highlighted with <mark>" {
+        &self.headline
+    }
+});
+
+/// Filters of a search, all optional
+#[derive(GraphQLInputObject, Debug, Default)]
+pub struct SearchFilters {
+    pub source_uuid: Option<String>,
+    pub reaction: Option<String>,
+    /// Feeds received on or after this date, `YYYY-MM-DD` or RFC 3339
+    pub from: Option<String>,
+    /// Feeds received before this date, `YYYY-MM-DD` or RFC 3339
+    pub to: Option<String>,
+}
+
+fn parse_filter_date(date: &Option<String>) -> Result<Option<NaiveDateTime>> {
+    match *date {
+        Some(ref date) => {
+            let date = date.trim();
+            let parsed = DateTime::parse_from_rfc3339(date).ok().map(|date| date.naive_utc())
+                .or_else(|| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok().map(|date| date.and_hms(0, 0, 0)));
+            match parsed {
+                Some(parsed) => Ok(Some(parsed)),
+                None => bail!("invalid date {}", date),
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+/// Ranked feeds of the user matching `terms`, each feed is searched with its own language configuration
+pub fn search(pg: &PgDatabase, terms: &str, filters: &SearchFilters, limit: i32, offset: i32, user: &User) -> Result<Vec<SearchResult>> {
+    let source_uuid = match filters.source_uuid {
+        Some(ref source_uuid) => Some(Uuid::parse_str(source_uuid)?),
+        None => None,
+    };
+    let reaction = match filters.reaction {
+        Some(ref reaction) => Some(Reaction::from_str(reaction)?),
+        None => None,
+    };
+    let from = parse_filter_date(&filters.from)?;
+    let to = parse_filter_date(&filters.to)?;
+    let tsquery = search_tsquery("$2::text");
+    let query = format!(r#"
+        SELECT feeds.*, users_feeds.reaction,
+            ts_rank_cd(feeds.search_vector, {tsquery}) AS rank,
+            ts_headline(
+                feeds.search_config::regconfig,
+                regexp_replace(
+                    coalesce(feeds.rss->>'summary', feeds.readable->>'excerpt', feeds.rss->>'content', feeds.twitter->>'text', feeds.mastodon->>'content', ''),
+                    '<[^>]*>', ' ', 'g'
+                ),
+                {tsquery},
+                $9::text
+            ) AS headline
+        FROM feeds
+        JOIN users_feeds ON users_feeds.feed_uuid = feeds.uuid
+        WHERE users_feeds.user_uuid = $1::uuid
+        AND feeds.search_vector @@ {tsquery}
+        AND ($3::uuid IS NULL OR feeds.source_uuid = $3::uuid)
+        AND ($4::reaction IS NULL OR users_feeds.reaction = $4::reaction)
+        AND ($5::timestamp IS NULL OR feeds.created >= $5::timestamp)
+        AND ($6::timestamp IS NULL OR feeds.created < $6::timestamp)
+        ORDER BY rank DESC, feeds.created DESC
+        LIMIT $7::int OFFSET $8::int;
+    "#, tsquery = tsquery);
+    let headline_options = format!("StartSel={}, StopSel={}, MaxFragments=2, MaxWords=30, MinWords=10", MARK_START, MARK_STOP);
+    pg.find(&query, &[&user.uuid, &terms, &source_uuid, &reaction, &from, &to, &limit, &offset, &headline_options])
+}
+
+pub fn search_resolver(pool: Pool<PostgresConnectionManager>, terms: &str, filters: Option<SearchFilters>, limit: i32, offset: i32, user: &User) -> Result<Vec<SearchResult>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    if terms.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    search(&pg, terms, &filters.unwrap_or_default(), limit, offset, user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headline_text_is_escaped() {
+        let headline = format!("a {}script{} &lt;img src=x onerror=alert(1)&gt; &amp; <b>", MARK_START, MARK_STOP);
+        assert_eq!(highlight(&headline), "a <mark>script</mark> &lt;img src=x onerror=alert(1)&gt; &amp; &lt;b&gt;");
+    }
+
+    #[test]
+    fn only_marks_are_markup() {
+        let headline = format!("{}rust{} </mark><script>", MARK_START, MARK_STOP);
+        assert_eq!(highlight(&headline), "<mark>rust</mark> &lt;/mark&gt;&lt;script&gt;");
+    }
+
+    #[test]
+    fn known_languages_have_a_config() {
+        assert_eq!(language_config("EN"), Some("english"));
+        assert_eq!(language_config("fra"), Some("french"));
+        assert_eq!(language_config("xx"), None);
+    }
+
+    #[test]
+    fn every_language_config_is_searched() {
+        let codes = ["da", "de", "en", "es", "fi", "fr", "hu", "it", "nl", "no", "pt", "ro", "ru", "sv", "tr"];
+        for code in codes.iter() {
+            assert!(SEARCH_CONFIGS.contains(&language_config(code).unwrap()));
+        }
+        assert!(SEARCH_CONFIGS.contains(&DEFAULT_SEARCH_CONFIG));
+    }
+
+    #[test]
+    fn tsquery_is_the_same_for_every_feed() {
+        let tsquery = search_tsquery("$2::text");
+        assert!(tsquery.starts_with("(plainto_tsquery('simple', $2::text) || plainto_tsquery('danish', $2::text)"));
+        assert!(!tsquery.contains("feeds."));
+    }
+}
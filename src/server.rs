@@ -9,9 +9,13 @@ use reqwest;
 use rocket;
 use graphql::query::{Schema, Query};
 use graphql::mutation::Mutation;
-use pg::create_db_pool;
+use pg::{create_db_pool, create_job_pool};
 use rss;
+use extraction_jobs;
+use inbound_mail;
+use data_export;
 use routes;
+use mailer;
 
 pub fn create_diesel_pool(config: &Config) -> Pool<ConnectionManager<PgConnection>> {
     let database_url = config.database_url.clone();
@@ -28,6 +32,7 @@ pub fn establish_connection(config: &Config) -> PgConnection {
 pub fn run() {
     dotenv().ok();
     let conf = Config::from_env();
+    mailer::from_config(&conf).expect("Invalid mail configuration");
     let connection = create_db_pool(&conf);
     let diesel_pool = create_diesel_pool(&conf);
     use embedded_migrations;
@@ -35,7 +40,17 @@ pub fn run() {
     embedded_migrations::run(&diesel_connection).expect("Migration Error");
 
     let client = reqwest::Client::new();
-    rss::run_rss_job(conf.rss_job_interval.clone(), client, connection.clone());
+    rss::run_rss_job(conf.rss_job_interval.clone(), conf.rss_job_workers, conf.rss_job_host_concurrency, client.clone(), create_job_pool(&conf, conf.rss_job_workers));
+    extraction_jobs::run_extraction_job(conf.rss_job_interval.clone(), conf.extraction_workers, conf.rss_job_host_concurrency, client, create_job_pool(&conf, conf.extraction_workers));
+    if let Some(ref domain) = conf.inbound_email_domain {
+        if let Some(ref addr) = conf.smtp_listen_addr {
+            inbound_mail::run_smtp_listener(addr.clone(), domain.clone(), create_job_pool(&conf, inbound_mail::MAX_SESSIONS));
+        }
+        if let Some(ref dir) = conf.inbound_maildir {
+            inbound_mail::run_maildir_job(dir.clone(), conf.rss_job_interval.clone(), create_job_pool(&conf, 1));
+        }
+    }
+    data_export::run_export_job(conf.export_dir.clone(), conf.rss_job_interval.clone(), create_job_pool(&conf, 1));
     rocket::ignite()
         .manage(Query::new(connection.clone(), diesel_pool.clone()))
         .manage(create_db_pool(&conf))
@@ -49,6 +64,11 @@ pub fn run() {
             routes::files,
             routes::graphiql,
             routes::post_graphql_handler,
+            routes::websub_verify,
+            routes::websub_push,
+            routes::webhook_push,
+            routes::opml_export,
+            routes::data_export_download,
         ])
         .launch();
 }
@@ -0,0 +1,224 @@
+use std::net::IpAddr;
+use uuid::Uuid;
+use chrono::prelude::*;
+use chrono::{Duration, NaiveDateTime};
+use postgres::rows::Row;
+use postgres::types::ToSql;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use rocket::request::{self, Request, FromRequest};
+use rocket::outcome::Outcome;
+
+use errors::*;
+use pg::{Insertable, PgDatabase};
+use refresh_tokens::revoke_family;
+use user::User;
+use config::CONFIG;
+
+/// `last_seen` is not written more often than this, every authenticated query would update it otherwise
+const LAST_SEEN_PRECISION_SECONDS: i64 = 60;
+const MAX_LABEL_LENGTH: usize = 100;
+
+/// Client of the request, recorded on the session it opens
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Ip of the client, the forwarded headers are only believed when the request comes from a trusted proxy.
+/// X-Forwarded-For is read from the right, the first address not added by a trusted proxy is the client
+pub fn client_ip(remote: Option<IpAddr>, forwarded_for: Option<&str>, real_ip: Option<&str>, trusted_proxies: &[IpAddr]) -> Option<String> {
+    let remote = remote?;
+    if !trusted_proxies.contains(&remote) {
+        return Some(remote.to_string());
+    }
+    let forwarded = forwarded_for.and_then(|forwarded| {
+        forwarded.rsplit(',')
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .find(|ip| !trusted_proxies.contains(ip))
+    });
+    let real_ip = real_ip.and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+    Some(forwarded.or(real_ip).unwrap_or(remote).to_string())
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientInfo {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let headers = request.headers();
+        Outcome::Success(ClientInfo {
+            user_agent: headers.get_one("User-Agent").map(|user_agent| user_agent.to_owned()),
+            ip: client_ip(
+                request.remote().map(|remote| remote.ip()),
+                headers.get_one("X-Forwarded-For"),
+                headers.get_one("X-Real-IP"),
+                &CONFIG.trusted_proxies,
+            ),
+        })
+    }
+}
+
+/// Login of a user on a device, revoked with the refresh tokens of its family
+#[derive(Debug)]
+pub struct Session {
+    pub uuid: Uuid,
+    pub user_uuid: Uuid,
+    pub device_label: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    /// Whether it is the session of the token of the query
+    pub current: bool,
+}
+
+impl Session {
+    pub fn new(uuid: Uuid, user: &User, device_label: Option<String>, client: &ClientInfo) -> Self {
+        let now = Utc::now().naive_utc();
+        Session {
+            uuid,
+            user_uuid: user.uuid,
+            device_label: device_label
+                .map(|label| label.trim().chars().take(MAX_LABEL_LENGTH).collect::<String>())
+                .and_then(|label| if label.is_empty() { None } else { Some(label) }),
+            user_agent: client.user_agent.clone(),
+            ip: client.ip.clone(),
+            created: now,
+            last_seen: now,
+            current: false,
+        }
+    }
+}
+
+impl<'a> From<Row<'a>> for Session {
+    fn from(row: Row) -> Self {
+        Session {
+            uuid: row.get("uuid"),
+            user_uuid: row.get("user_uuid"),
+            device_label: row.get("device_label"),
+            user_agent: row.get("user_agent"),
+            ip: row.get("ip"),
+            created: row.get("created"),
+            last_seen: row.get("last_seen"),
+            current: row.get("current"),
+        }
+    }
+}
+
+impl Insertable for Session {
+    fn insert_query(&self) -> String {
+        r#"
+            INSERT INTO sessions (uuid, user_uuid, device_label, user_agent, ip, created, last_seen)
+            VALUES ($1, $2, $3, $4, $5, $6, $7);
+        "#.to_owned()
+    }
+
+    fn insert_params(&self) -> Box<[&ToSql]> {
+        Box::new([
+            &self.uuid,
+            &self.user_uuid,
+            &self.device_label,
+            &self.user_agent,
+            &self.ip,
+            &self.created,
+            &self.last_seen,
+        ])
+    }
+}
+
+graphql_object!(Session: () as "Session" |&self| {
+    description: "Device the user is logged in on"
+
+    field uuid() -> Uuid {
+        self.uuid
+    }
+
+    field device_label() -> &Option<String> as "Name given at login" {
+        &self.device_label
+    }
+
+    field user_agent() -> &Option<String> {
+        &self.user_agent
+    }
+
+    field ip() -> &Option<String> as "Address of the last request" {
+        &self.ip
+    }
+
+    field created() -> String {
+        format!("{}", self.created)
+    }
+
+    field last_seen() -> String {
+        format!("{}", self.last_seen)
+    }
+
+    field current() -> bool as "Session of the token used for this query" {
+        self.current
+    }
+});
+
+/// Records the session of the user, once per ip change or precision window
+pub fn touch_session(pg: &PgDatabase, session_uuid: &Uuid, client: &ClientInfo) -> Result<u64> {
+    let now = Utc::now().naive_utc();
+    let query = r#"
+        UPDATE sessions SET last_seen = $1, ip = COALESCE($2, ip)
+        WHERE uuid = $3::uuid AND (last_seen < $4 OR ip IS DISTINCT FROM COALESCE($2, ip));
+    "#;
+    Ok(pg.update(query, &[&now, &client.ip, session_uuid, &(now - Duration::seconds(LAST_SEEN_PRECISION_SECONDS))])?)
+}
+
+/// Sessions still active, the most recently used first
+pub fn sessions_resolver(pool: Pool<PostgresConnectionManager>, current_session: &Uuid, user: &User) -> Result<Vec<Session>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let query = r#"
+        SELECT sessions.*, (sessions.uuid = $2::uuid) AS current FROM sessions
+        WHERE sessions.user_uuid = $1::uuid
+        AND EXISTS (
+            SELECT 1 FROM refresh_tokens
+            WHERE refresh_tokens.family_uuid = sessions.uuid
+            AND refresh_tokens.revoked_at IS NULL
+            AND refresh_tokens.expires_at > $3
+        )
+        ORDER BY sessions.last_seen DESC;
+    "#;
+    Ok(pg.find(query, &[&user.uuid, current_session, &Utc::now().naive_utc()])?)
+}
+
+pub fn revoke_session_resolver(pool: Pool<PostgresConnectionManager>, session_uuid: &str, user: &User) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let session_uuid = Uuid::parse_str(session_uuid)?;
+    if revoke_family(&pg, &session_uuid, &user.uuid)? == 0 {
+        return Err(ErrorKind::NotFound.into());
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn headers_of_untrusted_clients_are_ignored() {
+        let ip = client_ip(Some(ip("203.0.113.7")), Some("10.0.0.1"), Some("10.0.0.2"), &[ip("127.0.0.1")]);
+        assert_eq!(ip, Some("203.0.113.7".to_owned()));
+    }
+
+    #[test]
+    fn trusted_proxy_forwards_the_client() {
+        let trusted = [ip("127.0.0.1"), ip("10.0.0.5")];
+        let ip = client_ip(Some(ip("127.0.0.1")), Some("198.51.100.1, 203.0.113.7, 10.0.0.5"), None, &trusted);
+        assert_eq!(ip, Some("203.0.113.7".to_owned()));
+    }
+
+    #[test]
+    fn trusted_proxy_falls_back_to_real_ip_then_remote() {
+        let trusted = [ip("127.0.0.1")];
+        assert_eq!(client_ip(Some(ip("127.0.0.1")), Some("garbage"), Some("203.0.113.7"), &trusted), Some("203.0.113.7".to_owned()));
+        assert_eq!(client_ip(Some(ip("127.0.0.1")), None, None, &trusted), Some("127.0.0.1".to_owned()));
+    }
+}
@@ -0,0 +1,268 @@
+use std::str::FromStr;
+use uuid::Uuid;
+use chrono::prelude::*;
+use chrono::NaiveDateTime;
+use postgres::rows::Row;
+use postgres::types::ToSql;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+
+use errors::*;
+use graphql::query::Query;
+use pg::{Insertable, PgDatabase};
+use feeds::Feed;
+use users_feeds::Reaction;
+use users_sources::{SourceStat, user_source_exist};
+use search::search_tsquery;
+use user::User;
+
+lazy_static! {
+    /// Conditions of the feeds of a smart feed, `smart_feeds` and `users_feeds` must be in scope.
+    /// Without reactions a smart feed shows the unread feeds, like `unreaded_feeds`.
+    static ref MATCHING_FEEDS: String = format!(r#"
+    users_feeds.user_uuid = smart_feeds.user_uuid
+    AND (smart_feeds.query IS NULL OR feeds.search_vector @@ {})
+    AND (cardinality(smart_feeds.source_uuids) = 0 OR feeds.source_uuid = ANY(smart_feeds.source_uuids))
+    AND users_feeds.reaction = ANY(
+        CASE WHEN cardinality(smart_feeds.reactions) = 0 THEN ARRAY['Unreaded']::reaction[] ELSE smart_feeds.reactions END
+    )
+    AND (smart_feeds.window_days IS NULL OR feeds.created >= now() - make_interval(days => smart_feeds.window_days))
+"#, search_tsquery("smart_feeds.query"));
+}
+
+/// Saved search listed next to the sources
+#[derive(Debug)]
+pub struct SmartFeed {
+    pub uuid: Uuid,
+    pub user_uuid: Uuid,
+    pub name: String,
+    /// Words the feeds must contain, every feed matches without it
+    pub query: Option<String>,
+    /// Only feeds of these sources, all followed sources when empty
+    pub source_uuids: Vec<Uuid>,
+    /// Only feeds with these reactions, the unread ones when empty
+    pub reactions: Vec<Reaction>,
+    /// Only feeds received in the last days
+    pub window_days: Option<i32>,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+pub struct SmartFeedInput {
+    pub name: String,
+    pub query: Option<String>,
+    pub source_uuids: Option<Vec<String>>,
+    pub reactions: Option<Vec<String>>,
+    pub window_days: Option<i32>,
+}
+
+impl SmartFeed {
+    pub fn new(user: &User, input: SmartFeedInput) -> Result<Self> {
+        let mut smart_feed = SmartFeed {
+            uuid: Uuid::new_v4(),
+            user_uuid: user.uuid,
+            name: String::new(),
+            query: None,
+            source_uuids: Vec::new(),
+            reactions: Vec::new(),
+            window_days: None,
+            created: Utc::now().naive_utc(),
+            updated: Utc::now().naive_utc(),
+        };
+        smart_feed.apply(input)?;
+        Ok(smart_feed)
+    }
+
+    fn apply(&mut self, input: SmartFeedInput) -> Result<()> {
+        if input.name.trim().is_empty() {
+            bail!("smart feed name can not be empty");
+        }
+        if input.window_days.map(|days| days <= 0).unwrap_or(false) {
+            bail!("smart feed window must be at least one day");
+        }
+        let mut source_uuids = Vec::new();
+        for source_uuid in input.source_uuids.unwrap_or_default() {
+            source_uuids.push(Uuid::parse_str(&source_uuid)?);
+        }
+        let mut reactions = Vec::new();
+        for reaction in input.reactions.unwrap_or_default() {
+            reactions.push(Reaction::from_str(&reaction)?);
+        }
+        self.name = input.name.trim().to_owned();
+        self.query = input.query.and_then(|query| if query.trim().is_empty() { None } else { Some(query) });
+        self.source_uuids = source_uuids;
+        self.reactions = reactions;
+        self.window_days = input.window_days;
+        self.updated = Utc::now().naive_utc();
+        Ok(())
+    }
+}
+
+impl<'a> From<Row<'a>> for SmartFeed {
+    fn from(row: Row) -> Self {
+        SmartFeed {
+            uuid: row.get("uuid"),
+            user_uuid: row.get("user_uuid"),
+            name: row.get("name"),
+            query: row.get("query"),
+            source_uuids: row.get("source_uuids"),
+            reactions: row.get("reactions"),
+            window_days: row.get("window_days"),
+            created: row.get("created"),
+            updated: row.get("updated"),
+        }
+    }
+}
+
+impl Insertable for SmartFeed {
+    fn insert_query(&self) -> String {
+        r#"
+            INSERT INTO smart_feeds (uuid, user_uuid, name, query, source_uuids, reactions, window_days, created, updated)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+        "#.to_owned()
+    }
+
+    fn insert_params(&self) -> Box<[&ToSql]> {
+        Box::new([
+            &self.uuid,
+            &self.user_uuid,
+            &self.name,
+            &self.query,
+            &self.source_uuids,
+            &self.reactions,
+            &self.window_days,
+            &self.created,
+            &self.updated,
+        ])
+    }
+}
+
+graphql_object!(SmartFeed: Query as "SmartFeed" |&self| {
+    description: "Saved search listed next to the sources"
+
+    field uuid() -> Uuid {
+        self.uuid
+    }
+
+    field name() -> &String {
+        &self.name
+    }
+
+    field query() -> &Option<String> {
+        &self.query
+    }
+
+    field source_uuids() -> Vec<String> as "Sources the feeds come from, all followed sources when empty" {
+        self.source_uuids.iter().map(|uuid| uuid.hyphenated().to_string()).collect()
+    }
+
+    field reactions() -> Vec<String> as "Reactions of the feeds, unread when empty" {
+        self.reactions.iter().map(|reaction| reaction.to_string()).collect()
+    }
+
+    field window_days() -> Option<i32> as "Only feeds received in the last days" {
+        self.window_days
+    }
+
+    field created() -> String {
+        format!("{}", self.created)
+    }
+
+    field updated() -> String {
+        format!("{}", self.updated)
+    }
+});
+
+pub fn find_smart_feed(pg: &PgDatabase, uuid: &Uuid, user: &User) -> Result<Option<SmartFeed>> {
+    let query = "SELECT * FROM smart_feeds WHERE uuid = $1::uuid AND user_uuid = $2::uuid;";
+    Ok(pg.find_one(query, &[uuid, &user.uuid])?)
+}
+
+/// Sources of a smart feed must be followed, the feeds of the others never reach the user
+fn check_sources(pg: &PgDatabase, smart_feed: &SmartFeed, user: &User) -> Result<()> {
+    for source_uuid in &smart_feed.source_uuids {
+        if !user_source_exist(pg, source_uuid, user)? {
+            return Err(ErrorKind::NotFound.into());
+        }
+    }
+    Ok(())
+}
+
+pub fn create_smart_feed_resolver(pool: Pool<PostgresConnectionManager>, input: SmartFeedInput, user: &User) -> Result<SmartFeed> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let smart_feed = SmartFeed::new(user, input)?;
+    check_sources(&pg, &smart_feed, user)?;
+    pg.insert(&smart_feed)?;
+    Ok(smart_feed)
+}
+
+pub fn update_smart_feed_resolver(pool: Pool<PostgresConnectionManager>, uuid: &str, input: SmartFeedInput, user: &User) -> Result<SmartFeed> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let uuid = Uuid::parse_str(uuid)?;
+    let mut smart_feed = find_smart_feed(&pg, &uuid, user)?.ok_or(ErrorKind::NotFound)?;
+    smart_feed.apply(input)?;
+    check_sources(&pg, &smart_feed, user)?;
+    let query = r#"
+        UPDATE smart_feeds SET name = $1, query = $2, source_uuids = $3, reactions = $4, window_days = $5, updated = $6
+        WHERE uuid = $7::uuid AND user_uuid = $8::uuid;
+    "#;
+    pg.update(query, &[
+        &smart_feed.name,
+        &smart_feed.query,
+        &smart_feed.source_uuids,
+        &smart_feed.reactions,
+        &smart_feed.window_days,
+        &smart_feed.updated,
+        &uuid,
+        &user.uuid,
+    ])?;
+    Ok(smart_feed)
+}
+
+pub fn delete_smart_feed_resolver(pool: Pool<PostgresConnectionManager>, uuid: &str, user: &User) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let uuid = Uuid::parse_str(uuid)?;
+    let query = "DELETE FROM smart_feeds WHERE uuid = $1::uuid AND user_uuid = $2::uuid;";
+    if pg.update(query, &[&uuid, &user.uuid])? == 0 {
+        return Err(ErrorKind::NotFound.into());
+    }
+    Ok(true)
+}
+
+pub fn smart_feeds_resolver(pool: Pool<PostgresConnectionManager>, user: &User) -> Result<Vec<SmartFeed>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let query = "SELECT * FROM smart_feeds WHERE user_uuid = $1::uuid ORDER BY name;";
+    Ok(pg.find(query, &[&user.uuid])?)
+}
+
+pub fn smart_feed_feeds_resolver(pool: Pool<PostgresConnectionManager>, uuid: &str, limit: i32, offset: i32, user: &User) -> Result<Vec<Feed>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let uuid = Uuid::parse_str(uuid)?;
+    let query = format!(r#"
+        SELECT feeds.* FROM feeds
+        JOIN users_feeds ON users_feeds.feed_uuid = feeds.uuid
+        JOIN smart_feeds ON smart_feeds.uuid = $1::uuid
+        WHERE smart_feeds.user_uuid = $2::uuid
+        AND {}
+        ORDER BY feeds.updated DESC
+        LIMIT $3::int OFFSET $4::int;
+    "#, *MATCHING_FEEDS);
+    Ok(pg.find(&query, &[&uuid, &user.uuid, &limit, &offset])?)
+}
+
+/// Unread feeds of each smart feed, listed by `sources_stats` with the unread count of the sources,
+/// a smart feed filtering on other reactions still counts only its unread matches
+pub fn smart_feeds_stats(pg: &PgDatabase, user: &User) -> Result<Vec<SourceStat>> {
+    let query = format!(r#"
+        SELECT smart_feeds.uuid, TRUE AS smart_feed, (
+            SELECT COUNT(feeds.uuid)
+            FROM feeds
+            JOIN users_feeds ON users_feeds.feed_uuid = feeds.uuid
+            WHERE {}
+            AND users_feeds.reaction = 'Unreaded'
+        ) AS count FROM smart_feeds
+        WHERE smart_feeds.user_uuid = $1::uuid
+    "#, *MATCHING_FEEDS);
+    Ok(pg.find(&query, &[&user.uuid])?)
+}
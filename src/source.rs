@@ -5,7 +5,7 @@ use serde_json::Value;
 
 use errors::*;
 use source_type::SourceType;
-use source_option::{SourceOption, RssSource, TwitterSource };
+use source_option::{SourceOption, RssSource, TwitterSource, MastodonSource, WebPageSource, EmailSource, WebhookSource};
 use serde_json;
 
 use schema::sources;
@@ -20,6 +20,13 @@ pub struct Source {
     pub error: Option<String>,
     pub created: Option<NaiveDateTime>,
     pub updated: Option<NaiveDateTime>,
+    pub last_success: Option<NaiveDateTime>,
+    pub last_failure: Option<NaiveDateTime>,
+    pub failure_count: i32,
+    pub last_status: Option<i32>,
+    pub next_fetch_at: Option<NaiveDateTime>,
+    pub disabled: bool,
+    pub fetch_interval: Option<i32>,
 }
 
 impl Source {
@@ -34,6 +41,26 @@ impl Source {
                 // TODO remove unwrap
                 let twitter_source = serde_json::from_value::<TwitterSource>(self.data.clone().unwrap())?;
                 Ok(SourceOption::Twitter(twitter_source))
+            },
+            SourceType::Mastodon => {
+                let data = self.data.clone().ok_or(ErrorKind::NotFound)?;
+                let mastodon_source = serde_json::from_value::<MastodonSource>(data)?;
+                Ok(SourceOption::Mastodon(mastodon_source))
+            },
+            SourceType::WebPage => {
+                let data = self.data.clone().ok_or(ErrorKind::NotFound)?;
+                let web_page_source = serde_json::from_value::<WebPageSource>(data)?;
+                Ok(SourceOption::WebPage(web_page_source))
+            },
+            SourceType::Email => {
+                let data = self.data.clone().ok_or(ErrorKind::NotFound)?;
+                let email_source = serde_json::from_value::<EmailSource>(data)?;
+                Ok(SourceOption::Email(email_source))
+            },
+            SourceType::Webhook => {
+                let data = self.data.clone().ok_or(ErrorKind::NotFound)?;
+                let webhook_source = serde_json::from_value::<WebhookSource>(data)?;
+                Ok(SourceOption::Webhook(webhook_source))
             }
         }
     }
@@ -43,12 +70,31 @@ impl Source {
         Ok(Source::new(SourceType::Rss, data))
     }
 
-    #[allow(dead_code)]
     pub fn new_twitter(twitter_source: TwitterSource) -> Result<Self> {
         let data = serde_json::to_value(twitter_source)?;
         Ok(Source::new(SourceType::Twitter, data))
     }
 
+    pub fn new_mastodon(mastodon_source: MastodonSource) -> Result<Self> {
+        let data = serde_json::to_value(mastodon_source)?;
+        Ok(Source::new(SourceType::Mastodon, data))
+    }
+
+    pub fn new_web_page(web_page_source: WebPageSource) -> Result<Self> {
+        let data = serde_json::to_value(web_page_source)?;
+        Ok(Source::new(SourceType::WebPage, data))
+    }
+
+    pub fn new_email(email_source: EmailSource) -> Result<Self> {
+        let data = serde_json::to_value(email_source)?;
+        Ok(Source::new(SourceType::Email, data))
+    }
+
+    pub fn new_webhook(webhook_source: WebhookSource) -> Result<Self> {
+        let data = serde_json::to_value(webhook_source)?;
+        Ok(Source::new(SourceType::Webhook, data))
+    }
+
     fn new(source_type: SourceType, data: Value) -> Self {
         Source {
             uuid: Uuid::new_v4(),
@@ -57,6 +103,13 @@ impl Source {
             error: None,
             created: Some(Utc::now().naive_utc()),
             updated: Some(Utc::now().naive_utc()),
+            last_success: None,
+            last_failure: None,
+            failure_count: 0,
+            last_status: None,
+            next_fetch_at: None,
+            disabled: false,
+            fetch_interval: None,
         }
     }
 }
@@ -79,6 +132,41 @@ graphql_object!(Source: () as "Source" |&self| {
         }
     }
 
+    field twitter_source() -> Option<TwitterSource> as "twitter_source" {
+        match self.source_type {
+            SourceType::Twitter => self.data.clone().and_then(|data| serde_json::from_value::<TwitterSource>(data.clone()).ok()),
+            _ => None
+        }
+    }
+
+    field mastodon_source() -> Option<MastodonSource> as "mastodon_source" {
+        match self.source_type {
+            SourceType::Mastodon => self.data.clone().and_then(|data| serde_json::from_value::<MastodonSource>(data.clone()).ok()),
+            _ => None
+        }
+    }
+
+    field web_page_source() -> Option<WebPageSource> as "web_page_source" {
+        match self.source_type {
+            SourceType::WebPage => self.data.clone().and_then(|data| serde_json::from_value::<WebPageSource>(data.clone()).ok()),
+            _ => None
+        }
+    }
+
+    field email_source() -> Option<EmailSource> as "email_source" {
+        match self.source_type {
+            SourceType::Email => self.data.clone().and_then(|data| serde_json::from_value::<EmailSource>(data.clone()).ok()),
+            _ => None
+        }
+    }
+
+    field webhook_source() -> Option<WebhookSource> as "webhook_source" {
+        match self.source_type {
+            SourceType::Webhook => self.data.clone().and_then(|data| serde_json::from_value::<WebhookSource>(data.clone()).ok()),
+            _ => None
+        }
+    }
+
     field error() -> &Option<String> as "error" {
         &self.error
     }
@@ -90,4 +178,32 @@ graphql_object!(Source: () as "Source" |&self| {
     field updated() -> &Option<NaiveDateTime> {
         &self.updated
     }
+
+    field last_success() -> &Option<NaiveDateTime> as "Last successful fetch" {
+        &self.last_success
+    }
+
+    field last_failure() -> &Option<NaiveDateTime> as "Last failed fetch" {
+        &self.last_failure
+    }
+
+    field failure_count() -> i32 as "Consecutive failed fetches" {
+        self.failure_count
+    }
+
+    field last_status() -> Option<i32> as "HTTP status of the last fetch" {
+        self.last_status
+    }
+
+    field next_fetch_at() -> &Option<NaiveDateTime> as "Source is not fetched before this date" {
+        &self.next_fetch_at
+    }
+
+    field disabled() -> bool as "Source stopped being fetched after too many failures" {
+        self.disabled
+    }
+
+    field fetch_interval() -> Option<i32> as "Seconds between two fetches, adapted to the source publishing rate" {
+        self.fetch_interval
+    }
 });
@@ -1,14 +1,28 @@
+use refresh_tokens::hash_token;
+
 #[derive(Debug)]
 pub enum SourceOption {
     Rss(RssSource),
-    Twitter(TwitterSource)
+    Twitter(TwitterSource),
+    Mastodon(MastodonSource),
+    WebPage(WebPageSource),
+    Email(EmailSource),
+    Webhook(WebhookSource),
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RssSource {
     pub title: String,
     pub xml_url: String,
     pub html_url: String,
+    /// HTTP validators of the last fetched document, sent back for conditional GET
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// How the readable version of the items is obtained
+    #[serde(default)]
+    pub extractor: ExtractorOption,
 }
 
 impl RssSource {
@@ -17,20 +31,252 @@ impl RssSource {
             title: title.to_owned(),
             xml_url: xml_url.to_owned(),
             html_url: html_url.to_owned(),
+            etag: None,
+            last_modified: None,
+            extractor: ExtractorOption::default(),
         }
     }
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+graphql_object!(RssSource: () as "RssSource" |&self| {
+    description: "RssSource"
+
+    field title() -> &String {
+        &self.title
+    }
+
+    field xml_url() -> &String {
+        &self.xml_url
+    }
+
+    field html_url() -> &String {
+        &self.html_url
+    }
+
+    field etag() -> &Option<String> {
+        &self.etag
+    }
+
+    field last_modified() -> &Option<String> {
+        &self.last_modified
+    }
+
+    field extractor() -> &ExtractorOption {
+        &self.extractor
+    }
+});
+
+#[derive(GraphQLEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ExtractorKind {
+    None,
+    RssContent,
+    Readability,
+    Selectors,
+    External,
+}
+
+/// Per source choice of the readable content extractor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum ExtractorOption {
+    /// Items are stored without readable content
+    None,
+    /// The feed already ships full content, use it as is
+    RssContent,
+    /// Built-in readability engine, or Mercury when configured
+    Readability,
+    /// User supplied CSS selectors for sites readability gets wrong
+    Selectors {
+        content: String,
+        title: Option<String>,
+        #[serde(default)]
+        remove: Vec<String>,
+    },
+    /// Mercury compatible extraction service called with `?url=`
+    External {
+        endpoint: String,
+    },
+}
+
+impl Default for ExtractorOption {
+    fn default() -> Self {
+        ExtractorOption::Readability
+    }
+}
+
+impl ExtractorOption {
+    pub fn kind(&self) -> ExtractorKind {
+        match *self {
+            ExtractorOption::None => ExtractorKind::None,
+            ExtractorOption::RssContent => ExtractorKind::RssContent,
+            ExtractorOption::Readability => ExtractorKind::Readability,
+            ExtractorOption::Selectors { .. } => ExtractorKind::Selectors,
+            ExtractorOption::External { .. } => ExtractorKind::External,
+        }
+    }
+}
+
+graphql_object!(ExtractorOption: () as "Extractor" |&self| {
+    description: "Readable content extractor of a source"
+
+    field kind() -> ExtractorKind {
+        self.kind()
+    }
+
+    field content_selector() -> Option<&String> {
+        match *self {
+            ExtractorOption::Selectors { ref content, .. } => Some(content),
+            _ => None,
+        }
+    }
+
+    field title_selector() -> Option<&String> {
+        match *self {
+            ExtractorOption::Selectors { ref title, .. } => title.as_ref(),
+            _ => None,
+        }
+    }
+
+    field remove_selectors() -> Vec<String> {
+        match *self {
+            ExtractorOption::Selectors { ref remove, .. } => remove.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    field endpoint() -> Option<&String> {
+        match *self {
+            ExtractorOption::External { ref endpoint } => Some(endpoint),
+            _ => None,
+        }
+    }
+});
+
+#[derive(GraphQLInputObject, Debug)]
+pub struct ExtractorInput {
+    pub kind: ExtractorKind,
+    pub content_selector: Option<String>,
+    pub title_selector: Option<String>,
+    pub remove_selectors: Option<Vec<String>>,
+    pub endpoint: Option<String>,
+}
+
+/// Tweets of a hashtag search or of a user timeline, exactly one of them is set
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct TwitterSource {
     pub hashtag: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Newest tweet already ingested, older tweets are not asked again
+    #[serde(default)]
+    pub since_id: Option<String>,
 }
 
 impl TwitterSource {
-    #[allow(dead_code)]
-    pub fn new(hashtag: Option<String>) -> Self {
+    pub fn new(hashtag: Option<String>, username: Option<String>) -> Self {
         TwitterSource {
-            hashtag
+            hashtag,
+            username,
+            since_id: None,
+        }
+    }
+}
+
+/// Public statuses of a Mastodon account or hashtag, exactly one of them is set
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct MastodonSource {
+    /// Base url of the instance the timeline is read from
+    pub instance_url: String,
+    /// `user@instance` handle of the followed account
+    #[serde(default)]
+    pub account: Option<String>,
+    #[serde(default)]
+    pub hashtag: Option<String>,
+    /// Id of the account on `instance_url`, resolved on the first fetch
+    #[serde(default)]
+    pub account_id: Option<String>,
+    /// Newest status already ingested, older statuses are not asked again
+    #[serde(default)]
+    pub since_id: Option<String>,
+}
+
+impl MastodonSource {
+    pub fn new(instance_url: &str, account: Option<String>, hashtag: Option<String>) -> Self {
+        MastodonSource {
+            instance_url: instance_url.trim_right_matches('/').to_owned(),
+            account,
+            hashtag,
+            account_id: None,
+            since_id: None,
+        }
+    }
+}
+
+/// Html page without a feed, its items are the elements matching `item_selector`
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct WebPageSource {
+    pub title: String,
+    pub url: String,
+    pub item_selector: String,
+    /// Selectors below are relative to the item
+    pub title_selector: Option<String>,
+    /// The first link of the item when not set
+    pub link_selector: Option<String>,
+    /// Text or `datetime` attribute of the matched element
+    pub date_selector: Option<String>,
+    /// The whole item when not set
+    pub content_selector: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug)]
+pub struct WebPageInput {
+    pub url: String,
+    pub title: Option<String>,
+    pub item_selector: String,
+    pub title_selector: Option<String>,
+    pub link_selector: Option<String>,
+    pub date_selector: Option<String>,
+    pub content_selector: Option<String>,
+}
+
+/// Newsletters received on a private inbound address
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct EmailSource {
+    pub title: String,
+    /// `local_part@INBOUND_EMAIL_DOMAIN`, given to the newsletter senders
+    pub address: String,
+}
+
+impl EmailSource {
+    pub fn new(title: &str, address: &str) -> Self {
+        EmailSource {
+            title: title.to_owned(),
+            address: address.to_lowercase(),
+        }
+    }
+}
+
+/// Items posted by other systems on a secret ingest url
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookSource {
+    pub title: String,
+    /// Hash of the secret part of the ingest url, the url is only given once to the creator of the source
+    pub token_hash: String,
+}
+
+impl WebhookSource {
+    pub fn new(title: &str, token: &str) -> Self {
+        WebhookSource {
+            title: title.to_owned(),
+            token_hash: hash_token(token),
         }
     }
 }
+
+graphql_object!(WebhookSource: () as "WebhookSource" |&self| {
+    description: "Items posted by other systems on a secret ingest url"
+
+    field title() -> &String {
+        &self.title
+    }
+});
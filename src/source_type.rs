@@ -9,6 +9,17 @@ use diesel::pg::Pg;
 pub enum SourceType {
     Rss,
     Twitter,
+    Mastodon,
+    WebPage,
+    Email,
+    Webhook,
+}
+
+impl SourceType {
+    /// Only the user who created the source may list or follow it, its data holds a private address or token
+    pub fn is_private(&self) -> bool {
+        *self == SourceType::Email || *self == SourceType::Webhook
+    }
 }
 
 #[derive(SqlType, PartialEq)]
@@ -20,6 +31,10 @@ impl ToSql<SqlSourceType, Pg> for SourceType {
         match *self {
             SourceType::Rss => out.write_all(b"Rss")?,
             SourceType::Twitter => out.write_all(b"Twitter")?,
+            SourceType::Mastodon => out.write_all(b"Mastodon")?,
+            SourceType::WebPage => out.write_all(b"WebPage")?,
+            SourceType::Email => out.write_all(b"Email")?,
+            SourceType::Webhook => out.write_all(b"Webhook")?,
         }
         Ok(IsNull::No)
     }
@@ -30,6 +45,10 @@ impl FromSql<SqlSourceType, Pg> for SourceType {
         match not_none!(bytes) {
             b"Rss" => Ok(SourceType::Rss),
             b"Twitter" => Ok(SourceType::Twitter),
+            b"Mastodon" => Ok(SourceType::Mastodon),
+            b"WebPage" => Ok(SourceType::WebPage),
+            b"Email" => Ok(SourceType::Email),
+            b"Webhook" => Ok(SourceType::Webhook),
             _ => Err("Unrecognized enum variant".into()),
         }
     }
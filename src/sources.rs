@@ -2,12 +2,18 @@ use postgres::rows::Row;
 use postgres::types::ToSql;
 use r2d2::Pool;
 use r2d2_postgres::PostgresConnectionManager;
+use std::cmp;
 use uuid::Uuid;
+use chrono::prelude::*;
+use chrono::{Duration, NaiveDateTime};
+use serde_json;
 
 use errors::*;
 use pg::{Insertable, PgDatabase};
-use source_option::RssSource;
+use source_option::{RssSource, TwitterSource, MastodonSource};
 use source::Source;
+use schedule::MAX_HINTED_FETCH_INTERVAL;
+use refresh_tokens::hash_token;
 
 impl<'a> From<Row<'a>> for Source {
     fn from(row: Row) -> Self {
@@ -18,6 +24,13 @@ impl<'a> From<Row<'a>> for Source {
             error: row.get("error"),
             created: row.get("created"),
             updated: row.get("updated"),
+            last_success: row.get("last_success"),
+            last_failure: row.get("last_failure"),
+            failure_count: row.get("failure_count"),
+            last_status: row.get("last_status"),
+            next_fetch_at: row.get("next_fetch_at"),
+            disabled: row.get("disabled"),
+            fetch_interval: row.get("fetch_interval"),
         }
     }
 }
@@ -25,13 +38,27 @@ impl<'a> From<Row<'a>> for Source {
 impl Insertable for Source {
     fn insert_query(&self) -> String {
         r#"
-            INSERT INTO sources (uuid, source_type, data, error, created, updated)
-            VALUES ($1, $2, $3, $4, $5, $6);
+            INSERT INTO sources (uuid, source_type, data, error, created, updated, last_success, last_failure, failure_count, last_status, next_fetch_at, disabled, fetch_interval)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13);
         "#.to_owned()
     }
 
     fn insert_params(&self) -> Box<[&ToSql]> {
-        Box::new([&self.uuid, &self.source_type, &self.data, &self.error, &self.created, &self.updated])
+        Box::new([
+            &self.uuid,
+            &self.source_type,
+            &self.data,
+            &self.error,
+            &self.created,
+            &self.updated,
+            &self.last_success,
+            &self.last_failure,
+            &self.failure_count,
+            &self.last_status,
+            &self.next_fetch_at,
+            &self.disabled,
+            &self.fetch_interval,
+        ])
     }
 }
 
@@ -54,16 +81,29 @@ pub fn source_existe(pg: &PgDatabase, xml_url: &str) -> Result<bool> {
     Ok(pg.exist(exist_query, &[&json_param])?)
 }
 
+pub fn find_source_by_xml_url(pg: &PgDatabase, xml_url: &str) -> Result<Option<Source>> {
+    let query = r#"SELECT * FROM sources WHERE sources."data" @> $1;"#;
+    let json_param = json!({ "xml_url": xml_url });
+    Ok(pg.find_one(query, &[&json_param])?)
+}
+
 pub fn find_sources_resolver(pool: Pool<PostgresConnectionManager>, limit: i32, offset: i32) -> Result<Vec<Source>> {
     let pg = PgDatabase::from_pool(pool)?;
-    let find_query = r#"SELECT * FROM sources LIMIT $1::int OFFSET $2::int;"#;
+    let find_query = r#"SELECT * FROM sources WHERE source_type NOT IN ('Email', 'Webhook') LIMIT $1::int OFFSET $2::int;"#;
     let sources = pg.find(find_query, &[&limit, &offset])?;
     Ok(sources)
 }
 
-pub fn find_rss_sources(pg: &PgDatabase, limit: i32, offset: i32) -> Result<Vec<Source>> {
-    let find_rss_query = r#"SELECT * FROM sources WHERE source_type = 'Rss' LIMIT $1::int OFFSET $2::int;"#;
-    let sources = pg.find::<Source>(find_rss_query, &[&limit, &offset])?;
+/// Polled sources that are neither disabled nor backing off at `now`
+pub fn find_due_sources(pg: &PgDatabase, now: &NaiveDateTime, limit: i32, offset: i32) -> Result<Vec<Source>> {
+    let find_rss_query = r#"
+        SELECT * FROM sources
+        WHERE source_type IN ('Rss', 'Twitter', 'Mastodon', 'WebPage')
+            AND NOT disabled
+            AND (next_fetch_at IS NULL OR next_fetch_at <= $1)
+        LIMIT $2::int OFFSET $3::int;
+    "#;
+    let sources = pg.find::<Source>(find_rss_query, &[now, &limit, &offset])?;
     Ok(sources)
 }
 
@@ -71,4 +111,189 @@ pub fn find_source_by_uuid(pg: &PgDatabase, uuid: &Uuid) -> Result<Option<Source
     let query = "SELECT * FROM sources WHERE uuid = $1::uuid;";
     let source = pg.find_one(query, &[uuid])?;
     Ok(source)
-}
\ No newline at end of file
+}
+
+pub fn update_rss_source(pg: &PgDatabase, source: &Source, rss_source: &RssSource) -> Result<u64> {
+    let query = "UPDATE sources SET data = $1, updated = $2 WHERE uuid = $3::uuid;";
+    let data = serde_json::to_value(rss_source)?;
+    Ok(pg.update(query, &[&data, &Utc::now().naive_utc(), &source.uuid])?)
+}
+
+/// Only the HTTP validators are written, the other keys may have changed since the fetch started
+pub fn update_rss_validators(pg: &PgDatabase, source: &Source, etag: &Option<String>, last_modified: &Option<String>) -> Result<u64> {
+    let query = r#"
+        UPDATE sources SET data = data || jsonb_build_object('etag', $1::text, 'last_modified', $2::text), updated = $3
+        WHERE uuid = $4::uuid;
+    "#;
+    Ok(pg.update(query, &[etag, last_modified, &Utc::now().naive_utc(), &source.uuid])?)
+}
+
+pub fn update_twitter_source(pg: &PgDatabase, source: &Source, twitter_source: &TwitterSource) -> Result<u64> {
+    let query = "UPDATE sources SET data = $1, updated = $2 WHERE uuid = $3::uuid;";
+    let data = serde_json::to_value(twitter_source)?;
+    Ok(pg.update(query, &[&data, &Utc::now().naive_utc(), &source.uuid])?)
+}
+
+/// Twitter source following the same hashtag or user
+pub fn find_twitter_source(pg: &PgDatabase, twitter_source: &TwitterSource) -> Result<Option<Source>> {
+    let query = r#"SELECT * FROM sources WHERE source_type = 'Twitter' AND sources."data" @> $1;"#;
+    let json_param = match (&twitter_source.hashtag, &twitter_source.username) {
+        (&Some(ref hashtag), _) => json!({ "hashtag": hashtag }),
+        (&None, &Some(ref username)) => json!({ "username": username }),
+        (&None, &None) => return Ok(None),
+    };
+    Ok(pg.find_one(query, &[&json_param])?)
+}
+
+pub fn update_mastodon_source(pg: &PgDatabase, source: &Source, mastodon_source: &MastodonSource) -> Result<u64> {
+    let query = "UPDATE sources SET data = $1, updated = $2 WHERE uuid = $3::uuid;";
+    let data = serde_json::to_value(mastodon_source)?;
+    Ok(pg.update(query, &[&data, &Utc::now().naive_utc(), &source.uuid])?)
+}
+
+/// Mastodon source following the same account or hashtag on the same instance
+pub fn find_mastodon_source(pg: &PgDatabase, mastodon_source: &MastodonSource) -> Result<Option<Source>> {
+    let query = r#"SELECT * FROM sources WHERE source_type = 'Mastodon' AND sources."data" @> $1;"#;
+    let json_param = match (&mastodon_source.account, &mastodon_source.hashtag) {
+        (&Some(ref account), _) => json!({ "instance_url": mastodon_source.instance_url, "account": account }),
+        (&None, &Some(ref hashtag)) => json!({ "instance_url": mastodon_source.instance_url, "hashtag": hashtag }),
+        (&None, &None) => return Ok(None),
+    };
+    Ok(pg.find_one(query, &[&json_param])?)
+}
+
+/// Web page source scraping the same page with the same item selector
+pub fn find_web_page_source(pg: &PgDatabase, url: &str, item_selector: &str) -> Result<Option<Source>> {
+    let query = r#"SELECT * FROM sources WHERE source_type = 'WebPage' AND sources."data" @> $1;"#;
+    let json_param = json!({ "url": url, "item_selector": item_selector });
+    Ok(pg.find_one(query, &[&json_param])?)
+}
+
+/// Email source receiving the mails sent to `address`
+pub fn find_email_source(pg: &PgDatabase, address: &str) -> Result<Option<Source>> {
+    let query = r#"SELECT * FROM sources WHERE source_type = 'Email' AND sources."data" @> $1;"#;
+    let json_param = json!({ "address": address.to_lowercase() });
+    Ok(pg.find_one(query, &[&json_param])?)
+}
+
+/// Email and webhook sources only the user follows, their address or url is private to it
+pub fn find_private_sources(pg: &PgDatabase, user_uuid: &Uuid) -> Result<Vec<Source>> {
+    let query = r#"
+        SELECT sources.* FROM sources
+        JOIN users_sources ON users_sources.source_uuid = sources.uuid
+        WHERE users_sources.user_uuid = $1::uuid AND sources.source_type IN ('Email', 'Webhook')
+        AND NOT EXISTS (
+            SELECT 1 FROM users_sources AS others
+            WHERE others.source_uuid = sources.uuid AND others.user_uuid <> $1::uuid
+        );
+    "#;
+    Ok(pg.find(query, &[user_uuid])?)
+}
+
+/// Deletes the sources with their feeds and everything pointing to them
+pub fn delete_sources(pg: &PgDatabase, source_uuids: &Vec<Uuid>) -> Result<u64> {
+    let feeds = "SELECT uuid FROM feeds WHERE source_uuid = ANY($1)";
+    pg.update(&format!("DELETE FROM extraction_jobs WHERE feed_uuid IN ({});", feeds), &[source_uuids])?;
+    pg.update(&format!("DELETE FROM users_feeds WHERE feed_uuid IN ({});", feeds), &[source_uuids])?;
+    pg.update("DELETE FROM feeds WHERE source_uuid = ANY($1);", &[source_uuids])?;
+    pg.update("DELETE FROM filter_rules WHERE source_uuid = ANY($1);", &[source_uuids])?;
+    pg.update("DELETE FROM websub_subscriptions WHERE source_uuid = ANY($1);", &[source_uuids])?;
+    pg.update("DELETE FROM users_sources WHERE source_uuid = ANY($1);", &[source_uuids])?;
+    Ok(pg.update("DELETE FROM sources WHERE uuid = ANY($1);", &[source_uuids])?)
+}
+
+/// Webhook source whose ingest url ends with `token`, sources only keep its hash
+pub fn find_webhook_source(pg: &PgDatabase, token: &str) -> Result<Option<Source>> {
+    let query = r#"SELECT * FROM sources WHERE source_type = 'Webhook' AND sources."data" @> $1;"#;
+    let json_param = json!({ "token_hash": hash_token(token) });
+    Ok(pg.find_one(query, &[&json_param])?)
+}
+
+/// Newest of the fetched status ids and the previous `since_id`, ids are increasing numbers so longer ones are newer
+pub fn newest_id<'a, I: Iterator<Item = &'a String>>(ids: I, since_id: &'a Option<String>) -> Option<String> {
+    ids.chain(since_id.iter())
+        .max_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)))
+        .cloned()
+}
+
+/// A source is disabled after this many failed fetches in a row
+pub const MAX_CONSECUTIVE_FAILURES: i32 = 10;
+const BACKOFF_BASE_SECONDS: i64 = 60;
+const BACKOFF_MAX_SECONDS: i64 = 24 * 60 * 60;
+
+/// Delay before retrying a source, doubling with each consecutive failure up to a day
+pub fn backoff_delay(failure_count: i32) -> Duration {
+    let exponent = cmp::min(cmp::max(failure_count - 1, 0), 20) as u32;
+    let seconds = cmp::min(BACKOFF_BASE_SECONDS * 2i64.pow(exponent), BACKOFF_MAX_SECONDS);
+    Duration::seconds(seconds)
+}
+
+/// Clears the failures and schedules the next fetch `fetch_interval` seconds from now
+pub fn record_fetch_success(pg: &PgDatabase, source: &Source, status: i32, fetch_interval: i64) -> Result<u64> {
+    let now = Utc::now().naive_utc();
+    let next_fetch_at = now + Duration::seconds(fetch_interval);
+    let fetch_interval = fetch_interval as i32;
+    let query = r#"
+        UPDATE sources
+        SET last_success = $1, failure_count = 0, last_status = $2, error = NULL, next_fetch_at = $3, fetch_interval = $4
+        WHERE uuid = $5::uuid;
+    "#;
+    Ok(pg.update(query, &[&now, &status, &next_fetch_at, &fetch_interval, &source.uuid])?)
+}
+
+/// Exponential backoff, or the publisher `Retry-After` when longer, never past `MAX_HINTED_FETCH_INTERVAL`
+pub fn failure_delay(failure_count: i32, retry_after: Option<i64>) -> Duration {
+    let hinted = cmp::min(cmp::max(retry_after.unwrap_or(0), 0), MAX_HINTED_FETCH_INTERVAL);
+    cmp::max(backoff_delay(failure_count), Duration::seconds(hinted))
+}
+
+/// Backs the source off exponentially, or for as long as the publisher asked with `Retry-After` when that is longer
+pub fn record_fetch_failure(pg: &PgDatabase, source: &Source, status: Option<i32>, message: &str, retry_after: Option<i64>) -> Result<u64> {
+    let now = Utc::now().naive_utc();
+    let failure_count = source.failure_count + 1;
+    let delay = failure_delay(failure_count, retry_after);
+    let next_fetch_at = now + delay;
+    let disabled = failure_count >= MAX_CONSECUTIVE_FAILURES;
+    let query = r#"
+        UPDATE sources
+        SET last_failure = $1, failure_count = $2, last_status = $3, error = $4, next_fetch_at = $5, disabled = $6
+        WHERE uuid = $7::uuid;
+    "#;
+    Ok(pg.update(query, &[&now, &failure_count, &status, &message, &next_fetch_at, &disabled, &source.uuid])?)
+}
+
+pub fn reset_fetch_health(pg: &PgDatabase, source_uuid: &Uuid) -> Result<u64> {
+    let query = r#"
+        UPDATE sources
+        SET failure_count = 0, error = NULL, next_fetch_at = NULL, disabled = false
+        WHERE uuid = $1::uuid;
+    "#;
+    Ok(pg.update(query, &[source_uuid])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_a_day() {
+        assert_eq!(backoff_delay(1), Duration::seconds(60));
+        assert_eq!(backoff_delay(2), Duration::seconds(120));
+        assert_eq!(backoff_delay(5), Duration::seconds(960));
+        assert_eq!(backoff_delay(30), Duration::seconds(BACKOFF_MAX_SECONDS));
+        assert_eq!(backoff_delay(0), Duration::seconds(60));
+    }
+
+    #[test]
+    fn longer_retry_after_wins() {
+        assert_eq!(failure_delay(1, Some(600)), Duration::seconds(600));
+        assert_eq!(failure_delay(5, Some(10)), Duration::seconds(960));
+        assert_eq!(failure_delay(1, None), Duration::seconds(60));
+    }
+
+    #[test]
+    fn hostile_retry_after_is_clamped() {
+        assert_eq!(failure_delay(1, Some(i64::max_value())), Duration::seconds(MAX_HINTED_FETCH_INTERVAL));
+        assert_eq!(failure_delay(1, Some(i64::min_value())), Duration::seconds(60));
+    }
+}
@@ -1,27 +1,195 @@
+use std::io::Read;
 use url::Url;
 use r2d2::Pool;
 use r2d2_postgres::PostgresConnectionManager;
+use reqwest::Client;
 use pg::PgDatabase;
 
-use rss::fetch_feeds_channel;
+use rss::{fetch_feeds_channel_conditional, parse_channel, Channel, FeedsChannel};
+use feed_meta::FeedMeta;
+use feed_discovery::{discover_feeds, discovery_client, FeedCandidate};
 use sources;
-use source_option::RssSource;
+use uuid::Uuid;
+use source_option::{SourceOption, RssSource, TwitterSource, MastodonSource, WebPageSource, WebPageInput, ExtractorInput};
+use web_page::{PageSelectors, scrape_items};
+use readability::fetch_html;
+use mastodon::account_instance_url;
 use source::Source;
+use user::User;
+use users_sources::user_source_exist;
+use extractors::extractor_option;
+use websub;
 use errors::*;
 
-pub fn add_rss_source_resolver(pool: Pool<PostgresConnectionManager>, xml_url: &str) -> Result<Source> {
-    Url::parse(xml_url)?;
+/// Feeds and pages given to add_rss_source larger than this are refused, the timeout only bounds each read
+const MAX_DOCUMENT_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Either the added source, or the feeds found on a web page when there is more than one to choose from
+#[derive(Debug)]
+pub struct AddRssSource {
+    pub source: Option<Source>,
+    pub candidates: Vec<FeedCandidate>,
+}
+
+graphql_object!(AddRssSource: () as "AddRssSource" |&self| {
+    description: "Added source, or feed candidates discovered on a website"
+
+    field source() -> Option<&Source> as "Added source" {
+        self.source.as_ref()
+    }
+
+    field candidates() -> Vec<FeedCandidate> as "Feeds to pick from when the url was a website advertising several" {
+        self.candidates.clone()
+    }
+});
+
+/// The url is fetched once, its document is either the feed or the page to discover feeds in
+pub fn add_rss_source_resolver(pool: Pool<PostgresConnectionManager>, url: &str) -> Result<AddRssSource> {
+    let page_url = Url::parse(url)?;
     let pg = PgDatabase::from_pool(pool)?;
-    let maybe_feed = fetch_feeds_channel(xml_url)?;
-    let feed = maybe_feed.ok_or_else(|| ErrorKind::NotFound)?;
+    let client = discovery_client()?;
+    let mut response = client.get(page_url.clone()).send()?;
+    if !response.status().is_success() {
+        bail!(ErrorKind::HttpStatus(response.status().as_u16(), None));
+    }
+    let mut body = Vec::new();
+    response.by_ref().take(MAX_DOCUMENT_SIZE + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_DOCUMENT_SIZE {
+        bail!("document larger than {} bytes", MAX_DOCUMENT_SIZE);
+    }
+    let document = String::from_utf8_lossy(&body);
+    if let Ok((feed, meta)) = parse_channel(&document) {
+        let source = insert_rss_source(&client, &pg, url, feed, meta)?;
+        return Ok(AddRssSource { source: Some(source), candidates: Vec::new() });
+    }
+    let mut candidates = discover_feeds(&client, &document, &page_url);
+    if candidates.len() == 1 {
+        let candidate = candidates.remove(0);
+        let source = create_rss_source(&client, &pg, &candidate.url)?;
+        Ok(AddRssSource { source: Some(source), candidates: Vec::new() })
+    } else if candidates.is_empty() {
+        Err(ErrorKind::NotFound.into())
+    } else {
+        Ok(AddRssSource { source: None, candidates })
+    }
+}
+
+fn create_rss_source(client: &Client, pg: &PgDatabase, xml_url: &str) -> Result<Source> {
+    match fetch_feeds_channel_conditional(client, xml_url, &None, &None)? {
+        FeedsChannel::Modified { feed, meta, .. } => insert_rss_source(client, pg, xml_url, feed, meta),
+        FeedsChannel::NotModified { .. } => Err(ErrorKind::NotFound.into()),
+    }
+}
+
+fn insert_rss_source(client: &Client, pg: &PgDatabase, xml_url: &str, feed: Channel, meta: FeedMeta) -> Result<Source> {
     let source_title = feed.title.unwrap_or_else(|| xml_url.to_string());
     let html_url = feed.website.unwrap_or_else(|| xml_url.to_string());
     let rss_source = RssSource::new(&source_title, xml_url, &html_url);
     let source = Source::new_rss(rss_source)?;
-    if !sources::source_existe(&pg, xml_url)? {
+    if !sources::source_existe(pg, xml_url)? {
         pg.insert(&source)?;
+        if let Some(hub) = meta.hub {
+            let topic = meta.self_url.unwrap_or_else(|| xml_url.to_string());
+            if let Err(error) = websub::subscribe(client, pg, &source, &hub, &topic) {
+                println!("websub subscribe {} error {}", topic, error);
+            }
+        }
         Ok(source)
     } else {
         Err(ErrorKind::AlreadyExist.into())
     }
 }
+
+/// Creates a source following a hashtag or the timeline of a user, exactly one of them must be given
+pub fn add_twitter_source_resolver(pool: Pool<PostgresConnectionManager>, hashtag: Option<String>, username: Option<String>) -> Result<Source> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let clean = |value: Option<String>, prefix: char| value
+        .map(|value| value.trim().trim_left_matches(prefix).to_owned())
+        .and_then(|value| if value.is_empty() { None } else { Some(value) });
+    let twitter_source = match (clean(hashtag, '#'), clean(username, '@')) {
+        (Some(hashtag), None) => TwitterSource::new(Some(hashtag), None),
+        (None, Some(username)) => TwitterSource::new(None, Some(username)),
+        _ => bail!("either a hashtag or a username is expected"),
+    };
+    if sources::find_twitter_source(&pg, &twitter_source)?.is_some() {
+        return Err(ErrorKind::AlreadyExist.into());
+    }
+    let source = Source::new_twitter(twitter_source)?;
+    pg.insert(&source)?;
+    Ok(source)
+}
+
+/// Creates a source following a `user@instance` account, or a hashtag on `instance_url`
+pub fn add_mastodon_source_resolver(pool: Pool<PostgresConnectionManager>, account: Option<String>, hashtag: Option<String>, instance_url: Option<String>) -> Result<Source> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let account = account.map(|account| account.trim().trim_left_matches('@').to_owned()).and_then(|account| if account.is_empty() { None } else { Some(account) });
+    let hashtag = hashtag.map(|hashtag| hashtag.trim().trim_left_matches('#').to_owned()).and_then(|hashtag| if hashtag.is_empty() { None } else { Some(hashtag) });
+    let mastodon_source = match (account, hashtag) {
+        (Some(account), None) => {
+            let instance_url = match instance_url {
+                Some(instance_url) => instance_url,
+                None => account_instance_url(&account).ok_or("the account is expected as user@instance")?,
+            };
+            MastodonSource::new(&instance_url, Some(account), None)
+        },
+        (None, Some(hashtag)) => {
+            let instance_url = instance_url.ok_or("an instance url is expected to follow a hashtag")?;
+            MastodonSource::new(&instance_url, None, Some(hashtag))
+        },
+        _ => bail!("either an account or a hashtag is expected"),
+    };
+    Url::parse(&mastodon_source.instance_url)?;
+    if sources::find_mastodon_source(&pg, &mastodon_source)?.is_some() {
+        return Err(ErrorKind::AlreadyExist.into());
+    }
+    let source = Source::new_mastodon(mastodon_source)?;
+    pg.insert(&source)?;
+    Ok(source)
+}
+
+/// Creates a source scraping the items of a page without feed, the page must have at least one matching item
+pub fn add_web_page_source_resolver(pool: Pool<PostgresConnectionManager>, input: WebPageInput) -> Result<Source> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let page_url = Url::parse(&input.url)?;
+    let web_page_source = WebPageSource {
+        title: input.title.clone().unwrap_or_else(|| input.url.clone()),
+        url: page_url.as_str().to_owned(),
+        item_selector: input.item_selector,
+        title_selector: input.title_selector,
+        link_selector: input.link_selector,
+        date_selector: input.date_selector,
+        content_selector: input.content_selector,
+    };
+    let selectors = PageSelectors::new(&web_page_source)?;
+    if sources::find_web_page_source(&pg, &web_page_source.url, &web_page_source.item_selector)?.is_some() {
+        return Err(ErrorKind::AlreadyExist.into());
+    }
+    let html = fetch_html(&Client::new(), page_url.as_str())?;
+    if scrape_items(&html, &page_url, &selectors).is_empty() {
+        bail!("no element of the page matches the item selector");
+    }
+    let source = Source::new_web_page(web_page_source)?;
+    pg.insert(&source)?;
+    Ok(source)
+}
+
+/// Changes how the readable content of a followed rss source is extracted, for every follower so only admins can
+pub fn set_source_extractor_resolver(pool: Pool<PostgresConnectionManager>, source_uuid: &str, input: ExtractorInput, user: &User) -> Result<Source> {
+    if !user.is_admin() {
+        bail!("only admins can change the extractor of a source");
+    }
+    let pg = PgDatabase::from_pool(pool)?;
+    let source_uuid = Uuid::parse_str(source_uuid)?;
+    if !user_source_exist(&pg, &source_uuid, user)? {
+        return Err(ErrorKind::NotFound.into());
+    }
+    let source = sources::find_source_by_uuid(&pg, &source_uuid)?.ok_or(ErrorKind::NotFound)?;
+    let mut rss_source = match source.options()? {
+        SourceOption::Rss(rss_source) => rss_source,
+        _ => return Err(ErrorKind::NotFound.into()),
+    };
+    rss_source.extractor = extractor_option(input)?;
+    sources::update_rss_source(&pg, &source, &rss_source)?;
+    let source = sources::find_source_by_uuid(&pg, &source_uuid)?.ok_or(ErrorKind::NotFound)?;
+    Ok(source)
+}
@@ -1,5 +1,6 @@
 use jsonwebtoken::{encode, decode, Header, Validation};
 use uuid::Uuid;
+use chrono::prelude::*;
 use rocket::request::{self, Request, FromRequest};
 use rocket::outcome::Outcome;
 use rocket::http::Status;
@@ -7,18 +8,26 @@ use rocket::State;
 
 use errors::*;
 use config::Config;
+use pg::{DbConn, PgDatabase};
+use refresh_tokens::is_session_active;
+
+/// Access tokens are short lived, clients renew them with their refresh token
+pub const ACCESS_TOKEN_SECONDS: i64 = 15 * 60;
 
 #[derive(Debug)]
 pub struct AuthData {
     pub uuid: Uuid,
     pub email: String,
+    /// Session the token was issued for, revoking it rejects the token
+    pub session_uuid: Uuid,
 }
 
 impl AuthData {
-    pub fn new(uuid: Uuid, email: String) -> Self {
+    pub fn new(uuid: Uuid, email: String, session_uuid: Uuid) -> Self {
         AuthData {
             uuid: uuid,
-            email: email
+            email: email,
+            session_uuid: session_uuid,
         }
     }
 }
@@ -27,41 +36,66 @@ impl AuthData {
 pub struct Claime {
     pub uuid: String,
     pub email: String,
+    /// Session uuid, the family of its refresh tokens
+    pub sid: String,
+    pub jti: String,
+    pub iat: i64,
+    pub exp: i64,
 }
 
 impl Claime {
-    pub fn new(uuid: String, email: String) -> Self {
+    pub fn new(uuid: String, email: String, sid: String) -> Self {
+        let now = Utc::now().timestamp();
         Claime {
             uuid: uuid,
-            email: email
+            email: email,
+            sid: sid,
+            jti: Uuid::new_v4().hyphenated().to_string(),
+            iat: now,
+            exp: now + ACCESS_TOKEN_SECONDS,
         }
     }
     pub fn to_auth(&self) -> Result<AuthData> {
-        let auth_data = Uuid::parse_str(&self.uuid).map(move |uuid| AuthData::new(uuid, self.email.clone()))?;
-        Ok(auth_data)
+        let uuid = Uuid::parse_str(&self.uuid)?;
+        let session_uuid = Uuid::parse_str(&self.sid)?;
+        Ok(AuthData::new(uuid, self.email.clone(), session_uuid))
     }
 }
 
-pub fn create_token(uuid: Uuid, email: String, secret_key: &str) -> Result<String> {
-    let claims = Claime::new(uuid.hyphenated().to_string(), email.clone());
+pub fn create_token(uuid: Uuid, email: String, session_uuid: Uuid, secret_key: &str) -> Result<String> {
+    let claims = Claime::new(uuid.hyphenated().to_string(), email.clone(), session_uuid.hyphenated().to_string());
     let token = encode(&Header::default(), &claims, secret_key.as_bytes())?;
     Ok(token)
 }
 
-pub fn decode_auth(token: &str, secret_key: &str) -> Result<AuthData> {
+/// Checks the signature and the expiration, tokens issued before sessions existed have no `sid` and are refused
+pub fn decode_token(token: &str, secret_key: &str) -> Result<AuthData> {
     let claims = decode::<Claime>(token, secret_key.as_bytes(), &Validation::default())?;
     let claims = claims.claims;
+    if claims.exp <= Utc::now().timestamp() {
+        return Err(ErrorKind::WrongCredentials.into());
+    }
     let auth = claims.to_auth()?;
     Ok(auth)
 }
 
+/// Valid token of a session that was not logged out nor revoked
+pub fn decode_auth(pg: &PgDatabase, token: &str, secret_key: &str) -> Result<AuthData> {
+    let auth = decode_token(token, secret_key)?;
+    if !is_session_active(pg, &auth.session_uuid)? {
+        return Err(ErrorKind::WrongCredentials.into());
+    }
+    Ok(auth)
+}
+
 impl<'a, 'r> FromRequest<'a, 'r> for AuthData {
     type Error = ();
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
         let config = request.guard::<State<Config>>()?;
+        let pg: PgDatabase = request.guard::<DbConn>()?.into();
         match request.headers().get("Authorization").next() {
             Some(token) => {
-                match decode_auth(token, config.secret_key.as_ref()) {
+                match decode_auth(&pg, token, config.secret_key.as_ref()) {
                     Ok(auth) => {
                         Outcome::Success(auth)
                     },
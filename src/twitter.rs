@@ -0,0 +1,362 @@
+use std::cmp;
+use std::str;
+use chrono::prelude::*;
+use reqwest::Client;
+use reqwest::header::{Authorization, Bearer, Headers};
+use url::Url;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use errors::*;
+use config::CONFIG;
+use pg::PgDatabase;
+use feeds::{is_feed_exist, insert_feed, Feed};
+use source::Source;
+use source_option::TwitterSource;
+use sources::{newest_id, update_twitter_source, record_fetch_success, record_fetch_failure};
+use user::User;
+use rss::insert_subscribers_feeds;
+use filter_rules::{SubscriberRules, find_enabled_rules};
+use schedule::DEFAULT_FETCH_INTERVAL;
+
+/// Tweets asked per request, the most the search and timeline endpoints return
+const MAX_RESULTS: &str = "100";
+const TWEET_FIELDS: &str = "created_at,public_metrics,attachments";
+const EXPANSIONS: &str = "author_id,attachments.media_keys";
+const USER_FIELDS: &str = "name,username,profile_image_url";
+const MEDIA_FIELDS: &str = "url,preview_image_url";
+
+/// Tweet as stored in `feeds.twitter`
+#[derive(GraphQLObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tweet {
+    pub id: String,
+    pub text: String,
+    pub url: String,
+    pub author_username: String,
+    pub author_name: Option<String>,
+    pub author_image_url: Option<String>,
+    pub created_at: Option<String>,
+    pub retweet_count: i32,
+    pub like_count: i32,
+    pub media_urls: Vec<String>,
+}
+
+/// Timelines a twitter source can follow, implemented over http and by mocks
+pub trait TwitterClient {
+    /// Recent tweets with `hashtag`, newer than `since_id`
+    fn search_hashtag(&self, hashtag: &str, since_id: &Option<String>) -> Result<Vec<Tweet>>;
+    /// Tweets posted by `username`, newer than `since_id`
+    fn user_timeline(&self, username: &str, since_id: &Option<String>) -> Result<Vec<Tweet>>;
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiMetrics {
+    #[serde(default)]
+    retweet_count: i32,
+    #[serde(default)]
+    like_count: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiAttachments {
+    #[serde(default)]
+    media_keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiTweet {
+    id: String,
+    text: String,
+    author_id: Option<String>,
+    created_at: Option<String>,
+    public_metrics: Option<ApiMetrics>,
+    attachments: Option<ApiAttachments>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiUser {
+    id: String,
+    name: Option<String>,
+    username: String,
+    profile_image_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiMedia {
+    media_key: String,
+    url: Option<String>,
+    preview_image_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ApiIncludes {
+    #[serde(default)]
+    users: Vec<ApiUser>,
+    #[serde(default)]
+    media: Vec<ApiMedia>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiTimeline {
+    #[serde(default)]
+    data: Vec<ApiTweet>,
+    #[serde(default)]
+    includes: ApiIncludes,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiUserLookup {
+    data: ApiUser,
+}
+
+impl ApiTimeline {
+    fn into_tweets(self) -> Vec<Tweet> {
+        let includes = self.includes;
+        self.data.into_iter().map(|tweet| {
+            let author = tweet.author_id.as_ref()
+                .and_then(|author_id| includes.users.iter().find(|user| &user.id == author_id));
+            let author_username = author.map(|author| author.username.clone()).unwrap_or_else(|| "i".to_owned());
+            let media_keys = tweet.attachments.map(|attachments| attachments.media_keys).unwrap_or_else(Vec::new);
+            let media_urls = media_keys.iter()
+                .filter_map(|key| includes.media.iter().find(|media| &media.media_key == key))
+                .filter_map(|media| media.url.clone().or_else(|| media.preview_image_url.clone()))
+                .collect();
+            let metrics = tweet.public_metrics.unwrap_or(ApiMetrics { retweet_count: 0, like_count: 0 });
+            Tweet {
+                url: format!("https://twitter.com/{}/status/{}", author_username, tweet.id),
+                id: tweet.id,
+                text: tweet.text,
+                author_name: author.and_then(|author| author.name.clone()),
+                author_image_url: author.and_then(|author| author.profile_image_url.clone()),
+                author_username,
+                created_at: tweet.created_at,
+                retweet_count: metrics.retweet_count,
+                like_count: metrics.like_count,
+                media_urls,
+            }
+        }).collect()
+    }
+}
+
+/// Twitter API v2 client authenticated with an app bearer token
+pub struct HttpTwitterClient<'a> {
+    client: &'a Client,
+    api_url: String,
+    bearer_token: String,
+}
+
+impl<'a> HttpTwitterClient<'a> {
+    pub fn new(client: &'a Client, api_url: &str, bearer_token: &str) -> Self {
+        HttpTwitterClient {
+            client,
+            api_url: api_url.trim_right_matches('/').to_owned(),
+            bearer_token: bearer_token.to_owned(),
+        }
+    }
+
+    /// Client configured with TWITTER_API_URL and TWITTER_BEARER_TOKEN, none without a token
+    pub fn from_config(client: &'a Client) -> Option<Self> {
+        CONFIG.twitter_bearer_token.as_ref().map(|token| HttpTwitterClient::new(client, &CONFIG.twitter_api_url, token))
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str, params: &[(&str, &str)]) -> Result<T> {
+        let url = Url::parse_with_params(&format!("{}{}", self.api_url, path), params)?;
+        let mut response = self.client.get(url)
+            .header(Authorization(Bearer { token: self.bearer_token.clone() }))
+            .send()?;
+        if !response.status().is_success() {
+            bail!(ErrorKind::HttpStatus(response.status().as_u16(), rate_limit_reset(response.headers())));
+        }
+        Ok(response.json()?)
+    }
+
+    fn timeline(&self, path: &str, query: Option<&str>, since_id: &Option<String>) -> Result<Vec<Tweet>> {
+        let mut params = vec![
+            ("max_results", MAX_RESULTS),
+            ("tweet.fields", TWEET_FIELDS),
+            ("expansions", EXPANSIONS),
+            ("user.fields", USER_FIELDS),
+            ("media.fields", MEDIA_FIELDS),
+        ];
+        if let Some(query) = query {
+            params.push(("query", query));
+        }
+        if let Some(ref since_id) = *since_id {
+            params.push(("since_id", since_id));
+        }
+        let timeline: ApiTimeline = self.get(path, &params)?;
+        Ok(timeline.into_tweets())
+    }
+}
+
+impl<'a> TwitterClient for HttpTwitterClient<'a> {
+    fn search_hashtag(&self, hashtag: &str, since_id: &Option<String>) -> Result<Vec<Tweet>> {
+        let query = format!("#{} -is:retweet", hashtag.trim_left_matches('#'));
+        self.timeline("/2/tweets/search/recent", Some(&query), since_id)
+    }
+
+    fn user_timeline(&self, username: &str, since_id: &Option<String>) -> Result<Vec<Tweet>> {
+        let username = username.trim_left_matches('@');
+        let user: ApiUserLookup = self.get(&format!("/2/users/by/username/{}", username), &[("user.fields", USER_FIELDS)])?;
+        self.timeline(&format!("/2/users/{}/tweets", user.data.id), None, since_id)
+    }
+}
+
+/// Seconds until the rate limit window resets, the API sends it as an epoch in `x-rate-limit-reset`
+fn rate_limit_reset(headers: &Headers) -> Option<i64> {
+    headers.get_raw("x-rate-limit-reset")
+        .and_then(|raw| raw.one())
+        .and_then(|bytes| str::from_utf8(bytes).ok())
+        .and_then(|reset| reset.trim().parse::<i64>().ok())
+        .map(|reset| cmp::max(reset.saturating_sub(Utc::now().timestamp()), 0))
+}
+
+fn fetch_tweets(twitter_client: &TwitterClient, twitter_source: &TwitterSource) -> Result<Vec<Tweet>> {
+    match (&twitter_source.hashtag, &twitter_source.username) {
+        (&Some(ref hashtag), _) => twitter_client.search_hashtag(hashtag, &twitter_source.since_id),
+        (&None, &Some(ref username)) => twitter_client.user_timeline(username, &twitter_source.since_id),
+        (&None, &None) => bail!("twitter source without hashtag nor username"),
+    }
+}
+
+pub fn ingest_tweets(subscribers: &Vec<User>, source: &Source, tweets: &Vec<Tweet>, pg: &PgDatabase) -> Result<()> {
+    let filter_rules = find_enabled_rules(pg, subscribers)?;
+    let rules = SubscriberRules::new(&filter_rules);
+    for tweet in tweets {
+        if !is_feed_exist(pg, &tweet.url, source)? {
+            let feed = Feed::new(&tweet.url, None, None, serde_json::to_value(tweet).ok(), None, source.uuid);
+            if insert_feed(pg, &feed).is_ok() {
+                insert_subscribers_feeds(subscribers, &rules, &feed, pg)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn process_twitter_source(subscribers: &Vec<User>, source: &Source, twitter_source: &TwitterSource, twitter_client: &TwitterClient, pg: &PgDatabase) -> Result<()> {
+    match fetch_tweets(twitter_client, twitter_source) {
+        Ok(tweets) => {
+            ingest_tweets(subscribers, source, &tweets, pg)?;
+            let since_id = newest_id(tweets.iter().map(|tweet| &tweet.id), &twitter_source.since_id);
+            if since_id != twitter_source.since_id {
+                let mut twitter_source = twitter_source.clone();
+                twitter_source.since_id = since_id;
+                update_twitter_source(pg, source, &twitter_source)?;
+            }
+            record_fetch_success(pg, source, 200, DEFAULT_FETCH_INTERVAL)?;
+        },
+        Err(error) => {
+            println!("twitter {:?} error {}", source.uuid, error);
+            let (status, retry_after) = match *error.kind() {
+                ErrorKind::HttpStatus(status, retry_after) => (Some(status as i32), retry_after),
+                _ => (None, None),
+            };
+            record_fetch_failure(pg, source, status, &error.to_string(), retry_after)?;
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Answers canned tweets and records the calls it gets
+    struct MockTwitterClient {
+        tweets: Vec<Tweet>,
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl MockTwitterClient {
+        fn new(tweets: Vec<Tweet>) -> Self {
+            MockTwitterClient { tweets, calls: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl TwitterClient for MockTwitterClient {
+        fn search_hashtag(&self, hashtag: &str, since_id: &Option<String>) -> Result<Vec<Tweet>> {
+            self.calls.borrow_mut().push(format!("search {} {:?}", hashtag, since_id));
+            Ok(self.tweets.clone())
+        }
+
+        fn user_timeline(&self, username: &str, since_id: &Option<String>) -> Result<Vec<Tweet>> {
+            self.calls.borrow_mut().push(format!("timeline {} {:?}", username, since_id));
+            Ok(self.tweets.clone())
+        }
+    }
+
+    fn tweet(id: &str) -> Tweet {
+        Tweet {
+            id: id.to_owned(),
+            text: "text".to_owned(),
+            url: format!("https://twitter.com/rustlang/status/{}", id),
+            author_username: "rustlang".to_owned(),
+            author_name: None,
+            author_image_url: None,
+            created_at: None,
+            retweet_count: 0,
+            like_count: 0,
+            media_urls: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn hashtag_sources_search() {
+        let client = MockTwitterClient::new(vec![tweet("2"), tweet("10")]);
+        let mut source = TwitterSource::new(Some("rust".to_owned()), None);
+        source.since_id = Some("1".to_owned());
+        let tweets = fetch_tweets(&client, &source).unwrap();
+        assert_eq!(tweets.len(), 2);
+        assert_eq!(*client.calls.borrow(), vec!["search rust Some(\"1\")".to_owned()]);
+        assert_eq!(newest_id(tweets.iter().map(|tweet| &tweet.id), &source.since_id), Some("10".to_owned()));
+    }
+
+    #[test]
+    fn username_sources_read_the_timeline() {
+        let client = MockTwitterClient::new(Vec::new());
+        let source = TwitterSource::new(None, Some("rustlang".to_owned()));
+        assert!(fetch_tweets(&client, &source).unwrap().is_empty());
+        assert_eq!(*client.calls.borrow(), vec!["timeline rustlang None".to_owned()]);
+    }
+
+    #[test]
+    fn empty_sources_are_refused() {
+        let client = MockTwitterClient::new(Vec::new());
+        assert!(fetch_tweets(&client, &TwitterSource::new(None, None)).is_err());
+        assert!(client.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn timeline_joins_authors_and_media() {
+        let timeline: ApiTimeline = serde_json::from_str(r#"{
+            "data": [{
+                "id": "42",
+                "text": "hello",
+                "author_id": "7",
+                "public_metrics": {"retweet_count": 3, "like_count": 5},
+                "attachments": {"media_keys": ["m1"]}
+            }],
+            "includes": {
+                "users": [{"id": "7", "name": "Rust", "username": "rustlang"}],
+                "media": [{"media_key": "m1", "preview_image_url": "https://pbs.twimg.com/m1.jpg"}]
+            }
+        }"#).unwrap();
+        let tweets = timeline.into_tweets();
+        assert_eq!(tweets.len(), 1);
+        assert_eq!(tweets[0].url, "https://twitter.com/rustlang/status/42");
+        assert_eq!(tweets[0].author_name, Some("Rust".to_owned()));
+        assert_eq!(tweets[0].retweet_count, 3);
+        assert_eq!(tweets[0].like_count, 5);
+        assert_eq!(tweets[0].media_urls, vec!["https://pbs.twimg.com/m1.jpg".to_owned()]);
+    }
+
+    #[test]
+    fn reset_in_the_past_is_now() {
+        let mut headers = Headers::new();
+        headers.set_raw("x-rate-limit-reset", "0");
+        assert_eq!(rate_limit_reset(&headers), Some(0));
+        headers.set_raw("x-rate-limit-reset", format!("{}", Utc::now().timestamp() + 60));
+        assert!(rate_limit_reset(&headers).unwrap() > 50);
+    }
+}
@@ -6,6 +6,7 @@ use bcrypt::{DEFAULT_COST, hash, verify};
 
 use errors::*;
 use schema::users;
+use config::CONFIG;
 
 #[derive(Debug, PartialEq, Identifiable, Queryable, Insertable, Validate)]
 #[primary_key(uuid)]
@@ -20,6 +21,8 @@ pub struct User {
     pub password: String,
     pub created: Option<NaiveDateTime>,
     pub updated: Option<NaiveDateTime>,
+    /// Set once the user followed the link of the verification mail
+    pub email_verified: bool,
 }
 
 graphql_object!(User: () |&self| {
@@ -35,6 +38,10 @@ graphql_object!(User: () |&self| {
         &self.email
     }
 
+    field email_verified() -> bool {
+        self.email_verified
+    }
+
     field created() -> &Option<NaiveDateTime> {
         &self.created
     }
@@ -55,11 +62,17 @@ impl<'a> From<Row<'a>> for User {
             password: row.get("password"),
             created: row.get("created"),
             updated: row.get("updated"),
+            email_verified: row.get("email_verified"),
         }
     }
 }
 
 impl User {
+    /// Listed in ADMIN_LOGINS
+    pub fn is_admin(&self) -> bool {
+        CONFIG.admin_logins.contains(&self.login)
+    }
+
     pub fn new_secure(login: String, email: String, password: String) -> Result<User> {
         let hashed_password = hash_password(&password)?;
         let user = User {
@@ -69,6 +82,7 @@ impl User {
             password: hashed_password,
             created: Some(Utc::now().naive_utc()),
             updated: Some(Utc::now().naive_utc()),
+            email_verified: false,
         };
         Ok(user)
     }
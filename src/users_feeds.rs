@@ -22,9 +22,13 @@ pub struct UserFeed {
     pub user_uuid: Uuid,
     pub created: NaiveDateTime,
     pub updated: NaiveDateTime,
+    /// Seconds already listened or watched of the item enclosure
+    pub playback_position: Option<i32>,
+    /// Set by the filter rules of the user
+    pub tags: Vec<String>,
 }
 
-#[derive(Debug, EnumString, ToString, ToSql, FromSql)]
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, ToString, ToSql, FromSql)]
 #[postgres(name = "reaction")]
 pub enum Reaction {
     Unreaded,
@@ -45,6 +49,8 @@ impl UserFeed {
             feed_uuid,
             created: Utc::now().naive_utc(),
             updated: Utc::now().naive_utc(),
+            playback_position: None,
+            tags: Vec::new(),
         }
     }
 }
@@ -58,6 +64,8 @@ impl<'a> From<Row<'a>> for UserFeed {
             feed_uuid: row.get("feed_uuid"),
             created: row.get("created"),
             updated: row.get("updated"),
+            playback_position: row.get("playback_position"),
+            tags: row.get("tags"),
         }
     }
 }
@@ -65,12 +73,12 @@ impl<'a> From<Row<'a>> for UserFeed {
 impl Insertable for UserFeed {
     fn insert_query(&self) -> String {
         r#"
-            INSERT INTO users_feeds (uuid, reaction, user_uuid, feed_uuid, created, updated) VALUES ($1::uuid, $2, $3::uuid, $4::uuid, $5, $6)
+            INSERT INTO users_feeds (uuid, reaction, user_uuid, feed_uuid, created, updated, tags) VALUES ($1::uuid, $2, $3::uuid, $4::uuid, $5, $6, $7)
         "#.to_owned()
     }
 
     fn insert_params(&self) -> Box<[&ToSql]> {
-        Box::new([&self.uuid, &self.reaction, &self.user_uuid, &self.feed_uuid, &self.created, &self.updated])
+        Box::new([&self.uuid, &self.reaction, &self.user_uuid, &self.feed_uuid, &self.created, &self.updated, &self.tags])
     }
 }
 
@@ -100,6 +108,35 @@ pub fn reaction_feed_resolver(pool: Pool<PostgresConnectionManager>, feed_uuid:
     Ok(update_feed_reaction(&pg, &feed_uuid, &reaction, user)?)
 }
 
+pub fn update_playback_position(pg: &PgDatabase, feed_uuid: &Uuid, position: i32, user: &User) -> Result<u64> {
+    let query = r#"
+        UPDATE users_feeds SET playback_position = $1, playback_updated = $2
+        WHERE users_feeds.user_uuid = $3::uuid
+        AND users_feeds.feed_uuid = $4::uuid
+    "#;
+    Ok(pg.update(query, &[&position, &Utc::now().naive_utc(), &user.uuid, feed_uuid])?)
+}
+
+pub fn set_playback_position_resolver(pool: Pool<PostgresConnectionManager>, feed_uuid: &str, position: i32, user: &User) -> Result<i32> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let feed_uuid = Uuid::parse_str(feed_uuid)?;
+    if position < 0 {
+        bail!("playback position must be positive");
+    }
+    if update_playback_position(&pg, &feed_uuid, position, user)? == 0 {
+        return Err(ErrorKind::NotFound.into());
+    }
+    Ok(position)
+}
+
+pub fn playback_position_resolver(pool: Pool<PostgresConnectionManager>, feed_uuid: &str, user: &User) -> Result<Option<i32>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let feed_uuid = Uuid::parse_str(feed_uuid)?;
+    let query = "SELECT * FROM users_feeds WHERE user_uuid = $1::uuid AND feed_uuid = $2::uuid;";
+    let user_feed: Option<UserFeed> = pg.find_one(query, &[&user.uuid, &feed_uuid])?;
+    Ok(user_feed.and_then(|user_feed| user_feed.playback_position))
+}
+
 pub fn users_feeds_resolver(pool: Pool<PostgresConnectionManager>, limit: i32, offset: i32, user: &User) -> Result<Vec<Feed>> {
     let pg = PgDatabase::from_pool(pool)?;
     let query = r#"
@@ -153,3 +190,16 @@ pub fn feeds_by_reaction_resolver(pool: Pool<PostgresConnectionManager>, reactio
     "#;
     Ok(pg.find(query, &[&reaction, &user.uuid, &limit, &offset])?)
 }
+
+pub fn feeds_by_tag_resolver(pool: Pool<PostgresConnectionManager>, tag: &str, limit: i32, offset: i32, user: &User) -> Result<Vec<Feed>> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let query = r#"
+        SELECT feeds.* FROM feeds
+        JOIN users_feeds ON users_feeds.feed_uuid = feeds.uuid
+        WHERE $1 = ANY(users_feeds.tags)
+        AND users_feeds.user_uuid = $2
+        ORDER BY feeds.updated DESC
+        LIMIT $3::int OFFSET $4::int;
+    "#;
+    Ok(pg.find(query, &[&tag, &user.uuid, &limit, &offset])?)
+}
@@ -1,35 +1,128 @@
 use r2d2::Pool;
 use r2d2_diesel::ConnectionManager;
+use r2d2_postgres::PostgresConnectionManager;
 use diesel::PgConnection;
+use uuid::Uuid;
+use chrono::prelude::*;
 use validator::Validate;
 
 use errors::*;
+use pg::PgDatabase;
 use users_repository;
-use user::{User, verify_password};
-use token;
+use user::{User, hash_password, verify_password};
+use token::{self, AuthData};
+use refresh_tokens::{self, AuthTokens};
+use sessions::{ClientInfo, touch_session};
+use account_tokens;
+use data_export;
+use sources::{find_private_sources, delete_sources};
 use config::Config;
 
-pub fn signup_resolver(pool: &Pool<ConnectionManager<PgConnection>>, config: &Config, user: User) -> Result<String> {
+pub fn signup_resolver(pool: &Pool<ConnectionManager<PgConnection>>, pg_pool: Pool<PostgresConnectionManager>, config: &Config, client: &ClientInfo, user: User, device_label: Option<String>) -> Result<AuthTokens> {
     let connection = pool.get()?;
     let _ = user.validate()?;
     let _ = users_repository::insert(&connection, &user)?;
-    let token = token::create_token(user.uuid, user.email, config.secret_key.as_ref())?;
-    Ok(token)
+    let pg = PgDatabase::from_pool(pg_pool)?;
+    account_tokens::send_email_verification(&pg, config, &user, &user.email)?;
+    refresh_tokens::start_session(&pg, config, &user, device_label, client)
 }
 
-pub fn login_resolver(pool: &Pool<ConnectionManager<PgConnection>>, config: &Config, email: String, password: String) -> Result<String> {
+pub fn login_resolver(pool: &Pool<ConnectionManager<PgConnection>>, pg_pool: Pool<PostgresConnectionManager>, config: &Config, client: &ClientInfo, email: String, password: String, device_label: Option<String>) -> Result<AuthTokens> {
     let connection = pool.get()?;
     let user = users_repository::find_by_email(&connection, &email)?;
     if let Ok(true) = verify_password(&password, &user.password) {
-        Ok(token::create_token(user.uuid, email.to_owned(), config.secret_key.as_ref())?)
+        let pg = PgDatabase::from_pool(pg_pool)?;
+        refresh_tokens::start_session(&pg, config, &user, device_label, client)
     } else {
         Err(ErrorKind::WrongCredentials.into())
     }
 }
 
-pub fn auth_resolver<E>(pool: &Pool<ConnectionManager<PgConnection>>, config: &Config, token: String) -> Result<E> where E: From<User> {
+pub fn auth_resolver<E>(pool: &Pool<ConnectionManager<PgConnection>>, pg_pool: Pool<PostgresConnectionManager>, config: &Config, client: &ClientInfo, token: String) -> Result<E> where E: From<(User, AuthData)> {
     let connection = pool.get()?;
-    let auth_data = token::decode_auth(&token, config.secret_key.as_ref())?;
+    let pg = PgDatabase::from_pool(pg_pool)?;
+    let auth_data = token::decode_auth(&pg, &token, config.secret_key.as_ref())?;
+    touch_session(&pg, &auth_data.session_uuid, client)?;
     let user = users_repository::find_by_email(&connection, &auth_data.email)?;
-    Ok(user.into())
+    Ok((user, auth_data).into())
+}
+
+/// Runs the `User` validation rules on the changed fields, the password is the clear one
+fn validate_changes(user: &User, login: Option<&str>, email: Option<&str>, password: Option<&str>) -> Result<()> {
+    let changed = User {
+        uuid: user.uuid,
+        login: login.unwrap_or(&user.login).to_owned(),
+        email: email.unwrap_or(&user.email).to_owned(),
+        password: password.unwrap_or("unchanged").to_owned(),
+        created: user.created,
+        updated: user.updated,
+        email_verified: user.email_verified,
+    };
+    let _ = changed.validate()?;
+    Ok(())
+}
+
+fn check_password(password: &str, user: &User) -> Result<()> {
+    match verify_password(password, &user.password) {
+        Ok(true) => Ok(()),
+        _ => Err(ErrorKind::WrongCredentials.into()),
+    }
+}
+
+/// Logs out the other sessions, the current one stays open
+pub fn change_password_resolver(pg_pool: Pool<PostgresConnectionManager>, current_password: &str, new_password: &str, session_uuid: &Uuid, user: &User) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pg_pool)?;
+    check_password(current_password, user)?;
+    validate_changes(user, None, None, Some(new_password))?;
+    let query = "UPDATE users SET password = $1, updated = $2 WHERE uuid = $3::uuid;";
+    pg.update(query, &[&hash_password(new_password)?, &Utc::now().naive_utc(), &user.uuid])?;
+    refresh_tokens::revoke_other_families(&pg, &user.uuid, session_uuid)?;
+    Ok(true)
+}
+
+/// The email only changes once the verification mail sent to the new address is followed
+pub fn change_email_resolver(pg_pool: Pool<PostgresConnectionManager>, config: &Config, password: &str, email: &str, user: &User) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pg_pool)?;
+    check_password(password, user)?;
+    let email = email.trim();
+    if email.to_lowercase() == user.email.to_lowercase() {
+        bail!("email unchanged");
+    }
+    validate_changes(user, None, Some(email), None)?;
+    let query = "SELECT COUNT(*) AS exist FROM users WHERE lower(email) = lower($1);";
+    if pg.exist(query, &[&email])? {
+        return Err(ErrorKind::AlreadyExist.into());
+    }
+    account_tokens::send_email_verification(&pg, config, user, email)?;
+    Ok(true)
+}
+
+pub fn change_login_resolver(pg_pool: Pool<PostgresConnectionManager>, login: &str, user: &User) -> Result<User> {
+    let pg = PgDatabase::from_pool(pg_pool)?;
+    let login = login.trim();
+    validate_changes(user, Some(login), None, None)?;
+    let query = "UPDATE users SET login = $1, updated = $2 WHERE uuid = $3::uuid;";
+    pg.update(query, &[&login, &Utc::now().naive_utc(), &user.uuid])?;
+    let query = "SELECT * FROM users WHERE uuid = $1::uuid;";
+    Ok(pg.find_one(query, &[&user.uuid])?.ok_or(ErrorKind::NotFound)?)
+}
+
+/// Deletes the user, the rows it owns go with it, then its private sources and their items
+pub fn delete_account_resolver(pg_pool: Pool<PostgresConnectionManager>, config: &Config, password: &str, user: &User) -> Result<bool> {
+    let pg = PgDatabase::from_pool(pg_pool)?;
+    check_password(password, user)?;
+    let exports = data_export::find_user_exports(&pg, user)?;
+    pg.transaction(|pg| {
+        let private_sources: Vec<Uuid> = find_private_sources(pg, &user.uuid)?.iter().map(|source| source.uuid).collect();
+        if pg.update("DELETE FROM users WHERE uuid = $1::uuid;", &[&user.uuid])? == 0 {
+            return Err(ErrorKind::NotFound.into());
+        }
+        if !private_sources.is_empty() {
+            delete_sources(pg, &private_sources)?;
+        }
+        Ok(())
+    })?;
+    // Only once the deletion is committed, a rolled back one keeps the archives downloadable
+    data_export::remove_export_files(&config.export_dir, &exports);
+    Ok(true)
 }
@@ -9,6 +9,8 @@ use graphql::query::Query;
 use user::User;
 use source::Source;
 use users_feeds::Reaction;
+use sources::reset_fetch_health;
+use smart_feeds::smart_feeds_stats;
 use pg::{Insertable, PgDatabase};
 
 #[derive(Debug)]
@@ -16,15 +18,16 @@ pub struct UserSource {
     pub uuid: Uuid,
     pub user_uuid: Uuid,
     pub source_uuid: Uuid,
+    pub category: Option<String>,
 }
 
 impl UserSource {
-    #[allow(dead_code)]
-    pub fn new(user_uuid: Uuid, source_uuid: Uuid) -> Self {
+    pub fn new(user_uuid: Uuid, source_uuid: Uuid, category: Option<String>) -> Self {
         UserSource {
             uuid: Uuid::new_v4(),
             user_uuid,
             source_uuid,
+            category,
         }
     }
 }
@@ -35,6 +38,7 @@ impl<'a> From<Row<'a>> for UserSource {
             uuid: row.get("uuid"),
             user_uuid: row.get("user_uuid"),
             source_uuid: row.get("source_uuid"),
+            category: row.get("category"),
         }
     }
 }
@@ -42,12 +46,12 @@ impl<'a> From<Row<'a>> for UserSource {
 impl Insertable for UserSource {
     fn insert_query(&self) -> String {
         r#"
-            INSERT INTO users_sources (uuid, user_uuid, source_uuid) VALUES ($1::uuid, $2::uuid, $3::uuid)
+            INSERT INTO users_sources (uuid, user_uuid, source_uuid, category) VALUES ($1::uuid, $2::uuid, $3::uuid, $4)
         "#.to_owned()
     }
 
     fn insert_params(&self) -> Box<[&ToSql]> {
-        Box::new([&self.uuid, &self.user_uuid, &self.source_uuid])
+        Box::new([&self.uuid, &self.user_uuid, &self.source_uuid, &self.category])
     }
 }
 
@@ -68,9 +72,12 @@ pub fn fallow_source_resolver(pool: Pool<PostgresConnectionManager>, uuid: &str,
     let uuid = Uuid::parse_str(uuid)?;
     let maybe_source = find_user_source_by_uuid(&pg, uuid)?;
     if let Some(source) = maybe_source {
+        if source.source_type.is_private() {
+            return Err(ErrorKind::NotFound.into());
+        }
         let exist = user_source_exist(&pg, &uuid, user)?;
         if !exist {
-            let user_source = UserSource::new(user.uuid.clone(), source.uuid.clone());
+            let user_source = UserSource::new(user.uuid.clone(), source.uuid.clone(), None);
             pg.insert(&user_source)?;
             Ok(source)
         } else {
@@ -81,6 +88,17 @@ pub fn fallow_source_resolver(pool: Pool<PostgresConnectionManager>, uuid: &str,
     }
 }
 
+pub fn retry_source_resolver(pool: Pool<PostgresConnectionManager>, uuid: &str, user: &User) -> Result<Source> {
+    let pg = PgDatabase::from_pool(pool)?;
+    let uuid = Uuid::parse_str(uuid)?;
+    if !user_source_exist(&pg, &uuid, user)? {
+        return Err(ErrorKind::NotFound.into());
+    }
+    reset_fetch_health(&pg, &uuid)?;
+    let source = find_user_source_by_uuid(&pg, uuid)?;
+    source.ok_or_else(|| ErrorKind::NotFound.into())
+}
+
 pub fn users_sources_resolver(pool: Pool<PostgresConnectionManager>, limit: i32, offset: i32, user: &User) -> Result<Vec<Source>> {
     let pg = PgDatabase::from_pool(pool)?;
     let query = r#"
@@ -96,7 +114,7 @@ pub fn unfollowed_sources_resolver(pool: Pool<PostgresConnectionManager>, limit:
     let pg = PgDatabase::from_pool(pool)?;
     let query = r#"
         SELECT sources.* FROM sources
-        WHERE 0 = (
+        WHERE source_type NOT IN ('Email', 'Webhook') AND 0 = (
             SELECT COUNT(*)
             FROM users_sources
             WHERE sources.uuid = users_sources.source_uuid
@@ -131,6 +149,8 @@ pub fn find_users_by_source(pg: &PgDatabase, source: &Source) -> Result<Vec<User
 pub struct SourceStat {
     pub uuid: Uuid,
     pub count: i64,
+    /// The uuid is the one of a smart feed rather than a source
+    pub smart_feed: bool,
 }
 
 impl<'a> From<Row<'a>> for SourceStat {
@@ -138,6 +158,7 @@ impl<'a> From<Row<'a>> for SourceStat {
         SourceStat {
             uuid: row.get("uuid"),
             count: row.get("count"),
+            smart_feed: row.get("smart_feed"),
         }
     }
 }
@@ -152,11 +173,15 @@ graphql_object!(SourceStat: Query as "SourceStat" |&self| {
     field count() -> i32 as "Count" {
         self.count as i32
     }
+
+    field smart_feed() -> bool as "Whether the uuid is the one of a smart feed" {
+        self.smart_feed
+    }
 });
 
 fn sources_stats(pg: &PgDatabase, user: &User, reaction: &Reaction) -> Result<Vec<SourceStat>> {
     let query = r#"
-        SELECT sources.uuid, (
+        SELECT sources.uuid, FALSE AS smart_feed, (
             SELECT COUNT(feeds.uuid)
             FROM feeds
             JOIN users_feeds ON users_feeds.feed_uuid = feeds.uuid
@@ -172,5 +197,7 @@ fn sources_stats(pg: &PgDatabase, user: &User, reaction: &Reaction) -> Result<Ve
 
 pub fn sources_stats_resolver(pool: Pool<PostgresConnectionManager>, user: &User) -> Result<Vec<SourceStat>> {
     let pg = PgDatabase::from_pool(pool)?;
-    Ok(sources_stats(&pg, user, &Reaction::Unreaded)?)
+    let mut stats = sources_stats(&pg, user, &Reaction::Unreaded)?;
+    stats.extend(smart_feeds_stats(&pg, user)?);
+    Ok(stats)
 }
@@ -0,0 +1,263 @@
+use chrono::prelude::*;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use reqwest::Client;
+use scraper::{Html, Selector, ElementRef};
+use url::Url;
+
+use errors::*;
+use pg::PgDatabase;
+use feeds::{is_feed_exist, insert_feed, item_fingerprint, Feed, Rss};
+use source::Source;
+use source_option::WebPageSource;
+use sources::{record_fetch_success, record_fetch_failure};
+use extractors::parse_selector;
+use readability::{fetch_html, clean_html, text_of};
+use rss::{ChannelItem, insert_subscribers_feeds, error_status};
+use filter_rules::{SubscriberRules, find_enabled_rules};
+use schedule::DEFAULT_FETCH_INTERVAL;
+use user::User;
+
+const DATE_FORMATS: [&str; 5] = ["%Y-%m-%d", "%d/%m/%Y", "%B %d, %Y", "%b %d, %Y", "%d %B %Y"];
+
+lazy_static! {
+    static ref LINKS: Selector = Selector::parse("a[href]").expect("valid links selector");
+}
+
+/// Compiled selectors of a web page source
+pub struct PageSelectors {
+    item: Selector,
+    title: Option<Selector>,
+    link: Option<Selector>,
+    date: Option<Selector>,
+    content: Option<Selector>,
+}
+
+fn optional_selector(selector: &Option<String>) -> Result<Option<Selector>> {
+    match *selector {
+        Some(ref selector) if !selector.trim().is_empty() => Ok(Some(parse_selector(selector)?)),
+        _ => Ok(None),
+    }
+}
+
+impl PageSelectors {
+    pub fn new(web_page_source: &WebPageSource) -> Result<Self> {
+        Ok(PageSelectors {
+            item: parse_selector(&web_page_source.item_selector)?,
+            title: optional_selector(&web_page_source.title_selector)?,
+            link: optional_selector(&web_page_source.link_selector)?,
+            date: optional_selector(&web_page_source.date_selector)?,
+            content: optional_selector(&web_page_source.content_selector)?,
+        })
+    }
+}
+
+fn element_text(element: &ElementRef) -> String {
+    element.text().collect::<Vec<&str>>().join(" ").split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn first_match<'a>(item: &ElementRef<'a>, selector: &Option<Selector>) -> Option<ElementRef<'a>> {
+    selector.as_ref().and_then(|selector| item.select(selector).next())
+}
+
+/// Dates of web pages come in every shape, the common ones are tried in turn
+pub fn parse_date(date: &str) -> Option<NaiveDateTime> {
+    let date = date.trim();
+    DateTime::parse_from_rfc3339(date).ok()
+        .or_else(|| DateTime::parse_from_rfc2822(date).ok())
+        .map(|date| date.naive_utc())
+        .or_else(|| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok())
+        .or_else(|| {
+            DATE_FORMATS.iter()
+                .filter_map(|format| NaiveDate::parse_from_str(date, format).ok())
+                .next()
+                .map(|date| date.and_hms(0, 0, 0))
+        })
+}
+
+fn item_link(item: &ElementRef, selectors: &PageSelectors, page_url: &Url) -> Option<String> {
+    let element = match selectors.link {
+        Some(ref selector) => item.select(selector).next(),
+        None if item.value().name() == "a" => Some(*item),
+        None => None,
+    };
+    let href = element
+        .and_then(|element| element.value().attr("href").map(|href| href.to_owned()))
+        .or_else(|| element.unwrap_or(*item).select(&LINKS).next().and_then(|link| link.value().attr("href").map(|href| href.to_owned())))?;
+    page_url.join(&href).ok().map(|url| url.into_string())
+}
+
+fn item_date(item: &ElementRef, selectors: &PageSelectors) -> Option<NaiveDateTime> {
+    let element = first_match(item, &selectors.date)?;
+    element.value().attr("datetime")
+        .and_then(parse_date)
+        .or_else(|| parse_date(&element_text(&element)))
+}
+
+/// Items of the page, those without a link point to the page with their fingerprint as fragment
+pub fn scrape_items(html: &str, page_url: &Url, selectors: &PageSelectors) -> Vec<ChannelItem> {
+    let document = Html::parse_document(html);
+    document.select(&selectors.item).map(|item| {
+        let title = first_match(&item, &selectors.title)
+            .map(|title| element_text(&title))
+            .and_then(|title| if title.is_empty() { None } else { Some(title) });
+        let mut content = String::new();
+        match selectors.content {
+            Some(ref selector) => {
+                for element in item.select(selector) {
+                    clean_html(element, page_url, &[], &mut content);
+                }
+            },
+            None => clean_html(item, page_url, &[], &mut content),
+        }
+        let content = if content.trim().is_empty() { None } else { Some(content) };
+        let fingerprint = item_fingerprint(page_url.as_str(), &title, &content);
+        let link = item_link(&item, selectors, page_url).unwrap_or_else(|| {
+            let mut url = page_url.clone();
+            url.set_fragment(Some(&fingerprint));
+            url.into_string()
+        });
+        let published = item_date(&item, selectors).unwrap_or_else(|| Utc::now().naive_utc());
+        let rss = Rss {
+            id: link.clone(),
+            title,
+            summary: content.as_ref().map(|content| text_of(content)),
+            content,
+            author: None,
+            published: published.to_string(),
+            updated: None,
+            alternate: Some(link.clone()),
+            keywords: Vec::new(),
+            enclosure: None,
+            fingerprint,
+            enclosures: Vec::new(),
+            podcast: None,
+        };
+        ChannelItem { links: vec![link], published, rss }
+    }).collect()
+}
+
+pub fn ingest_web_page_items(subscribers: &Vec<User>, source: &Source, items: &Vec<ChannelItem>, pg: &PgDatabase) -> Result<()> {
+    let filter_rules = find_enabled_rules(pg, subscribers)?;
+    let rules = SubscriberRules::new(&filter_rules);
+    for item in items {
+        for link in &item.links {
+            if !is_feed_exist(pg, link, source)? {
+                let feed = Feed::new(link, Some(item.rss.clone()), None, None, None, source.uuid);
+                if insert_feed(pg, &feed).is_ok() {
+                    insert_subscribers_feeds(subscribers, &rules, &feed, pg)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn fetch_items(client: &Client, web_page_source: &WebPageSource) -> Result<Vec<ChannelItem>> {
+    let page_url = Url::parse(&web_page_source.url)?;
+    let selectors = PageSelectors::new(web_page_source)?;
+    let html = fetch_html(client, &web_page_source.url)?;
+    Ok(scrape_items(&html, &page_url, &selectors))
+}
+
+pub fn process_web_page_source(subscribers: &Vec<User>, source: &Source, web_page_source: &WebPageSource, client: &Client, pg: &PgDatabase) -> Result<()> {
+    match fetch_items(client, web_page_source) {
+        Ok(items) => {
+            ingest_web_page_items(subscribers, source, &items, pg)?;
+            record_fetch_success(pg, source, 200, DEFAULT_FETCH_INTERVAL)?;
+        },
+        Err(error) => {
+            println!("web page {} error {}", web_page_source.url, error);
+            record_fetch_failure(pg, source, error_status(&error), &error.to_string(), None)?;
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selectors(item: &str, title: Option<&str>, link: Option<&str>, date: Option<&str>, content: Option<&str>) -> PageSelectors {
+        let web_page_source = WebPageSource {
+            title: "Example".to_owned(),
+            url: "https://example.com/news/".to_owned(),
+            item_selector: item.to_owned(),
+            title_selector: title.map(|selector| selector.to_owned()),
+            link_selector: link.map(|selector| selector.to_owned()),
+            date_selector: date.map(|selector| selector.to_owned()),
+            content_selector: content.map(|selector| selector.to_owned()),
+        };
+        PageSelectors::new(&web_page_source).unwrap()
+    }
+
+    const PAGE: &str = r#"<html><body>
+        <article class="post">
+          <h2>  First   post </h2>
+          <time datetime="2018-04-01T10:00:00Z">April 1st</time>
+          <a class="more" href="/news/first">Read more</a>
+          <div class="body"><p>First body</p></div>
+        </article>
+        <article class="post">
+          <h2>Second post</h2>
+          <span class="date">March 5, 2018</span>
+          <div class="body"><p>Second body</p></div>
+        </article>
+      </body></html>"#;
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("2018-04-01T10:00:00+02:00"), Some(NaiveDate::from_ymd(2018, 4, 1).and_hms(8, 0, 0)));
+        assert_eq!(parse_date("Sun, 01 Apr 2018 10:00:00 GMT"), Some(NaiveDate::from_ymd(2018, 4, 1).and_hms(10, 0, 0)));
+        assert_eq!(parse_date("2018-04-01 10:00:00"), Some(NaiveDate::from_ymd(2018, 4, 1).and_hms(10, 0, 0)));
+        assert_eq!(parse_date(" 01/04/2018 "), Some(NaiveDate::from_ymd(2018, 4, 1).and_hms(0, 0, 0)));
+        assert_eq!(parse_date("1 April 2018"), Some(NaiveDate::from_ymd(2018, 4, 1).and_hms(0, 0, 0)));
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn scrapes_items_with_selectors() {
+        let page_url = Url::parse("https://example.com/news/").unwrap();
+        let selectors = selectors("article.post", Some("h2"), Some("a.more"), Some("time, .date"), Some(".body"));
+        let items = scrape_items(PAGE, &page_url, &selectors);
+        assert_eq!(items.len(), 2);
+
+        let first = &items[0];
+        assert_eq!(first.rss.title, Some("First post".to_owned()));
+        assert_eq!(first.links, vec!["https://example.com/news/first".to_owned()]);
+        assert_eq!(first.rss.id, "https://example.com/news/first");
+        assert_eq!(first.published, NaiveDate::from_ymd(2018, 4, 1).and_hms(10, 0, 0));
+        assert!(first.rss.content.as_ref().unwrap().contains("First body"));
+        assert!(!first.rss.content.as_ref().unwrap().contains("Read more"));
+
+        let second = &items[1];
+        assert_eq!(second.published, NaiveDate::from_ymd(2018, 3, 5).and_hms(0, 0, 0));
+        assert_eq!(second.links, vec![format!("https://example.com/news/#{}", second.rss.fingerprint)]);
+    }
+
+    #[test]
+    fn items_default_to_their_first_link_and_whole_content() {
+        let page_url = Url::parse("https://example.com/news/").unwrap();
+        let items = scrape_items(PAGE, &page_url, &selectors("article.post", None, None, None, None));
+        assert_eq!(items[0].links, vec!["https://example.com/news/first".to_owned()]);
+        assert_eq!(items[0].rss.title, None);
+        assert!(items[0].rss.content.as_ref().unwrap().contains("Read more"));
+
+        let links = scrape_items(r#"<a class="item" href="a.html">A</a><a class="item" href="https://other.example/b">B</a>"#, &page_url, &selectors("a.item", None, None, None, None));
+        let links: Vec<&str> = links.iter().map(|item| item.links[0].as_str()).collect();
+        assert_eq!(links, vec!["https://example.com/news/a.html", "https://other.example/b"]);
+    }
+
+    #[test]
+    fn invalid_selector_is_refused() {
+        let web_page_source = WebPageSource {
+            title: "Example".to_owned(),
+            url: "https://example.com/".to_owned(),
+            item_selector: "article[".to_owned(),
+            title_selector: None,
+            link_selector: None,
+            date_selector: None,
+            content_selector: None,
+        };
+        assert!(PageSelectors::new(&web_page_source).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use chrono::prelude::*;
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use scraper::Html;
+use serde_json;
+use url::Url;
+use uuid::Uuid;
+use validator::Validate;
+
+use errors::*;
+use config::CONFIG;
+use pg::PgDatabase;
+use feeds::{is_feed_exist, insert_feed, item_fingerprint, Feed, Rss};
+use source::Source;
+use source_option::WebhookSource;
+use sources::find_webhook_source;
+use users_sources::{find_users_by_source, UserSource};
+use readability::{clean_html, text_of};
+use rss::insert_subscribers_feeds;
+use filter_rules::{SubscriberRules, find_enabled_rules};
+use user::User;
+
+/// Entry posted on an ingest url
+#[derive(Debug, Deserialize, Validate)]
+pub struct WebhookItem {
+    #[validate(length(min = "1", max = "1000"))]
+    pub title: String,
+    #[validate(url)]
+    pub url: String,
+    pub content: Option<String>,
+    pub author: Option<String>,
+    #[serde(default)]
+    #[validate(length(max = "50"))]
+    pub tags: Vec<String>,
+}
+
+/// One item, a list of items, or an object with an `items` list
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum WebhookPayload {
+    Batch { items: Vec<WebhookItem> },
+    List(Vec<WebhookItem>),
+    Item(WebhookItem),
+}
+
+impl WebhookPayload {
+    fn into_items(self) -> Vec<WebhookItem> {
+        match self {
+            WebhookPayload::Batch { items } | WebhookPayload::List(items) => items,
+            WebhookPayload::Item(item) => vec![item],
+        }
+    }
+}
+
+/// Created webhook source with the url to post items on, the token is not shown again
+#[derive(Debug)]
+pub struct WebhookIngest {
+    pub source: Source,
+    pub ingest_url: String,
+}
+
+graphql_object!(WebhookIngest: () as "WebhookIngest" |&self| {
+    description: "Created webhook source and its secret ingest url"
+
+    field source() -> &Source {
+        &self.source
+    }
+
+    field ingest_url() -> &String as "POST JSON items on it, keep it secret" {
+        &self.ingest_url
+    }
+});
+
+/// Ingest url of a token on PUBLIC_URL, the public url of the server
+pub fn ingest_url(public_url: &str, token: &str) -> String {
+    format!("{}/webhooks/{}", public_url.trim_right_matches('/'), token)
+}
+
+fn to_rss(item: WebhookItem) -> Result<Rss> {
+    let item_url = Url::parse(&item.url)?;
+    let content = item.content.map(|content| {
+        let mut cleaned = String::new();
+        clean_html(Html::parse_fragment(&content).root_element(), &item_url, &[], &mut cleaned);
+        cleaned
+    }).and_then(|content| if content.trim().is_empty() { None } else { Some(content) });
+    Ok(Rss {
+        id: item.url.clone(),
+        fingerprint: item_fingerprint(&item.url, &Some(item.title.clone()), &content),
+        title: Some(item.title),
+        summary: content.as_ref().map(|content| text_of(content)),
+        content,
+        author: item.author,
+        published: Utc::now().naive_utc().to_string(),
+        updated: None,
+        alternate: Some(item.url),
+        keywords: item.tags,
+        enclosure: None,
+        enclosures: Vec::new(),
+        podcast: None,
+    })
+}
+
+/// Validates every item before storing any, returns how many were new
+pub fn process_webhook(pg: &PgDatabase, token: &str, body: &[u8]) -> Result<i32> {
+    let source = find_webhook_source(pg, token)?.ok_or(ErrorKind::NotFound)?;
+    let payload: WebhookPayload = serde_json::from_slice(body)?;
+    let items = payload.into_items();
+    for item in &items {
+        item.validate()?;
+    }
+    let subscribers = find_users_by_source(pg, &source)?;
+    let filter_rules = find_enabled_rules(pg, &subscribers)?;
+    let rules = SubscriberRules::new(&filter_rules);
+    let mut inserted = 0;
+    for item in items {
+        if is_feed_exist(pg, &item.url, &source)? {
+            continue;
+        }
+        let url = item.url.clone();
+        let feed = Feed::new(&url, Some(to_rss(item)?), None, None, None, source.uuid);
+        insert_feed(pg, &feed)?;
+        insert_subscribers_feeds(&subscribers, &rules, &feed, pg)?;
+        inserted += 1;
+    }
+    Ok(inserted)
+}
+
+pub fn create_webhook_source_resolver(pool: Pool<PostgresConnectionManager>, title: &str, user: &User) -> Result<WebhookIngest> {
+    let public_url = CONFIG.public_url.clone().ok_or("PUBLIC_URL is not configured")?;
+    let pg = PgDatabase::from_pool(pool)?;
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let source = Source::new_webhook(WebhookSource::new(title, &token))?;
+    pg.insert(&source)?;
+    let user_source = UserSource::new(user.uuid.clone(), source.uuid.clone(), None);
+    pg.insert(&user_source)?;
+    Ok(WebhookIngest { source, ingest_url: ingest_url(&public_url, &token) })
+}
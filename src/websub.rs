@@ -0,0 +1,358 @@
+use std::cmp;
+use std::str;
+use uuid::Uuid;
+use chrono::NaiveDateTime;
+use chrono::prelude::*;
+use chrono::Duration;
+use postgres::rows::Row;
+use postgres::types::ToSql;
+use reqwest::Client;
+use rocket::request::{self, Request, FromRequest};
+use rocket::outcome::Outcome;
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+use crypto::sha2::{Sha256, Sha512};
+use crypto::util::fixed_time_eq;
+
+use errors::*;
+use config::CONFIG;
+use pg::{Insertable, PgDatabase};
+use source::Source;
+use source_option::SourceOption;
+use sources::find_source_by_uuid;
+use users_sources::find_users_by_source;
+use rss::{ingest_rss_entries, parse_channel};
+
+/// Lease asked to the hub, hubs are free to grant a shorter one
+const REQUESTED_LEASE_SECONDS: i32 = 10 * 24 * 60 * 60;
+/// Subscriptions are renewed when they expire within this many seconds
+const RENEW_BEFORE_SECONDS: i64 = 24 * 60 * 60;
+/// A subscription request is not sent again for a topic before this many seconds
+const RETRY_AFTER_SECONDS: i64 = 60 * 60;
+
+#[derive(Debug)]
+pub struct WebSubSubscription {
+    pub uuid: Uuid,
+    pub source_uuid: Uuid,
+    pub hub_url: String,
+    pub topic_url: String,
+    pub secret: String,
+    pub verified: bool,
+    pub lease_seconds: Option<i32>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+impl WebSubSubscription {
+    pub fn new(source_uuid: Uuid, hub_url: &str, topic_url: &str) -> Self {
+        WebSubSubscription {
+            uuid: Uuid::new_v4(),
+            source_uuid,
+            hub_url: hub_url.to_owned(),
+            topic_url: topic_url.to_owned(),
+            secret: format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()),
+            verified: false,
+            lease_seconds: None,
+            expires_at: None,
+            created: Utc::now().naive_utc(),
+            updated: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn callback_url(&self, base_url: &str) -> String {
+        format!("{}/websub/callback/{}", base_url.trim_right_matches('/'), self.uuid.hyphenated())
+    }
+}
+
+impl<'a> From<Row<'a>> for WebSubSubscription {
+    fn from(row: Row) -> Self {
+        WebSubSubscription {
+            uuid: row.get("uuid"),
+            source_uuid: row.get("source_uuid"),
+            hub_url: row.get("hub_url"),
+            topic_url: row.get("topic_url"),
+            secret: row.get("secret"),
+            verified: row.get("verified"),
+            lease_seconds: row.get("lease_seconds"),
+            expires_at: row.get("expires_at"),
+            created: row.get("created"),
+            updated: row.get("updated"),
+        }
+    }
+}
+
+impl Insertable for WebSubSubscription {
+    fn insert_query(&self) -> String {
+        r#"
+            INSERT INTO websub_subscriptions (uuid, source_uuid, hub_url, topic_url, secret, verified, lease_seconds, expires_at, created, updated)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);
+        "#.to_owned()
+    }
+
+    fn insert_params(&self) -> Box<[&ToSql]> {
+        Box::new([
+            &self.uuid,
+            &self.source_uuid,
+            &self.hub_url,
+            &self.topic_url,
+            &self.secret,
+            &self.verified,
+            &self.lease_seconds,
+            &self.expires_at,
+            &self.created,
+            &self.updated,
+        ])
+    }
+}
+
+/// `X-Hub-Signature` header of a content distribution request
+pub struct HubSignature(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for HubSignature {
+    type Error = ();
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let signature = request.headers().get_one("X-Hub-Signature").map(|signature| signature.to_owned());
+        Outcome::Success(HubSignature(signature))
+    }
+}
+
+pub fn find_subscription_by_uuid(pg: &PgDatabase, uuid: &Uuid) -> Result<Option<WebSubSubscription>> {
+    let query = "SELECT * FROM websub_subscriptions WHERE uuid = $1::uuid;";
+    Ok(pg.find_one(query, &[uuid])?)
+}
+
+pub fn subscription_exist(pg: &PgDatabase, source: &Source) -> Result<bool> {
+    let query = "SELECT COUNT(*) AS exist FROM websub_subscriptions WHERE source_uuid = $1::uuid;";
+    Ok(pg.exist(query, &[&source.uuid])?)
+}
+
+/// Verified subscriptions whose lease still runs, such sources only need a safety net poll
+pub fn has_active_subscription(pg: &PgDatabase, source: &Source) -> Result<bool> {
+    let query = r#"
+        SELECT COUNT(*) AS exist FROM websub_subscriptions
+        WHERE source_uuid = $1::uuid
+            AND verified
+            AND expires_at > $2;
+    "#;
+    Ok(pg.exist(query, &[&source.uuid, &Utc::now().naive_utc()])?)
+}
+
+fn send_subscription_request(client: &Client, subscription: &WebSubSubscription, callback_base_url: &str) -> Result<()> {
+    let callback = subscription.callback_url(callback_base_url);
+    let lease_seconds = REQUESTED_LEASE_SECONDS.to_string();
+    let params = [
+        ("hub.mode", "subscribe"),
+        ("hub.topic", subscription.topic_url.as_str()),
+        ("hub.callback", callback.as_str()),
+        ("hub.secret", subscription.secret.as_str()),
+        ("hub.lease_seconds", lease_seconds.as_str()),
+    ];
+    let response = client.post(&subscription.hub_url).form(&params).send()?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(ErrorKind::HttpStatus(response.status().as_u16(), None).into())
+    }
+}
+
+/// Subscribes the source to its hub, does nothing when WEBSUB_CALLBACK_URL is not configured
+pub fn subscribe(client: &Client, pg: &PgDatabase, source: &Source, hub_url: &str, topic_url: &str) -> Result<()> {
+    if let Some(ref callback_base_url) = CONFIG.websub_callback_url {
+        if !subscription_exist(pg, source)? {
+            let subscription = WebSubSubscription::new(source.uuid, hub_url, topic_url);
+            pg.insert(&subscription)?;
+            send_subscription_request(client, &subscription, callback_base_url)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resubscribes leases about to expire, and requests the hub never verified
+pub fn renew_subscriptions(client: &Client, pg: &PgDatabase) -> Result<()> {
+    if let Some(ref callback_base_url) = CONFIG.websub_callback_url {
+        let now = Utc::now().naive_utc();
+        let query = r#"
+            SELECT * FROM websub_subscriptions
+            WHERE (NOT verified OR expires_at < $1)
+                AND updated < $2;
+        "#;
+        let renew_before = now + Duration::seconds(RENEW_BEFORE_SECONDS);
+        let retry_before = now - Duration::seconds(RETRY_AFTER_SECONDS);
+        let subscriptions: Vec<WebSubSubscription> = pg.find(query, &[&renew_before, &retry_before])?;
+        for subscription in subscriptions {
+            match send_subscription_request(client, &subscription, callback_base_url) {
+                Ok(_) => {
+                    let query = "UPDATE websub_subscriptions SET updated = $1 WHERE uuid = $2::uuid;";
+                    pg.update(query, &[&now, &subscription.uuid])?;
+                },
+                Err(error) => println!("websub renew {} error {}", subscription.topic_url, error),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What an intent verification request asks for, decided before touching the subscription
+#[derive(Debug, PartialEq)]
+enum IntentAnswer {
+    Subscribe { lease_seconds: i32, challenge: Option<String> },
+    Unsubscribe(String),
+    Reject,
+}
+
+fn intent_answer(subscription: &WebSubSubscription, mode: &str, topic: &str, challenge: Option<String>, lease_seconds: Option<i32>) -> IntentAnswer {
+    if subscription.topic_url != topic {
+        return IntentAnswer::Reject;
+    }
+    match mode {
+        "subscribe" => IntentAnswer::Subscribe {
+            lease_seconds: lease_seconds.map(|lease| cmp::max(lease, 0)).unwrap_or(REQUESTED_LEASE_SECONDS),
+            challenge,
+        },
+        "unsubscribe" | "denied" => IntentAnswer::Unsubscribe(challenge.unwrap_or_default()),
+        _ => IntentAnswer::Reject,
+    }
+}
+
+/// Answers the hub intent verification, returns the challenge to echo when the request matches a subscription we asked for
+pub fn verify_intent(pg: &PgDatabase, uuid: &str, mode: &str, topic: &str, challenge: Option<String>, lease_seconds: Option<i32>) -> Result<Option<String>> {
+    let uuid = Uuid::parse_str(uuid)?;
+    let subscription = find_subscription_by_uuid(pg, &uuid)?.ok_or(ErrorKind::NotFound)?;
+    let now = Utc::now().naive_utc();
+    match intent_answer(&subscription, mode, topic, challenge, lease_seconds) {
+        IntentAnswer::Subscribe { lease_seconds, challenge } => {
+            let expires_at = now + Duration::seconds(lease_seconds as i64);
+            let query = r#"
+                UPDATE websub_subscriptions
+                SET verified = true, lease_seconds = $1, expires_at = $2, updated = $3
+                WHERE uuid = $4::uuid;
+            "#;
+            pg.update(query, &[&lease_seconds, &expires_at, &now, &uuid])?;
+            Ok(challenge)
+        },
+        IntentAnswer::Unsubscribe(challenge) => {
+            pg.update("DELETE FROM websub_subscriptions WHERE uuid = $1::uuid;", &[&uuid])?;
+            Ok(Some(challenge))
+        },
+        IntentAnswer::Reject => Ok(None),
+    }
+}
+
+fn hmac_hex<D: Digest>(digest: D, secret: &str, body: &[u8]) -> String {
+    let mut hmac = Hmac::new(digest, secret.as_bytes());
+    hmac.input(body);
+    hmac.result().code().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checks a `method=hexdigest` signature of the body against the subscription secret
+pub fn verify_signature(secret: &str, signature: &str, body: &[u8]) -> bool {
+    let mut parts = signature.splitn(2, '=');
+    let (method, expected) = match (parts.next(), parts.next()) {
+        (Some(method), Some(expected)) => (method, expected.to_lowercase()),
+        _ => return false,
+    };
+    let computed = match method {
+        "sha1" => hmac_hex(Sha1::new(), secret, body),
+        "sha256" => hmac_hex(Sha256::new(), secret, body),
+        "sha512" => hmac_hex(Sha512::new(), secret, body),
+        _ => return false,
+    };
+    computed.len() == expected.len() && fixed_time_eq(computed.as_bytes(), expected.as_bytes())
+}
+
+/// Ingests a pushed feed document, unsigned or badly signed payloads are dropped as the spec requires
+pub fn process_push(pg: &PgDatabase, uuid: &str, signature: Option<&String>, body: &[u8]) -> Result<()> {
+    let uuid = Uuid::parse_str(uuid)?;
+    let subscription = find_subscription_by_uuid(pg, &uuid)?.ok_or(ErrorKind::NotFound)?;
+    let is_signed = signature.map(|signature| verify_signature(&subscription.secret, signature, body)).unwrap_or(false);
+    if !is_signed {
+        println!("websub push for {} with invalid signature ignored", subscription.topic_url);
+        return Ok(());
+    }
+    let source = find_source_by_uuid(pg, &subscription.source_uuid)?.ok_or(ErrorKind::NotFound)?;
+    let rss_source = match source.options()? {
+        SourceOption::Rss(rss_source) => rss_source,
+        _ => return Err(ErrorKind::NotFound.into()),
+    };
+    let document = str::from_utf8(body).map_err(|_| ErrorKind::InvalidFeed)?;
+    let (channel, _) = parse_channel(document)?;
+    let subscribers = find_users_by_source(pg, &source)?;
+    ingest_rss_entries(&subscribers, &source, &rss_source, &channel.items, pg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const TOPIC: &str = "https://example.com/feed.xml";
+
+    fn subscription() -> WebSubSubscription {
+        WebSubSubscription::new(Uuid::new_v4(), "https://hub.example.com/", TOPIC)
+    }
+
+    #[test]
+    fn accepts_valid_signatures() {
+        let body = b"<rss></rss>";
+        let signature = format!("sha1={}", hmac_hex(Sha1::new(), "secret", body));
+        assert!(verify_signature("secret", &signature, body));
+        let signature = format!("sha256={}", hmac_hex(Sha256::new(), "secret", body).to_uppercase());
+        assert!(verify_signature("secret", &signature, body));
+    }
+
+    #[test]
+    fn rejects_bad_signatures() {
+        let body = b"<rss></rss>";
+        let signature = format!("sha1={}", hmac_hex(Sha1::new(), "secret", body));
+        assert!(!verify_signature("other secret", &signature, body));
+        assert!(!verify_signature("secret", &signature, b"<rss>tampered</rss>"));
+        assert!(!verify_signature("secret", "md5=abcd", body));
+        assert!(!verify_signature("secret", "sha1", body));
+        assert!(!verify_signature("secret", "sha1=", body));
+    }
+
+    #[test]
+    fn subscribe_intent_echoes_the_challenge() {
+        let answer = intent_answer(&subscription(), "subscribe", TOPIC, Some("challenge".to_owned()), Some(3600));
+        assert_eq!(answer, IntentAnswer::Subscribe { lease_seconds: 3600, challenge: Some("challenge".to_owned()) });
+        let answer = intent_answer(&subscription(), "subscribe", TOPIC, None, None);
+        assert_eq!(answer, IntentAnswer::Subscribe { lease_seconds: REQUESTED_LEASE_SECONDS, challenge: None });
+    }
+
+    #[test]
+    fn intents_for_other_topics_are_rejected() {
+        let answer = intent_answer(&subscription(), "subscribe", "https://evil.example.com/feed.xml", Some("challenge".to_owned()), None);
+        assert_eq!(answer, IntentAnswer::Reject);
+        assert_eq!(intent_answer(&subscription(), "publish", TOPIC, None, None), IntentAnswer::Reject);
+    }
+
+    #[test]
+    fn denied_intent_unsubscribes() {
+        assert_eq!(intent_answer(&subscription(), "denied", TOPIC, None, None), IntentAnswer::Unsubscribe(String::new()));
+    }
+
+    #[test]
+    fn subscription_request_reaches_the_hub() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let hub_url = format!("http://{}/", listener.local_addr().unwrap());
+        let hub = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0; 8192];
+            let read = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+        let subscription = WebSubSubscription::new(Uuid::new_v4(), &hub_url, TOPIC);
+        send_subscription_request(&Client::new(), &subscription, "https://mindstream.example.com/").unwrap();
+        let request = hub.join().unwrap();
+        assert!(request.starts_with("POST / "));
+        assert!(request.contains("hub.mode=subscribe"));
+        assert!(request.contains(&format!("hub.secret={}", subscription.secret)));
+        assert!(request.contains("mindstream.example.com%2Fwebsub%2Fcallback%2F"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use url::Url;
+
+/// Caps the number of jobs running against the same host at the same time.
+pub struct HostLimiter {
+    max_per_host: usize,
+    running: Mutex<HashMap<String, usize>>,
+    released: Condvar,
+}
+
+impl HostLimiter {
+    pub fn new(max_per_host: usize) -> Self {
+        HostLimiter {
+            max_per_host: if max_per_host == 0 { 1 } else { max_per_host },
+            running: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a slot is free for `host`, the slot is given back when the permit is dropped.
+    pub fn acquire<'a>(&'a self, host: &str) -> HostPermit<'a> {
+        let mut running = self.running.lock().unwrap();
+        loop {
+            let count = *running.get(host).unwrap_or(&0);
+            if count < self.max_per_host {
+                running.insert(host.to_owned(), count + 1);
+                break;
+            }
+            running = self.released.wait(running).unwrap();
+        }
+        HostPermit { limiter: self, host: host.to_owned() }
+    }
+
+    fn release(&self, host: &str) {
+        let mut running = self.running.lock().unwrap();
+        let is_idle = match running.get_mut(host) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => false,
+        };
+        if is_idle {
+            running.remove(host);
+        }
+        self.released.notify_all();
+    }
+}
+
+pub struct HostPermit<'a> {
+    limiter: &'a HostLimiter,
+    host: String,
+}
+
+impl<'a> Drop for HostPermit<'a> {
+    fn drop(&mut self) {
+        self.limiter.release(&self.host);
+    }
+}
+
+pub fn host_of(url: &str) -> String {
+    Url::parse(url).ok()
+        .and_then(|url| url.host_str().map(|host| host.to_owned()))
+        .unwrap_or_else(String::new)
+}
+
+/// Reorders the jobs round robin over their hosts, so that workers do not
+/// pile up waiting on the same host while other hosts have pending work.
+fn interleave_by_host<T>(items: Vec<(String, T)>) -> Vec<(String, T)> {
+    let mut indexes: HashMap<String, usize> = HashMap::new();
+    let mut buckets: Vec<Vec<(String, T)>> = Vec::new();
+    for (host, item) in items {
+        let index = *indexes.entry(host.clone()).or_insert_with(|| {
+            buckets.push(Vec::new());
+            buckets.len() - 1
+        });
+        buckets[index].push((host, item));
+    }
+    for bucket in buckets.iter_mut() {
+        bucket.reverse();
+    }
+    let mut interleaved = Vec::new();
+    let mut remaining = true;
+    while remaining {
+        remaining = false;
+        for bucket in buckets.iter_mut() {
+            if let Some(item) = bucket.pop() {
+                interleaved.push(item);
+                remaining = true;
+            }
+        }
+    }
+    interleaved
+}
+
+/// Runs `job` over every `(host, item)` on `workers` threads and waits for all of them.
+pub fn run_pool<T, F>(items: Vec<(String, T)>, workers: usize, max_per_host: usize, job: F)
+    where T: Send + 'static, F: Fn(T) + Send + Sync + 'static
+{
+    let (sender, receiver) = channel();
+    for item in interleave_by_host(items) {
+        sender.send(item).expect("worker pool queue closed");
+    }
+    drop(sender);
+
+    let receiver = Arc::new(Mutex::new(receiver));
+    let limiter = Arc::new(HostLimiter::new(max_per_host));
+    let job = Arc::new(job);
+    let workers = if workers == 0 { 1 } else { workers };
+    let handles: Vec<thread::JoinHandle<()>> = (0..workers).map(|_| {
+        let receiver = receiver.clone();
+        let limiter = limiter.clone();
+        let job = job.clone();
+        thread::spawn(move || {
+            loop {
+                let next = receiver.lock().unwrap().recv();
+                match next {
+                    Ok((host, item)) => {
+                        let _permit = limiter.acquire(&host);
+                        job(item);
+                    },
+                    Err(_) => break,
+                }
+            }
+        })
+    }).collect();
+
+    for handle in handles {
+        if handle.join().is_err() {
+            println!("worker pool thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn job(host: &str, id: u32) -> (String, u32) {
+        (host.to_owned(), id)
+    }
+
+    #[test]
+    fn interleave_round_robins_hosts_in_order() {
+        let items = vec![job("a", 1), job("a", 2), job("a", 3), job("b", 4), job("c", 5), job("b", 6)];
+        let ids: Vec<u32> = interleave_by_host(items).into_iter().map(|(_, id)| id).collect();
+        assert_eq!(ids, vec![1, 4, 5, 2, 6, 3]);
+    }
+
+    #[test]
+    fn interleave_keeps_single_host_and_empty() {
+        let ids: Vec<u32> = interleave_by_host(vec![job("a", 1), job("a", 2)]).into_iter().map(|(_, id)| id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(interleave_by_host::<u32>(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn host_of_urls() {
+        assert_eq!(host_of("https://example.com/feed.xml"), "example.com");
+        assert_eq!(host_of("not a url"), "");
+    }
+
+    #[test]
+    fn run_pool_caps_jobs_per_host() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(Mutex::new(0));
+        let done = Arc::new(AtomicUsize::new(0));
+        let items = (0..8).map(|id| job("a", id)).collect();
+        let (job_running, job_peak, job_done) = (running.clone(), peak.clone(), done.clone());
+        run_pool(items, 4, 2, move |_| {
+            let now = job_running.fetch_add(1, Ordering::SeqCst) + 1;
+            {
+                let mut peak = job_peak.lock().unwrap();
+                if now > *peak {
+                    *peak = now;
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+            job_running.fetch_sub(1, Ordering::SeqCst);
+            job_done.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(done.load(Ordering::SeqCst), 8);
+        assert!(*peak.lock().unwrap() <= 2);
+    }
+}